@@ -14,7 +14,7 @@ async fn run() {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("monte carlo")
-        .with_resizable(false)
+        .with_resizable(true)
         .with_inner_size(PhysicalSize::new(1280_i32, 1024_i32))
         .build(&event_loop)
         .unwrap();
@@ -24,6 +24,7 @@ async fn run() {
     let mut renderer = monte_carlo::BvhRaytracing::new(&window).await;
 
     // println!("Press 'return' to render the scene to the window!");
+    let mut title = String::new();
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
@@ -39,13 +40,13 @@ async fn run() {
                     },
                 ..
             } => *control_flow = ControlFlow::Exit,
-            // WindowEvent::Resized(physical_size) => {
-            //     renderer.resize(*physical_size);
-            // }
-            // WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-            //     // new_inner_size is &&mut so we have to dereference it twice
-            //     renderer.resize(**new_inner_size);
-            // }
+            WindowEvent::Resized(physical_size) => {
+                renderer.resize(*physical_size);
+            }
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                // new_inner_size is &&mut so we have to dereference it twice
+                renderer.resize(**new_inner_size);
+            }
             _ => {
                 // Process input events
                 renderer.input(event);
@@ -55,9 +56,16 @@ async fn run() {
             match renderer.render() {
                 // Sleep a tiny bit to allow host machine to do other stuff - remove if you want to run a full chat.
                 // Ok(_) => std::thread::sleep(std::time::Duration::from_millis(32)),
-                Ok(_) => {},
+                Ok(_) => {
+                    // Show the accumulation progress in the title
+                    let progress = renderer.title();
+                    if progress != title {
+                        window.set_title(&progress);
+                        title = progress;
+                    }
+                }
                 // Reconfigure the surface if lost
-                // Err(wgpu::SurfaceError::Lost) => basic_renderer.resize(basic_renderer.get_size()),
+                Err(wgpu::SurfaceError::Lost) => renderer.resize(renderer.size()),
                 // The system is out of memory, we should probably quit
                 Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                 // All other errors (Outdated, Timeout) should be resolved by the next frame
@@ -72,5 +80,45 @@ async fn run() {
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let arg = |i: usize, default: u32| {
+        args.get(i)
+            .map_or(default, |a| a.parse().expect("Expect a positive number"))
+    };
+    // Render an image sequence without a window: monte_carlo --sequence <animation file> [width] [height]
+    if args.get(1).map(|a| a.as_str()) == Some("--sequence") {
+        let path = args.get(2).expect("Expect an animation file");
+        let animation = monte_carlo::Animation::load(std::path::Path::new(path))
+            .unwrap_or_else(|e| panic!("Unable to load the animation: {}", e));
+        let directory = pollster::block_on(monte_carlo::render_sequence(
+            arg(3, 1280),
+            arg(4, 1024),
+            &animation,
+        ))
+        .unwrap_or_else(|e| panic!("Unable to render the animation: {}", e));
+        println!("Saved image sequence: {}", directory);
+        return;
+    }
+    // Render a turntable image sequence without a window: monte_carlo --turntable [frames] [spp] [width] [height]
+    if args.get(1).map(|a| a.as_str()) == Some("--turntable") {
+        let directory = pollster::block_on(monte_carlo::render_turntable(
+            arg(4, 1280),
+            arg(5, 1024),
+            arg(2, 120),
+            arg(3, monte_carlo::DEFAULT_FRAME_SPP),
+        ));
+        println!("Saved image sequence: {}", directory);
+        return;
+    }
+    // Render without a window: monte_carlo --headless [frames] [width] [height]
+    if args.get(1).map(|a| a.as_str()) == Some("--headless") {
+        let path = pollster::block_on(monte_carlo::render_headless(
+            arg(3, 1280),
+            arg(4, 1024),
+            arg(2, 16),
+        ));
+        println!("Saved image: {}", path);
+        return;
+    }
     pollster::block_on(run());
 }
@@ -1,12 +1,21 @@
 mod aabb;
+mod animation;
+mod background_job;
 mod buffer_bindings;
 mod bvh_node;
 mod camera;
+mod camera_bookmarks;
+mod colormap;
+mod compute_renderer;
+mod conductivity_regions;
 mod constant_medium;
 mod constants;
 mod construction_scene;
 mod construction_scene_bvh_node;
+mod convergence;
 mod cuboid;
+mod headless;
+mod hit_record;
 mod hittable_primitive;
 mod interactive_section;
 mod linear_constant_medium;
@@ -14,20 +23,36 @@ mod linear_hittable;
 mod linear_scene_bvh;
 mod material;
 mod mesh;
+mod mesh_instance;
+mod picking;
 mod quad;
+mod radiosity;
+mod ray;
 mod result;
 mod scenes;
 mod sphere;
+mod tone_mapping;
 mod triangle;
 mod uniforms_bindings;
 mod util;
 mod vertex;
+mod view_factors;
 mod window;
+mod wos;
+mod wos_benchmarks;
+mod wos_transient;
 
-use cgmath::{Point3, Vector2, Vector3};
+use std::{collections::HashSet, sync::Arc, time::Instant};
+
+use cgmath::{InnerSpace, Point3, Vector2, Vector3};
 use winit::{event::WindowEvent, window::Window};
 
-use self::{linear_hittable::LinearHittable, linear_scene_bvh::LinearSceneBvh};
+pub use self::animation::{Animation, DEFAULT_FRAME_SPP};
+pub use self::headless::{render_headless, render_sequence, render_turntable};
+use self::linear_scene_bvh::LinearSceneBvh;
+
+/// Storage buffers bound to the compute shader (the scene buffers and the wos stats), above the default limit of 8 per stage
+const STORAGE_BUFFERS_PER_SHADER_STAGE: u32 = 11;
 
 // Some bits need to be tidied into more granular structs.
 pub struct BvhRaytracing {
@@ -35,18 +60,48 @@ pub struct BvhRaytracing {
     current_input_mouse_pos: winit::dpi::PhysicalPosition<f64>, // TODO: tidy
     rot_mouse_down: bool,                                       // TODO: tidy
     current_rot_mouse_pos: winit::dpi::PhysicalPosition<f64>,   // TODO: tidy
+    pan_mouse_down: bool,
+    current_pan_mouse_pos: winit::dpi::PhysicalPosition<f64>,
+    cursor_pos: winit::dpi::PhysicalPosition<f64>,
+    camera_mode: camera::CameraMode,
+    fly_keys: HashSet<winit::event::VirtualKeyCode>,
+    last_frame: Instant,
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
+    /// Render resolution relative to the surface (the display pass does the scaling)
+    render_scale: f32,
     quad: quad::Quad,
     render_pipeline: wgpu::RenderPipeline,
+    compute_renderer: compute_renderer::ComputeRenderer,
+    constants: constants::Constants,
     uniforms_bindings: uniforms_bindings::UniformsBindings,
     camera: camera::Camera,
-    interactive_section: Option<interactive_section::InteractiveSection>,
+    camera_bookmarks: camera_bookmarks::CameraBookmarks,
+    modifiers: winit::event::ModifiersState,
+    interactive_sections: interactive_section::InteractiveSections,
     scene_bvh: LinearSceneBvh,
+    scene_thermal: radiosity::SceneThermal,
+    transient_problem: wos_transient::TransientProblem,
+    conductivity_regions: Arc<conductivity_regions::ConductivityRegions>,
+    /// CPU probes, benchmarks and view factors running on worker threads, with the report printed once each is done
+    report_jobs: Vec<background_job::BackgroundJob<String>>,
+    /// Radiative exchange being solved on a worker thread for the flux display
+    radiative_job: Option<
+        background_job::BackgroundJob<(
+            radiosity::RadiativeExchange,
+            Vec<view_factors::SurfacePatch>,
+        )>,
+    >,
+    /// Print the noise once the requested read back arrives
+    print_noise: bool,
+    /// Print the colour map legend once the requested section range read back arrives
+    print_colormap_legend: bool,
     scene_bvh_bind_group: wgpu::BindGroup,
     result: result::Result,
+    convergence: convergence::Convergence,
 }
 
 impl BvhRaytracing {
@@ -68,17 +123,7 @@ impl BvhRaytracing {
             .await
             .unwrap();
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (device, queue) = Self::request_device(&adapter).await;
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -90,37 +135,30 @@ impl BvhRaytracing {
         surface.configure(&device, &config);
 
         // Constants & window uniforms bindings
-        let constants = constants::Constants::new();
+        let mut constants = constants::Constants::new();
+        constants.set_display_encode_srgb(!config.format.describe().srgb);
         let window = window::Window::new(&size);
         let uniforms_bindings =
             uniforms_bindings::UniformsBindings::new(&device, &[constants], &[window]);
 
-        // Camera
-        let camera = camera::Camera::new(
-            &device,
-            Point3::<f32>::new(0.2, 0.5, 0.9),
-            Point3::<f32>::new(0.0, 0.1, 0.0),
-            Vector3::<f32>::new(0.0, 1.0, 0.0),
-            25.0,
-            window,
-            0.0,
-            4.0,
-            0.1,
-        );
+        // Camera (from bookmark 0 if there is one)
+        let camera_bookmarks =
+            camera_bookmarks::CameraBookmarks::load(camera_bookmarks::BOOKMARKS_PATH);
+        let camera = Self::create_camera(&device, window, &camera_bookmarks);
 
         // Scene
-        let mut scene_bvh = scenes::test_wos_bunny_mesh_scene();
-        let (scene_bvh_bind_group_layout, scene_bvh_bind_group) =
-            scene_bvh.create_device_buffers(&device);
+        let (scene_bvh, scene_bvh_bind_group_layout, scene_bvh_bind_group, interactive_sections) =
+            Self::create_scene(&device, &queue);
+        interactive_sections.print_legend();
 
-        // Interactive Section (optional)
-        let interactive_section = Some(interactive_section::InteractiveSection::new(
-            LinearHittable {
-                geometry_type: 2,
-                scene_index: 0,
-            }, // Use first cuboid (check scene_bvh above)
-        ));
-        // let interactive_section = None;
+        // Surface emissivities and temperatures for the radiative exchange
+        let scene_thermal = scenes::test_wos_bunny_mesh_thermal();
+
+        // Initial and time varying boundary temperatures for the transient conduction probe
+        let transient_problem = scenes::test_wos_bunny_mesh_transient();
+
+        // Piecewise constant conductivity of the medium for the CPU probe
+        let conductivity_regions = Arc::new(scenes::test_wos_bunny_mesh_regions());
 
         // Create basic quad to render fragments onto.
         let quad = quad::Quad::new(&device);
@@ -128,18 +166,37 @@ impl BvhRaytracing {
         // Create the result texture to store current calculation status
         let result = result::Result::new(&device, &queue, window);
 
+        // Stop criterion of the accumulation (the result is saved once it is met)
+        let convergence = convergence::Convergence::new(convergence::StopCriterion {
+            target_spp: Some(1024.0),
+            time_budget: None,
+            noise_threshold: Some(0.01),
+        });
+
         // Load shader
         let shader = device.create_shader_module(&wgpu::include_wgsl!("monte_carlo.wgsl"));
 
-        // Create the render pipeline
+        // Create the compute pipeline (tiled accumulation of the result)
+        let compute_renderer = compute_renderer::ComputeRenderer::new(
+            &device,
+            &shader,
+            &[
+                uniforms_bindings.get_bind_group_layout(),
+                camera.get_bind_group_layout(),
+                &scene_bvh_bind_group_layout,
+                result.get_bind_group_layout(),
+            ],
+        );
+
+        // Create the render pipeline (display of the result)
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
-                    &uniforms_bindings.get_bind_group_layout(),
-                    &camera.get_bind_group_layout(),
+                    uniforms_bindings.get_bind_group_layout(),
+                    camera.get_bind_group_layout(),
                     &scene_bvh_bind_group_layout,
-                    &result.get_bind_group_layout(),
+                    result.get_bind_group_layout(),
                 ],
                 push_constant_ranges: &[],
             });
@@ -183,24 +240,362 @@ impl BvhRaytracing {
             current_input_mouse_pos: winit::dpi::PhysicalPosition::new(0.0, 0.0),
             rot_mouse_down: false,
             current_rot_mouse_pos: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            pan_mouse_down: false,
+            current_pan_mouse_pos: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            cursor_pos: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            camera_mode: camera::CameraMode::Arcball,
+            fly_keys: HashSet::new(),
+            last_frame: Instant::now(),
             surface,
             device,
             queue,
+            config,
             size,
+            render_scale: 1.0,
             quad,
             render_pipeline,
+            compute_renderer,
+            constants,
             uniforms_bindings,
             camera,
-            interactive_section,
+            camera_bookmarks,
+            modifiers: winit::event::ModifiersState::empty(),
+            interactive_sections,
             scene_bvh,
+            scene_thermal,
+            transient_problem,
+            conductivity_regions,
+            report_jobs: vec![],
+            radiative_job: None,
+            print_noise: false,
+            print_colormap_legend: false,
             scene_bvh_bind_group,
             result,
+            convergence,
         }
     }
 
+    /// Device with the limits needed by the scene bindings
+    async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        // Check the storage buffer limit up front, otherwise the device request fails with an opaque validation error
+        let supported = adapter.limits().max_storage_buffers_per_shader_stage;
+        assert!(
+            supported >= STORAGE_BUFFERS_PER_SHADER_STAGE,
+            "The adapter ({}) supports {} storage buffers per shader stage, the renderer binds {}",
+            adapter.get_info().name,
+            supported,
+            STORAGE_BUFFERS_PER_SHADER_STAGE
+        );
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                    limits: wgpu::Limits {
+                        max_storage_buffers_per_shader_stage: STORAGE_BUFFERS_PER_SHADER_STAGE,
+                        ..wgpu::Limits::default()
+                    },
+                    label: None,
+                },
+                None,
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Camera at bookmark 0, or the default view of the scene
+    fn startup_camera(
+        bookmarks: &camera_bookmarks::CameraBookmarks,
+    ) -> camera_bookmarks::CameraState {
+        bookmarks.get(0).unwrap_or(camera_bookmarks::CameraState {
+            look_from: Point3::<f32>::new(0.2, 0.5, 0.9),
+            look_at: Point3::<f32>::new(0.0, 0.1, 0.0),
+            v_up: Vector3::<f32>::new(0.0, 1.0, 0.0),
+            v_fov: 25.0,
+            projection: camera::Projection::Perspective,
+            aperture: 0.0,
+            focus_dist: 4.0,
+        })
+    }
+
+    fn create_camera(
+        device: &wgpu::Device,
+        window: window::Window,
+        bookmarks: &camera_bookmarks::CameraBookmarks,
+    ) -> camera::Camera {
+        let state = Self::startup_camera(bookmarks);
+        camera::Camera::new(
+            device,
+            state.look_from,
+            state.look_at,
+            state.v_up,
+            state.v_fov,
+            state.projection,
+            window,
+            state.aperture,
+            state.focus_dist,
+            0.1,
+        )
+    }
+
+    /// Scene with its device buffers, and the interactive sections (probe planes and lines referencing wos albedo blend primitives in the scene)
+    fn create_scene(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> (
+        LinearSceneBvh,
+        wgpu::BindGroupLayout,
+        wgpu::BindGroup,
+        interactive_section::InteractiveSections,
+    ) {
+        let (mut scene_bvh, interactive_sections) = scenes::test_wos_bunny_mesh_scene();
+        let (scene_bvh_bind_group_layout, scene_bvh_bind_group) =
+            scene_bvh.create_device_buffers(device);
+        interactive_sections.apply_enabled(queue, &mut scene_bvh);
+        (
+            scene_bvh,
+            scene_bvh_bind_group_layout,
+            scene_bvh_bind_group,
+            interactive_sections,
+        )
+    }
+
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    /// Reconfigure the surface and reallocate the result for a new window size (the accumulation restarts)
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        // Minimised windows have no size
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.size = new_size;
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+        self.surface.configure(&self.device, &self.config);
+        self.resize_result();
+    }
+
+    /// Change the render resolution relative to the surface (the accumulation restarts)
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale;
+        self.resize_result();
+        let render_size = self.result.size();
+        println!(
+            "Render scale: {} ({}x{})",
+            self.render_scale, render_size.width, render_size.height
+        );
+    }
+
+    /// Reallocate the result at the render resolution, updating the window uniform and camera aspect ratio
+    fn resize_result(&mut self) {
+        let window = window::Window::scaled(&self.size, self.render_scale);
+        self.uniforms_bindings
+            .update_window_buffer(&self.queue, &[window]);
+        self.camera.set_window(&self.queue, window);
+        self.result.resize(&self.device, &self.queue, window);
+    }
+
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if bookmark_slot(*key).is_some() => {
+                // Number keys jump to a camera bookmark, with shift they save the current camera to it
+                let slot = bookmark_slot(*key).unwrap();
+                if self.modifiers.shift() {
+                    let state = self.camera.state();
+                    match self.camera_bookmarks.set(slot, state) {
+                        Ok(()) => println!(
+                            "Saved camera bookmark {} to {}: {}",
+                            slot,
+                            self.camera_bookmarks.path().display(),
+                            state
+                        ),
+                        Err(e) => println!("Unable to save camera bookmark {}: {}", slot, e),
+                    }
+                } else if let Some(state) = self.camera_bookmarks.get(slot) {
+                    self.camera
+                        .set_state(&self.device, &self.queue, &mut self.result, state);
+                    println!("Camera bookmark {}: {}", slot, state);
+                } else {
+                    println!("No camera bookmark {} (shift + {} to save one)", slot, slot);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } if self.camera_mode == camera::CameraMode::Fly && fly_direction(*key).is_some() => {
+                // Fly movement keys are held, the camera is moved each frame in render
+                match state {
+                    winit::event::ElementState::Pressed => self.fly_keys.insert(*key),
+                    winit::event::ElementState::Released => self.fly_keys.remove(key),
+                };
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::E),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Switch between the arcball and fly camera modes
+                self.camera_mode = match self.camera_mode {
+                    camera::CameraMode::Arcball => camera::CameraMode::Fly,
+                    camera::CameraMode::Fly => camera::CameraMode::Arcball,
+                };
+                self.fly_keys.clear();
+                println!("Camera mode: {:?}", self.camera_mode);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Period),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Open the aperture (shallower depth of field)
+                let aperture =
+                    self.camera
+                        .scale_aperture(&self.device, &self.queue, &mut self.result, 2.0);
+                println!("Camera aperture: {}", aperture);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Comma),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Close the aperture (deeper depth of field)
+                let aperture =
+                    self.camera
+                        .scale_aperture(&self.device, &self.queue, &mut self.result, 0.5);
+                println!("Camera aperture: {}", aperture);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Semicolon),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycle the bokeh shape
+                let blades =
+                    self.camera
+                        .cycle_bokeh_blades(&self.device, &self.queue, &mut self.result);
+                if blades == 0 {
+                    println!("Camera bokeh: round");
+                } else {
+                    println!("Camera bokeh: {} blades", blades);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::J),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycle the camera projection
+                let projection = self.camera.projection().next();
+                self.camera
+                    .set_projection(&self.device, &self.queue, &mut self.result, projection);
+                println!("Camera projection: {}", projection);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if self.modifiers.alt() && axis_view(*key).is_some() => {
+                // Alt + X, Y, Z views the camera target along the world axis (side, top and front views)
+                let (name, axis, v_up) = axis_view(*key).unwrap();
+                self.camera
+                    .view_along(&self.device, &self.queue, &mut self.result, axis, v_up);
+                println!("Camera {} view", name);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::G),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Print the current camera (in the bookmarks file format)
+                println!("Camera: {}", self.camera.state());
+            }
             // Movements events for interactive drag
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Left,
+                ..
+            } if self.modifiers.ctrl() => {
+                // Ctrl click focuses the camera on the object under the cursor
+                self.focus_on_cursor();
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Left,
+                ..
+            } if self.modifiers.alt() => {
+                // Alt click sets the depth of field focus distance to the surface under the cursor
+                match self.cursor_pick() {
+                    Some(picked) => {
+                        let focus_dist = self.camera.focus_on_point(
+                            &self.device,
+                            &self.queue,
+                            &mut self.result,
+                            picked.position,
+                        );
+                        println!("Camera focus distance: {:.4}", focus_dist);
+                    }
+                    None => println!("Nothing under the cursor to focus on"),
+                }
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Left,
+                ..
+            } if self.modifiers.shift() => {
+                // Shift click picks the primitive under the cursor as the target of mouse drags (nothing returns to the active section)
+                let picked = self.cursor_pick();
+                match picked {
+                    Some(picked) => println!("Picked {}", picked),
+                    None => println!("Nothing under the cursor"),
+                }
+                self.interactive_sections
+                    .select(picked.map(|picked| picked.hittable));
+            }
             WindowEvent::MouseInput {
                 state: winit::event::ElementState::Pressed,
                 button: winit::event::MouseButton::Left,
@@ -232,36 +627,79 @@ impl BvhRaytracing {
                 self.rot_mouse_down = false;
                 self.current_rot_mouse_pos = winit::dpi::PhysicalPosition::new(0.0, 0.0);
             }
+            // Movement events for panning the camera
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Middle,
+                ..
+            } => {
+                self.pan_mouse_down = true;
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Released,
+                button: winit::event::MouseButton::Middle,
+                ..
+            } => {
+                self.pan_mouse_down = false;
+                self.current_pan_mouse_pos = winit::dpi::PhysicalPosition::new(0.0, 0.0);
+            }
             WindowEvent::CursorMoved { position: pos, .. } => {
-                // If currently rotating
+                self.cursor_pos = *pos;
+                // If currently rotating (orbiting in arcball mode, looking around in fly mode)
                 if self.rot_mouse_down {
                     if self.current_rot_mouse_pos.x > 0.001 && self.current_rot_mouse_pos.y > 0.001
                     {
-                        self.camera.rotate(
+                        let prev = Vector2::<f32>::new(
+                            self.current_rot_mouse_pos.x as f32,
+                            self.current_rot_mouse_pos.y as f32,
+                        );
+                        let cur = Vector2::<f32>::new(pos.x as f32, pos.y as f32);
+                        match self.camera_mode {
+                            camera::CameraMode::Arcball => self.camera.rotate(
+                                &self.device,
+                                &self.queue,
+                                &mut self.result,
+                                self.size,
+                                prev,
+                                cur,
+                            ),
+                            camera::CameraMode::Fly => self.camera.look_around(
+                                &self.device,
+                                &self.queue,
+                                &mut self.result,
+                                self.size,
+                                prev,
+                                cur,
+                            ),
+                        }
+                    }
+                    self.current_rot_mouse_pos = *pos;
+                } else if self.pan_mouse_down {
+                    if self.current_pan_mouse_pos.x > 0.001 && self.current_pan_mouse_pos.y > 0.001
+                    {
+                        self.camera.pan(
                             &self.device,
                             &self.queue,
                             &mut self.result,
                             self.size,
                             Vector2::<f32>::new(
-                                self.current_rot_mouse_pos.x as f32,
-                                self.current_rot_mouse_pos.y as f32,
+                                self.current_pan_mouse_pos.x as f32,
+                                self.current_pan_mouse_pos.y as f32,
                             ),
                             Vector2::<f32>::new(pos.x as f32, pos.y as f32),
                         );
                     }
-                    self.current_rot_mouse_pos = *pos;
+                    self.current_pan_mouse_pos = *pos;
                 } else if self.input_mouse_down {
                     // Else if we are dragging an input command (e.g. moving the interactive section)
                     if self.current_input_mouse_pos.x > 0.001
                         && self.current_input_mouse_pos.y > 0.001
-                        && self.interactive_section.is_some()
                     {
-                        self.interactive_section.as_mut().unwrap().translate(
+                        self.interactive_sections.drag(
                             &self.device,
                             &self.queue,
                             &mut self.scene_bvh,
                             &mut self.result,
-                            self.size,
                             Vector2::<f32>::new(
                                 self.current_input_mouse_pos.x as f32,
                                 self.current_input_mouse_pos.y as f32,
@@ -276,34 +714,498 @@ impl BvhRaytracing {
                 delta: winit::event::MouseScrollDelta::LineDelta(_, pos_y),
                 ..
             } => {
-                self.camera.zoom(
+                self.camera
+                    .zoom(&self.device, &self.queue, &mut self.result, *pos_y);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::S),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                let path = self.result.write_texture_to_working_dir(
+                    &self.device,
+                    &self.queue,
+                    &self.constants.colormap_display(),
+                    &self.constants.tone_mapping(),
+                );
+                println!("Saved image: {}", path);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::F),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycle the wos blend display (value, flux magnitude, flux direction)
+                let display = self.constants.cycle_wos_gradient_display();
+                println!("WoS gradient display: {}", display);
+                self.uniforms_bindings
+                    .update_constants_buffer(&self.queue, &[self.constants]);
+                self.result.reset_texture(&self.device, &self.queue);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::P),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Probe the solution and its gradient at the camera target with the CPU solver (over the conductivity regions)
+                let point = self.camera.look_at();
+                let scene = self.scene_bvh.cpu_copy();
+                let settings = self.constants.wos_settings();
+                let regions = Arc::clone(&self.conductivity_regions);
+                println!("WoS probe at {:?} running in the background", point);
+                self.report_jobs
+                    .push(background_job::BackgroundJob::spawn(move || {
+                        let solver = wos::WosSolver::new(&scene, settings).with_regions(&regions);
+                        let estimate = solver.solve(point, 1000, true);
+                        format!(
+                            "WoS probe at {:?} (in domain: {}, conductivity: {}): value {:?}, gradient {:?}\nWoS probe stats: {}\n",
+                            point,
+                            solver.in_domain(point),
+                            regions.conductivity(point),
+                            estimate.value,
+                            estimate.gradient,
+                            estimate.stats
+                        )
+                    }));
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::B),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Run the analytic benchmark problems with the CPU solver and report the error against the number of walks
+                println!("WoS benchmarks running in the background");
+                self.report_jobs
+                    .push(background_job::BackgroundJob::spawn(|| {
+                        wos_benchmarks::BenchmarkProblem::all()
+                            .iter()
+                            .map(|problem| problem.run(&[16, 64, 256, 1024, 4096], 8).to_string())
+                            .collect()
+                    }));
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::U),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Probe the transient (heat equation) solution at the camera target over time with the CPU space-time solver
+                let point = self.camera.look_at();
+                let scene = self.scene_bvh.cpu_copy();
+                let settings = self.constants.wos_settings();
+                let problem = self.transient_problem;
+                println!(
+                    "Transient WoS probe at {:?} running in the background",
+                    point
+                );
+                self.report_jobs
+                    .push(background_job::BackgroundJob::spawn(move || {
+                        let solver =
+                            wos_transient::TransientWosSolver::new(&scene, settings, problem);
+                        let times = [0.0, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0];
+                        let mut report = format!("Transient WoS probe at {:?}:\n", point);
+                        for estimate in solver.solve(point, &times, 1000) {
+                            report += &format!(
+                                "  t {:>6.2}: value {:.4} ({})\n",
+                                estimate.time, estimate.value, estimate.stats
+                            );
+                        }
+                        report
+                    }));
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::W),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Report the termination statistics of the walks run on the GPU since the last reset
+                let stats = self.result.read_wos_stats(&self.device, &self.queue);
+                println!("WoS stats: {}", stats);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::I),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle between the exterior and interior wos domain
+                let domain = self.constants.toggle_wos_domain();
+                println!(
+                    "WoS domain: {}",
+                    if domain == 1 { "interior" } else { "exterior" }
+                );
+                self.uniforms_bindings
+                    .update_constants_buffer(&self.queue, &[self.constants]);
+                self.result.reset_texture(&self.device, &self.queue);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Tab),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Select the next interactive section
+                self.interactive_sections.cycle_active();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::T),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Show or hide the active interactive section
+                self.interactive_sections.toggle_active(
                     &self.device,
                     &self.queue,
+                    &mut self.scene_bvh,
                     &mut self.result,
-                    self.size,
-                    *pos_y,
                 );
             }
             WindowEvent::KeyboardInput {
                 input:
                     winit::event::KeyboardInput {
-                        virtual_keycode: Some(winit::event::VirtualKeyCode::S),
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::R),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycle the interactive drag between translate, rotate and scale
+                self.interactive_sections.cycle_manipulation();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Slash),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle snapping the interactive drag to whole increments
+                self.interactive_sections.toggle_snap();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::X),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.interactive_sections.set_axis(Vector3::unit_x());
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Y),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.interactive_sections.set_axis(Vector3::unit_y());
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Z),
                         state: winit::event::ElementState::Pressed,
                         ..
                     },
                 ..
             } => {
-                // self.result
-                // .write_texture_to_working_dir(&self.device, &self.queue, self.size);
+                self.interactive_sections.set_axis(Vector3::unit_z());
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::C),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycle the section colour map (switching to/from the albedo blend changes what is accumulated)
+                let previous = self.constants.colormap_display().map;
+                let map = self.constants.cycle_wos_colormap();
+                self.uniforms_bindings
+                    .update_constants_buffer(&self.queue, &[self.constants]);
+                if previous == 0 || map == 0 {
+                    self.result.reset_texture(&self.device, &self.queue);
+                }
+                print!("{}", self.constants.colormap_display().legend());
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::V),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle the section isocontour lines (display only, so the accumulation is kept)
+                self.constants.toggle_wos_colormap_contours();
+                self.uniforms_bindings
+                    .update_constants_buffer(&self.queue, &[self.constants]);
+                print!("{}", self.constants.colormap_display().legend());
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::M),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle the colour map range between manual and auto (fitting immediately to the current result)
+                let auto = self.constants.toggle_wos_colormap_auto();
+                println!(
+                    "WoS colour map range: {}",
+                    if auto { "auto" } else { "manual" }
+                );
+                if auto {
+                    self.result.request_section_range(&self.device, &self.queue);
+                    self.print_colormap_legend = true;
+                }
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::H),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Compute the view factors between the scene surfaces (cuboid faces, spheres and mesh material groups) on the CPU
+                let scene = self.scene_bvh.cpu_copy();
+                println!("View factors running in the background");
+                self.report_jobs
+                    .push(background_job::BackgroundJob::spawn(move || {
+                        let patches = view_factors::SurfacePatch::scene_patches(&scene);
+                        view_factors::ViewFactorSolver::new(&scene, &patches)
+                            .solve(10000)
+                            .to_string()
+                    }));
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Q),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle the net radiative flux display, solving the radiative exchange between the scene surfaces on the CPU
+                let on = self.constants.toggle_radiative_flux_display();
+                println!("Radiative flux display: {}", if on { "on" } else { "off" });
+                if on {
+                    // Solved in the background, the patch colours are set once it is done (toggling off discards it)
+                    let scene = self.scene_bvh.cpu_copy();
+                    let scene_thermal = self.scene_thermal;
+                    println!("Radiative exchange running in the background");
+                    self.radiative_job = Some(background_job::BackgroundJob::spawn(move || {
+                        let patches = view_factors::SurfacePatch::scene_patches(&scene);
+                        let view_factors =
+                            view_factors::ViewFactorSolver::new(&scene, &patches).solve(10000);
+                        let exchange = radiosity::RadiositySolver::from_scene(
+                            &view_factors,
+                            &patches,
+                            &scene_thermal,
+                        )
+                        .solve();
+                        (exchange, patches)
+                    }));
+                } else {
+                    self.radiative_job = None;
+                }
+                self.uniforms_bindings
+                    .update_constants_buffer(&self.queue, &[self.constants]);
+                self.result.reset_texture(&self.device, &self.queue);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::N),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle adaptive sampling (the accumulation is weighted by sample count, so it is kept)
+                let on = self.constants.toggle_adaptive_sampling();
+                println!("Adaptive sampling: {}", if on { "on" } else { "off" });
+                self.uniforms_bindings
+                    .update_constants_buffer(&self.queue, &[self.constants]);
+
+                // The noise is printed once the read back arrives (a few frames later)
+                self.result.request_noise_stats(
+                    &self.device,
+                    &self.queue,
+                    self.constants.adaptive_target_error(),
+                );
+                self.print_noise = true;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::K),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Toggle the stop criterion (resuming the accumulation if it has already stopped)
+                let on = self.convergence.toggle();
+                println!(
+                    "Stop criterion: {}",
+                    if on {
+                        format!("{:?}", self.convergence.criterion)
+                    } else {
+                        "off".to_string()
+                    }
+                );
                 self.result
-                    .write_texture_to_working_dir(&self.device, self.size);
+                    .set_paused(&self.queue, self.convergence.is_stopped());
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::O),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Cycle the tone map operator (display only, so the accumulation is kept)
+                self.constants.cycle_tone_map_operator();
+                self.update_display();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Equals),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.constants.add_exposure(0.5);
+                self.update_display();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::Minus),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.constants.add_exposure(-0.5);
+                self.update_display();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::L),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.constants.toggle_false_color();
+                self.update_display();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::LBracket),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Halve the render resolution (down to a quarter of the surface)
+                self.set_render_scale((self.render_scale * 0.5).max(0.25));
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    winit::event::KeyboardInput {
+                        virtual_keycode: Some(winit::event::VirtualKeyCode::RBracket),
+                        state: winit::event::ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                // Double the render resolution (supersampling up to 2x the surface, each display pixel filters 2x2 result pixels)
+                self.set_render_scale((self.render_scale * 2.0).min(2.0));
             }
             _ => {}
         }
         true
     }
 
+    /// Update the display transform (tone mapping), the accumulation stays linear so it is kept
+    fn update_display(&mut self) {
+        self.uniforms_bindings
+            .update_constants_buffer(&self.queue, &[self.constants]);
+        println!("{}", self.constants.tone_mapping().describe());
+    }
+
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // Fly the camera with the held movement keys
+        let dt = self.last_frame.elapsed().as_secs_f32();
+        self.last_frame = Instant::now();
+        if !self.fly_keys.is_empty() {
+            self.fly(dt);
+        }
+
+        // Restart the accumulation timing (and sampling) whenever the result has been reset
+        if self.result.pass_index() == 0 {
+            self.convergence.restart();
+            if self.result.paused() {
+                self.result.set_paused(&self.queue, false);
+            }
+        }
+
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -314,6 +1216,20 @@ impl BvhRaytracing {
                 label: Some("Render Encoder"),
             });
 
+        // Accumulate the tiles of this pass
+        self.compute_renderer.encode_pass(
+            &mut encoder,
+            &[
+                self.uniforms_bindings.get_bind_group(),
+                self.camera.get_bind_group(),
+                &self.scene_bvh_bind_group,
+                self.result.get_bind_group(),
+            ],
+            self.result.size(),
+            self.constants.vertical_render_slices(),
+        );
+
+        // Display the result
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -336,10 +1252,10 @@ impl BvhRaytracing {
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_vertex_buffer(0, self.quad.vertices.slice(..));
             render_pass.set_index_buffer(self.quad.indices.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.set_bind_group(0, &self.uniforms_bindings.get_bind_group(), &[]);
-            render_pass.set_bind_group(1, &self.camera.get_bind_group(), &[]);
+            render_pass.set_bind_group(0, self.uniforms_bindings.get_bind_group(), &[]);
+            render_pass.set_bind_group(1, self.camera.get_bind_group(), &[]);
             render_pass.set_bind_group(2, &self.scene_bvh_bind_group, &[]);
-            render_pass.set_bind_group(3, &self.result.get_bind_group(), &[]); // We are limited to 4 bind groups
+            render_pass.set_bind_group(3, self.result.get_bind_group(), &[]); // We are limited to 4 bind groups
             render_pass.draw_indexed(0..self.quad.num_indices, 0, 0..1);
         }
 
@@ -347,9 +1263,194 @@ impl BvhRaytracing {
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
+        // Nothing more was accumulated if the stop criterion has been met
+        if self.result.paused() {
+            return Ok(());
+        }
+
         // Update the result index (as the fragment shader has just been executed)
-        self.result.increment_pass_index(&mut self.queue);
+        self.result.increment_pass_index(&self.queue);
+
+        // Read back the noise (and the section range for the auto colour map) after each full frame,
+        // the stop criterion is checked and the range is fitted once they arrive (a few frames later)
+        if self
+            .result
+            .pass_index()
+            .is_multiple_of(self.constants.vertical_render_slices())
+        {
+            self.result.request_noise_stats(
+                &self.device,
+                &self.queue,
+                self.constants.adaptive_target_error(),
+            );
+            if self.constants.wos_colormap_auto() {
+                self.result.request_section_range(&self.device, &self.queue);
+                // The legend is printed for the first fit of the accumulation, not each refit
+                self.print_colormap_legend |=
+                    self.result.pass_index() == self.constants.vertical_render_slices();
+            }
+        }
+        self.update_convergence();
+        self.fit_colormap_range();
+        self.poll_background_jobs();
 
         Ok(())
     }
+
+    /// Print the reports of the finished background jobs and apply the radiative exchange once it is solved
+    fn poll_background_jobs(&mut self) {
+        self.report_jobs.retain(|job| match job.poll() {
+            Some(report) => {
+                print!("{}", report);
+                false
+            }
+            None => true,
+        });
+
+        let finished = self.radiative_job.as_ref().and_then(|job| job.poll());
+        if let Some((exchange, patches)) = finished {
+            self.radiative_job = None;
+            print!("{}", exchange);
+            print!("{}", exchange.flux_display().legend());
+            let patch_colors = exchange.patch_colors(&self.scene_bvh, &patches);
+            self.scene_bvh.set_patch_colors(&self.queue, patch_colors);
+            self.result.reset_texture(&self.device, &self.queue);
+        }
+    }
+
+    /// Move the camera by the held fly keys, at a speed relative to the distance to the camera target (so it suits the scale of the scene)
+    fn fly(&mut self, dt: f32) {
+        let state = self.camera.state();
+        let speed = FLY_SPEED * (state.look_at - state.look_from).magnitude();
+        let direction = self
+            .fly_keys
+            .iter()
+            .filter_map(|&key| fly_direction(key))
+            .fold(Vector3::new(0.0, 0.0, 0.0), |sum, d| sum + d);
+        if direction.magnitude2() > 0.0 {
+            self.camera.fly(
+                &self.device,
+                &self.queue,
+                &mut self.result,
+                direction.normalize() * speed * dt.min(0.1),
+            );
+        }
+    }
+
+    /// Focus the camera on the object under the cursor (framing spheres and cuboids, looking at the hit point of mesh triangles)
+    fn focus_on_cursor(&mut self) {
+        match self.cursor_pick() {
+            Some(picked) => {
+                let (target, radius) = match picked.hittable.geometry_type {
+                    1 | 2 => {
+                        let bounding_box = picked.hittable.bounding_box(&self.scene_bvh);
+                        let min = Point3::from(*bounding_box.min());
+                        let max = Point3::from(*bounding_box.max());
+                        (min + (max - min) * 0.5, Some((max - min).magnitude() * 0.5))
+                    }
+                    _ => (picked.position, None),
+                };
+                self.camera
+                    .focus(&self.device, &self.queue, &mut self.result, target, radius);
+                println!("Camera focus: {:?}", target);
+            }
+            None => println!("Nothing under the cursor to focus on"),
+        }
+    }
+
+    /// Primitive under the cursor (camera ray through the cursor pixel)
+    fn cursor_pick(&self) -> Option<picking::Pick> {
+        let u = self.cursor_pos.x as f32 / self.size.width as f32;
+        let v = 1.0 - self.cursor_pos.y as f32 / self.size.height as f32;
+        picking::pick(&self.scene_bvh, &self.camera.get_ray(u, v))
+    }
+
+    /// Window title with the accumulation progress
+    pub fn title(&self) -> String {
+        self.convergence.title()
+    }
+
+    /// Update the accumulation progress once the requested noise read back arrives, pausing and saving the result once the stop criterion is met
+    fn update_convergence(&mut self) {
+        let (passes, noise) = match self.result.poll_noise_stats(&self.device) {
+            Some(readback) => readback,
+            None => return,
+        };
+        if self.print_noise {
+            println!("Noise: {}", noise);
+            self.print_noise = false;
+        }
+        let elapsed = self.convergence.elapsed();
+        let met = self.convergence.update(passes, noise, elapsed);
+        if let Some(progress) = self.convergence.progress() {
+            println!("Progress: {}", progress);
+        }
+        if met {
+            self.result.set_paused(&self.queue, true);
+            let path = self.result.write_texture_to_working_dir(
+                &self.device,
+                &self.queue,
+                &self.constants.colormap_display(),
+                &self.constants.tone_mapping(),
+            );
+            println!("Converged, saved image: {}", path);
+        }
+    }
+
+    /// Fit the colour map range to the scalar wos result accumulated on the sections, once the requested read back arrives
+    fn fit_colormap_range(&mut self) {
+        let (min, max) = match self.result.poll_section_range(&self.device) {
+            Some(range) => range,
+            None => return,
+        };
+        if !self.constants.wos_colormap_auto() {
+            return;
+        }
+        self.constants.set_wos_colormap_range(min, max);
+        self.uniforms_bindings
+            .update_constants_buffer(&self.queue, &[self.constants]);
+        if self.print_colormap_legend {
+            print!("{}", self.constants.colormap_display().legend());
+            self.print_colormap_legend = false;
+        }
+    }
+}
+
+/// Fly speed in camera target distances per second
+const FLY_SPEED: f32 = 0.5;
+
+/// Movement direction (right, up, forward) of a fly mode key
+/// (the arrow and page keys, which have no other bindings, so every command stays available in fly mode)
+fn fly_direction(key: winit::event::VirtualKeyCode) -> Option<Vector3<f32>> {
+    use winit::event::VirtualKeyCode::*;
+    match key {
+        Up => Some(Vector3::new(0.0, 0.0, 1.0)),
+        Down => Some(Vector3::new(0.0, 0.0, -1.0)),
+        Left => Some(Vector3::new(-1.0, 0.0, 0.0)),
+        Right => Some(Vector3::new(1.0, 0.0, 0.0)),
+        PageUp => Some(Vector3::new(0.0, 1.0, 0.0)),
+        PageDown => Some(Vector3::new(0.0, -1.0, 0.0)),
+        _ => None,
+    }
+}
+
+/// Name, view axis and up vector of the axis aligned camera views
+fn axis_view(
+    key: winit::event::VirtualKeyCode,
+) -> Option<(&'static str, Vector3<f32>, Vector3<f32>)> {
+    use winit::event::VirtualKeyCode::*;
+    match key {
+        X => Some(("side", Vector3::unit_x(), Vector3::unit_y())),
+        Y => Some(("top", Vector3::unit_y(), -Vector3::unit_z())),
+        Z => Some(("front", Vector3::unit_z(), Vector3::unit_y())),
+        _ => None,
+    }
+}
+
+/// Camera bookmark slot of a number key
+fn bookmark_slot(key: winit::event::VirtualKeyCode) -> Option<usize> {
+    use winit::event::VirtualKeyCode::*;
+    [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9]
+        .iter()
+        .position(|&k| k == key)
 }
@@ -45,7 +45,7 @@ struct Constants {
     pass_samples_per_pixel: i32;
     /// Maximum depth of bounced ray.
     max_depth: i32;
-    /// Number of vertical subdivision for single frame passes.
+    /// Number of passes a full frame of tiles is accumulated over (the tiles of each pass are dispatched together).
     vertical_render_slices: i32;
     /// 0: Off, 1: On
     draw_vertical_render_slice_region: u32;
@@ -55,6 +55,52 @@ struct Constants {
     draw_bvh_attenuation: f32;
     /// WoS Tolerance Distance
     wos_tolerance: f32;
+    /// WoS blend display. 0: Solution value, 1: Flux (gradient) magnitude, 2: Flux (gradient) direction
+    wos_gradient_display: u32;
+    /// Scale applied to the gradient before it is displayed
+    wos_gradient_scale: f32;
+    /// WoS domain. 0: Exterior (outside of all primitives), 1: Interior (inside the closed primitives)
+    wos_domain: u32;
+    /// Maximum number of sphere steps in a single walk
+    wos_max_steps: u32;
+    /// WoS epsilon shell. 0: Absolute (wos_tolerance), 1: Relative (wos_tolerance * distance to the boundary at the start of the walk)
+    wos_epsilon_policy: u32;
+    /// Number of steps before russian roulette is applied to a walk (0: Off)
+    wos_roulette_steps: u32;
+    /// Probability of a walk surviving each russian roulette step
+    wos_roulette_survival: f32;
+    /// Radius of the far field boundary sphere around the origin for exterior problems (0: Off, i.e. at infinity)
+    wos_far_field_radius: f32;
+    /// Colour map of the scalar wos result (mean value or flux magnitude) on directly visible sections. 0: Off (albedo blend), 1: Viridis, 2: Inferno, 3: Coolwarm
+    wos_colormap: u32;
+    /// Number of isocontour lines drawn on the colour mapped sections (0: Off)
+    wos_colormap_contours: u32;
+    /// Boundary value on the far field boundary sphere
+    wos_far_field_value: vec3<f32>;
+    /// Scalar value mapped to the start of the colour map
+    wos_colormap_min: f32;
+    /// Scalar value mapped to the end of the colour map
+    wos_colormap_max: f32;
+    /// Colour map range. 0: Manual, 1: Auto (fitted on the CPU)
+    wos_colormap_auto: u32;
+    /// Radiative flux display of directly visible surfaces from the patch colours. 0: Off, 1: On
+    radiative_flux_display: u32;
+    /// Per pixel adaptive sampling from the sample variance. 0: Off (pass_samples_per_pixel everywhere), 1: On
+    adaptive_sampling: u32;
+    /// Target relative standard error of the pixel mean luminance, pixels below it stop sampling
+    adaptive_target_error: f32;
+    /// Samples of a pixel before its variance is trusted (sampled uniformly until then)
+    adaptive_min_samples: u32;
+    /// Maximum samples of a pixel in a single pass (noisy pixels get up to this many)
+    adaptive_max_pass_samples: u32;
+    /// Tone map operator of the display pass. 0: None (clamp), 1: Reinhard, 2: ACES (fitted), 3: Filmic (Hable)
+    tone_map_operator: u32;
+    /// Display exposure in stops (the accumulated radiance is scaled by 2^exposure)
+    exposure: f32;
+    /// False colour display of the exposed luminance (inferno over middle grey +- 6 stops). 0: Off, 1: On
+    false_color: u32;
+    /// Apply the sRGB encoding in the display pass (when the render target format doesn't). 0: Off, 1: On
+    display_encode_srgb: u32;
     /// Material for the background
     background: Material;
 };
@@ -200,6 +246,22 @@ fn random_in_unit_disk(entropy: u32) -> vec3<f32> {
     return p;
 }
 
+/// Uniform point in a regular polygon inscribed in the unit disk (polygonal bokeh), or in the disk with fewer than 3 blades
+fn random_in_aperture(blades: u32, entropy: u32) -> vec3<f32> {
+    if (blades < 3u) {
+        return random_in_unit_disk(entropy);
+    }
+    // Pick one of the triangles between the centre and an edge of the polygon, then a uniform point in it
+    var n = f32(blades);
+    var k = floor(random_float(hash(entropy)) * n);
+    var a0 = 2.0 * constants.pi * k / n;
+    var a1 = a0 + 2.0 * constants.pi / n;
+    var r = sqrt(random_float(hash(entropy + 1u)));
+    var s = random_float(hash(entropy + 2u));
+    var p = r * ((1.0 - s) * vec2<f32>(cos(a0), sin(a0)) + s * vec2<f32>(cos(a1), sin(a1)));
+    return vec3<f32>(p.x, p.y, 0.0);
+}
+
 fn random_unit_vector(entropy: u32) -> vec3<f32> {
     return normalize(random_in_unit_sphere(entropy));
 }
@@ -207,6 +269,8 @@ fn random_unit_vector(entropy: u32) -> vec3<f32> {
 // Camera
 struct Camera {
     origin: vec3<f32>;
+    // 0: Perspective (thin lens), 1: Orthographic, 2: Equirectangular (360), 3: Fisheye (180, equidistant)
+    projection: u32;
     lower_left_corner: vec3<f32>;
     horizontal: vec3<f32>;
     vertical: vec3<f32>;
@@ -214,6 +278,8 @@ struct Camera {
     v: vec3<f32>;
     w: vec3<f32>;
     lens_radius: f32;
+    // Number of aperture blades shaping the bokeh (0: round)
+    bokeh_blades: u32;
 };
 
 [[group(1), binding(0)]]
@@ -249,11 +315,15 @@ struct ConstantMedium {
 
 struct TriangleVertex {
     position: vec3<f32>;
+    /// Angle weighted pseudonormal of the vertex
+    normal: vec3<f32>;
 };
 
 struct Triangle {
     indicies: vec3<u32>;
     material_index: u32; 
+    /// Pseudonormals of the edges (0 -> 1, 1 -> 2, 2 -> 0)
+    edge_normals: array<vec3<f32>, 3>;
 };
 
 /// Axis aligned bounding box.
@@ -273,7 +343,7 @@ struct BvhNode {
 
 /// Experimental data structure to hold all bvh compatible data for a single hittable geometry to compose into the bvh tree
 struct LinearHittable {
-    /// 0: BvhNode, 1: Sphere, 2: Cuboid, 3: ConstantMedium, 4: Triangle
+    /// 0: BvhNode, 1: Sphere, 2: Cuboid, 3: ConstantMedium, 4: Triangle, 5: MeshInstance
     geometry_type: u32;
     /// Given the geometry type, the actual data is stored at the following index in the linear_scene_bvh vector (for the appropriate type).
     scene_index: u32;
@@ -284,8 +354,24 @@ fn is_primitive(geometry_type: u32) -> bool {
     return geometry_type == 1u || geometry_type == 2u || geometry_type == 4u;
 }
 
+/// Placement of a mesh, the top level bvh holds the instances and each traverses the (bottom level) bvh of its mesh in object space
+struct MeshInstance {
+    world_to_object: mat4x4<f32>;
+    object_to_world: mat4x4<f32>;
+    /// Hittable index of the root of the mesh bvh
+    root: u32;
+    /// First triangle of the mesh
+    first_triangle: u32;
+    /// First triangle of this instance into the triangle patch colours
+    first_patch: u32;
+    /// Scale of object space distances to world space (smallest singular value of object_to_world, conservative for non-uniform scales)
+    distance_scale: f32;
+};
+
 // Releated to Hittable
 let bvh_node_null_ptr: u32 = 4294967295u;
+// Stack marker for the end of a mesh instance bvh traversal (back to world space)
+let instance_exit_ptr: u32 = 4294967294u;
 
 // Scene Linear Arrays - these are the data structures that are sent from cpu to the gpu.
 struct SceneLinearMaterials {
@@ -320,6 +406,14 @@ struct SceneTriangles {
     vals: array<Triangle>;
 };
 
+struct ScenePatchColors {
+    vals: array<vec4<f32>>;
+};
+
+struct SceneMeshInstances {
+    vals: array<MeshInstance>;
+};
+
 [[group(2), binding(0)]]
 var<storage, read> scene_materials: SceneLinearMaterials;
 
@@ -344,6 +438,12 @@ var<storage, read> scene_triangle_verticies: SceneTriangleVertices;
 [[group(2), binding(7)]]
 var<storage, read> scene_triangles: SceneTriangles;
 
+[[group(2), binding(8)]]
+var<storage, read> scene_patch_colors: ScenePatchColors;
+
+[[group(2), binding(9)]]
+var<storage, read> scene_instances: SceneMeshInstances;
+
 // Ray
 struct Ray {
     origin: vec3<f32>;
@@ -372,6 +472,8 @@ struct HitRecord {
 
     /// Track the number of bvh hits this ray has made
     number_bvh_hits: u32;
+    /// Index of the primitive surface into the patch colours (6 faces per cuboid, then spheres, then triangles)
+    patch_index: u32;
 };
 
 fn new_hit_record() -> HitRecord {
@@ -385,6 +487,7 @@ fn new_hit_record() -> HitRecord {
         0.0,
         0.0,
         0u,
+        0u,
     );
 }
 
@@ -450,33 +553,75 @@ fn cuboid_sd(cuboid_index: u32, point: vec3<f32>, hit_record: ptr<function, HitR
     return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
 }
 
-/// Attribution: https://iquilezles.org/articles/triangledistance/
-fn triange_ud(triangle_index: u32, point: vec3<f32>, hit_record: ptr<function, HitRecord>) -> f32 {
+/// Signed distance from the point to the triangle, with the sign taken from the angle weighted pseudonormal of the closest feature (face, edge or vertex).
+/// The closest triangle of a closed mesh therefore gives the correct inside (negative) / outside (positive) sign.
+/// Attribution: Real-Time Collision Detection (Ericson) closest point on triangle & Baerentzen and Aanaes 2005.
+fn triangle_sd(triangle_index: u32, point: vec3<f32>, hit_record: ptr<function, HitRecord>) -> f32 {
     var triangle = scene_triangles.vals[triangle_index];
 
     var material = scene_materials.vals[triangle.material_index];
     set_material_data(hit_record, &material);
 
-    var v1 = scene_triangle_verticies.vals[triangle.indicies.x].position;
-    var v2 = scene_triangle_verticies.vals[triangle.indicies.y].position;
-    var v3 = scene_triangle_verticies.vals[triangle.indicies.z].position;
-
-    var v21 = v2 - v1; var p1 = point - v1;
-    var v32 = v3 - v2; var p2 = point - v2;
-    var v13 = v1 - v3; var p3 = point - v3;
-    var nor = cross(v21, v13);
-
-    // Inside/outside test
-    if (sign(dot(cross(v21, nor), p1)) + sign(dot(cross(v32, nor), p2)) + sign(dot(cross(v13, nor), p3)) < 2.0) {
-        // 3 edges 
-        return sqrt(min(min(
-            dot2(v21 * clamp(dot(v21, p1) / dot2(v21), 0.0, 1.0) - p1),
-            dot2(v32 * clamp(dot(v32, p2) / dot2(v32), 0.0, 1.0) - p2)
-        ), dot2(v13 * clamp(dot(v13, p3) / dot2(v13), 0.0, 1.0) - p3)));
+    var a = scene_triangle_verticies.vals[triangle.indicies.x];
+    var b = scene_triangle_verticies.vals[triangle.indicies.y];
+    var c = scene_triangle_verticies.vals[triangle.indicies.z];
+
+    var ab = b.position - a.position;
+    var ac = c.position - a.position;
+    var ap = point - a.position;
+    var bp = point - b.position;
+    var cp = point - c.position;
+    var d1 = dot(ab, ap);
+    var d2 = dot(ac, ap);
+    var d3 = dot(ab, bp);
+    var d4 = dot(ac, bp);
+    var d5 = dot(ab, cp);
+    var d6 = dot(ac, cp);
+    var va = d3 * d6 - d5 * d4;
+    var vb = d5 * d2 - d1 * d6;
+    var vc = d1 * d4 - d3 * d2;
+
+    var closest = vec3<f32>(0.0);
+    var pseudonormal = vec3<f32>(0.0);
+    if (d1 <= 0.0 && d2 <= 0.0) {
+        // Vertex region a
+        closest = a.position;
+        pseudonormal = a.normal;
+    } else if (d3 >= 0.0 && d4 <= d3) {
+        // Vertex region b
+        closest = b.position;
+        pseudonormal = b.normal;
+    } else if (vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0) {
+        // Edge region ab
+        closest = a.position + ab * (d1 / (d1 - d3));
+        pseudonormal = triangle.edge_normals[0];
+    } else if (d6 >= 0.0 && d5 <= d6) {
+        // Vertex region c
+        closest = c.position;
+        pseudonormal = c.normal;
+    } else if (vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0) {
+        // Edge region ca
+        closest = a.position + ac * (d2 / (d2 - d6));
+        pseudonormal = triangle.edge_normals[2];
+    } else if (va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0) {
+        // Edge region bc
+        closest = b.position + (c.position - b.position) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        pseudonormal = triangle.edge_normals[1];
+    } else if (va + vb + vc == 0.0) {
+        // Degenerate triangle
+        closest = a.position;
+        pseudonormal = a.normal;
     } else {
-        // 1 face
-        return sqrt(dot(nor, p1) * dot(nor, p1) / dot2(nor));
+        // Face region
+        closest = a.position + ab * (vb / (va + vb + vc)) + ac * (vc / (va + vb + vc));
+        pseudonormal = cross(ab, ac);
+    }
+
+    var offset = point - closest;
+    if (dot(offset, pseudonormal) < 0.0) {
+        return -length(offset);
     }
+    return length(offset);
 }
 
 fn primitive_distance(primitive_geometry_type: u32, primitive_scene_index: u32, point: vec3<f32>, hit_record: ptr<function, HitRecord>) -> f32 {
@@ -491,7 +636,7 @@ fn primitive_distance(primitive_geometry_type: u32, primitive_scene_index: u32,
         }
         case 4u: {
             // Triangle
-            return triange_ud(primitive_scene_index, point, hit_record);
+            return triangle_sd(primitive_scene_index, point, hit_record);
         }
         default: {
             return constants.infinity; // Non-primitive geometry type - TODO - better error.
@@ -500,14 +645,18 @@ fn primitive_distance(primitive_geometry_type: u32, primitive_scene_index: u32,
 }
 
 /// Global signed distance function for all scene primatives (using bvh stack traversal)
+/// Mesh instances traverse their mesh bvh with the point in object space, scaling the distances back to world space.
 /// Uses the hitRecord to store domain boundary data.
+/// The closest primitive is found by unsigned distance and its signed distance returned, so that the sign is correct for closed meshes
+/// (where each triangle only knows the sign of its own closest feature) and non-overlapping primitives.
 /// Return the signed distance from the point to the closest primitive.
 fn scene_sd(point: vec3<f32>, rec: ptr<function, HitRecord>) -> f32 {
     var closest_so_far = constants.infinity;
+    var closest_sd = constants.infinity;
 
     // Precondition, return early if scene is empty
     if (arrayLength(&scene_hittables.vals) == 0u) {
-        return closest_so_far;
+        return closest_sd;
     }
 
     // Use a basic stack data structure frfom a fixed array (the stack value is the index of the scene hittable)
@@ -520,6 +669,10 @@ fn scene_sd(point: vec3<f32>, rec: ptr<function, HitRecord>) -> f32 {
     // Push the root node index onto the stack (which is the first value in the scene array)
     stack[stack_top] = 0u;
 
+    // Point and distance scale in the space of the bvh being traversed
+    var current_point = point;
+    var current_scale = 1.0;
+
     // While the stack is not empty
     for (;stack_top >= 0;) {
         // Check for stack depth exceeded
@@ -527,6 +680,14 @@ fn scene_sd(point: vec3<f32>, rec: ptr<function, HitRecord>) -> f32 {
             return constants.infinity; // TODO - add better error signal
         }
 
+        // End of a mesh instance, back to world space
+        if (stack[stack_top] == instance_exit_ptr) {
+            current_point = point;
+            current_scale = 1.0;
+            stack_top = stack_top - 1;
+            continue;
+        }
+
         // Get hittable from top of stack 
         var current_hittable = scene_hittables.vals[ stack[stack_top] ];
 
@@ -535,7 +696,7 @@ fn scene_sd(point: vec3<f32>, rec: ptr<function, HitRecord>) -> f32 {
             var bvh = scene_bvh_nodes.vals[ current_hittable.scene_index ];
 
             // What is the distance to this bvh node?
-            var dist = aabb_sd(stack[stack_top], point);
+            var dist = aabb_sd(stack[stack_top], current_point) * current_scale;
 
             // Pop the stack (aabb hit check done).
             stack_top = stack_top - 1;
@@ -554,17 +715,30 @@ fn scene_sd(point: vec3<f32>, rec: ptr<function, HitRecord>) -> f32 {
             continue;
         } 
 
+        // Is this a mesh instance
+        if (current_hittable.geometry_type == 5u) {
+            // Replace the instance with the exit marker and traverse the mesh bvh in object space
+            var instance = scene_instances.vals[ current_hittable.scene_index ];
+            stack[stack_top] = instance_exit_ptr;
+            stack_top = stack_top + 1;
+            stack[stack_top] = instance.root;
+            current_point = (instance.world_to_object * vec4<f32>(point, 1.0)).xyz;
+            current_scale = instance.distance_scale;
+            continue;
+        }
+
         // Is this a primitive
         if (is_primitive(current_hittable.geometry_type)) {
             // Primitive
             var temp_hit_record = new_hit_record();
-            var dist = primitive_distance(current_hittable.geometry_type, scene_hittables.vals[ stack[stack_top] ].scene_index, point, &temp_hit_record);
+            var dist = primitive_distance(current_hittable.geometry_type, scene_hittables.vals[ stack[stack_top] ].scene_index, current_point, &temp_hit_record) * current_scale;
 
             // Pop the stack primitive hit check done.
             stack_top = stack_top - 1;
-            if (dist < closest_so_far) {
+            if (abs(dist) < closest_so_far) {
                 // If this is the closest so far, update the closest measure and hit record
-                closest_so_far = dist;
+                closest_so_far = abs(dist);
+                closest_sd = dist;
                 (*rec).albedo = temp_hit_record.albedo;
             }
             continue;
@@ -584,25 +758,230 @@ fn scene_sd(point: vec3<f32>, rec: ptr<function, HitRecord>) -> f32 {
         return constants.infinity; // TODO - better error signal :(
     }
 
-    return closest_so_far;
+    return closest_sd;
+}
+
+/// Is the point inside any of the (closed) scene primitives
+fn scene_inside(point: vec3<f32>) -> bool {
+    var hr = new_hit_record();
+    return scene_sd(point, &hr) < 0.0;
+}
+
+// Walk on spheres termination statistics (read back on the cpu)
+struct WosStats {
+    walks: atomic<u32>;
+    unterminated: atomic<u32>;
+    roulette_terminated: atomic<u32>;
+    far_field_terminated: atomic<u32>;
+};
+
+[[group(3), binding(2)]]
+var<storage, read_write> wos_stats: WosStats;
+
+/// Distance from the point to the wos domain boundary (positive inside the domain, negative outside).
+/// The optional far field boundary sphere is included, and the far_field flag set if it is the closest boundary.
+fn wos_domain_sd(point: vec3<f32>, rec: ptr<function, HitRecord>, far_field: ptr<function, bool>) -> f32 {
+    var dist = scene_sd(point, rec);
+    if (constants.wos_domain == 1u) {
+        dist = -dist;
+    }
+
+    (*far_field) = false;
+    if (constants.wos_far_field_radius > 0.0 && constants.wos_far_field_radius - length(point) < dist) {
+        (*far_field) = true;
+        (*rec).albedo = constants.wos_far_field_value;
+        return constants.wos_far_field_radius - length(point);
+    }
+    return dist;
+}
+
+/// Epsilon shell of a walk starting at the distance from the boundary (absolute tolerance or relative to the starting distance)
+fn wos_epsilon(start_dist: f32) -> f32 {
+    if (constants.wos_epsilon_policy == 1u) {
+        return constants.wos_tolerance * max(start_dist, 0.0);
+    }
+    return constants.wos_tolerance;
 }
 
 /// Walk on Spheres
-/// Recusively walk on spheres sampled from the test point and return a hitrecord which contains the boundary surface data. 
+/// Walk on spheres sampled from the test point and return a hitrecord which contains the boundary surface data
+/// (with the albedo weighted by the russian roulette survival). Termination statistics are counted into wos_stats.
+/// Walks that never reach the boundary take the far field value (the boundary at infinity) on exterior problems,
+/// interior problems have no far field so they take the closest boundary value.
 fn wos(point: vec3<f32>, entropy: u32) -> HitRecord {
     var dist = constants.infinity;
+    var far_field = false;
+    var epsilon = constants.wos_tolerance;
+    var weight = 1.0;
     var curr_point = point;
     var hr = new_hit_record(); // Used to store the boundary surface data
-    for (var i = 0; i < 32; i = i + 1) {
-        dist = scene_sd(curr_point, &hr);
-        if (dist < constants.wos_tolerance) {
-            break;
+    atomicAdd(&wos_stats.walks, 1u);
+    for (var i = 0u; i < constants.wos_max_steps; i = i + 1u) {
+        dist = wos_domain_sd(curr_point, &hr, &far_field);
+        if (i == 0u) {
+            epsilon = wos_epsilon(dist);
+        }
+        if (dist < epsilon) {
+            if (far_field) {
+                atomicAdd(&wos_stats.far_field_terminated, 1u);
+            }
+            hr.albedo = hr.albedo * weight;
+            return hr;
         }
-        curr_point = curr_point + dist * normalize(random_in_unit_sphere(hash(entropy + u32(i))));
+
+        // Russian roulette
+        if (constants.wos_roulette_steps > 0u && i >= constants.wos_roulette_steps) {
+            if (random_float(hash(entropy + 7919u * (i + 1u))) > constants.wos_roulette_survival) {
+                atomicAdd(&wos_stats.roulette_terminated, 1u);
+                hr.albedo = vec3<f32>(0.0);
+                return hr;
+            }
+            weight = weight / constants.wos_roulette_survival;
+        }
+
+        curr_point = curr_point + dist * normalize(random_in_unit_sphere(hash(entropy + i)));
+    }
+
+    // The walk never reached the boundary, so it is treated as escaped to the far field (exterior) or the closest boundary is used (interior)
+    atomicAdd(&wos_stats.unterminated, 1u);
+    if (constants.wos_domain == 0u) {
+        hr.albedo = constants.wos_far_field_value;
     }
+    hr.albedo = hr.albedo * weight;
     return hr;
 } 
 
+/// Walk on spheres value and gradient sample
+struct WosSample {
+    /// Boundary surface albedo (solution estimate)
+    value: vec3<f32>;
+    /// Gradient of each value channel (columns are the x, y and z partial derivatives)
+    gradient: mat3x3<f32>;
+};
+
+/// Walk on Spheres with gradient estimation from the first sphere step.
+/// Uses grad u(x) = 3/R * E[u(x + R v) v] over uniform directions v, with an antithetic pair of walks from x + R v and x - R v.
+fn wos_gradient(point: vec3<f32>, entropy: u32) -> WosSample {
+    var hr = new_hit_record();
+    var far_field = false;
+    var dist = wos_domain_sd(point, &hr, &far_field);
+    // Absolute tolerance whatever the epsilon policy (a shell relative to the distance itself would never contain the point)
+    if (dist < constants.wos_tolerance) {
+        // Already on the boundary, the gradient can't be estimated from a sphere step
+        return WosSample(hr.albedo, mat3x3<f32>(vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(0.0)));
+    }
+
+    var dir = random_unit_vector(hash(entropy));
+    var rec_pos = wos(point + dist * dir, hash(entropy + 1u));
+    var rec_neg = wos(point - dist * dir, hash(entropy + 2u));
+
+    var delta = (rec_pos.albedo - rec_neg.albedo) * (1.5 / dist);
+    return WosSample((rec_pos.albedo + rec_neg.albedo) * 0.5, mat3x3<f32>(delta * dir.x, delta * dir.y, delta * dir.z));
+}
+
+/// Color to display for the wos blend material, depending on the wos gradient display mode.
+fn wos_blend_color(point: vec3<f32>, entropy: u32) -> vec3<f32> {
+    if (constants.wos_gradient_display == 0u) {
+        return wos(point, entropy).albedo;
+    }
+
+    var sample = wos_gradient(point, entropy);
+    var g = sample.gradient * constants.wos_gradient_scale;
+    if (constants.wos_gradient_display == 1u) {
+        // Flux magnitude of each channel
+        return sqrt(g[0] * g[0] + g[1] * g[1] + g[2] * g[2]);
+    }
+
+    // Flux direction of the channel mean, mapped linearly (so it averages to the mean gradient) from [-1, 1] to [0, 1]
+    var mean_g = vec3<f32>(dot(g[0], vec3<f32>(1.0 / 3.0)), dot(g[1], vec3<f32>(1.0 / 3.0)), dot(g[2], vec3<f32>(1.0 / 3.0)));
+    return clamp(0.5 + 0.5 * mean_g, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+/// Scalar wos result for the colour mapped sections (mean channel value or mean channel flux magnitude)
+fn wos_scalar(point: vec3<f32>, entropy: u32) -> f32 {
+    if (constants.wos_gradient_display == 0u) {
+        return dot(wos(point, entropy).albedo, vec3<f32>(1.0 / 3.0));
+    }
+
+    var g = wos_gradient(point, entropy).gradient;
+    return length(vec3<f32>(dot(g[0], vec3<f32>(1.0 / 3.0)), dot(g[1], vec3<f32>(1.0 / 3.0)), dot(g[2], vec3<f32>(1.0 / 3.0))));
+}
+
+// Colour Map Functions
+
+/// Colour map lookup (t is clamped to [0, 1] and the returned colour is sRGB encoded). 1: Viridis, 2: Inferno, 3: Coolwarm
+/// Viridis and inferno are polynomial fits (attribution: https://www.shadertoy.com/view/WlfXRN, CC0),
+/// coolwarm is linearly interpolated from the Moreland diverging map control points.
+fn colormap(map: u32, t: f32) -> vec3<f32> {
+    var t = clamp(t, 0.0, 1.0);
+    var c = vec3<f32>(0.0);
+    if (map == 1u) {
+        c = vec3<f32>(0.27772733, 0.0054073445, 0.3340998) + t * (vec3<f32>(0.10509304, 1.4046135, 1.3845902) + t * (vec3<f32>(-0.33086183, 0.21484756, 0.09509516) + t * (vec3<f32>(-4.6342305, -5.799101, -19.332441) + t * (vec3<f32>(6.22827, 14.179933, 56.69055) + t * (vec3<f32>(4.776385, -13.745145, -65.35303) + t * vec3<f32>(-5.435456, 4.6458526, 26.312435))))));
+    } else if (map == 2u) {
+        c = vec3<f32>(0.00021894037, 0.0016510046, -0.019480899) + t * (vec3<f32>(0.10651342, 0.56395645, 3.9327123) + t * (vec3<f32>(11.602493, -3.972854, -15.942394) + t * (vec3<f32>(-41.703995, 17.4364, 44.354145) + t * (vec3<f32>(77.16294, -33.40236, -81.80731) + t * (vec3<f32>(-71.31943, 32.626064, 73.20952) + t * vec3<f32>(25.131126, -12.242669, -23.070325))))));
+    } else {
+        var points = array<vec3<f32>, 5>(
+            vec3<f32>(0.230, 0.299, 0.754),
+            vec3<f32>(0.552, 0.690, 0.996),
+            vec3<f32>(0.865, 0.865, 0.865),
+            vec3<f32>(0.958, 0.603, 0.482),
+            vec3<f32>(0.706, 0.016, 0.150),
+        );
+        var x = t * 4.0;
+        var i = min(i32(x), 3);
+        c = mix(points[i], points[i + 1], x - f32(i));
+    }
+    return clamp(c, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+/// sRGB encoded to linear colour (the render target applies the sRGB encoding)
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+/// Linear to sRGB encoded colour (for render targets without the sRGB encoding)
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+/// Narkowicz fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+/// Hable (Uncharted 2) filmic curve
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    var a = 0.15;
+    var b = 0.50;
+    var c = 0.10;
+    var d = 0.20;
+    var e = 0.02;
+    var f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+/// Display transform of the linear HDR accumulated radiance to a linear colour in [0, 1] (tone_mapping.rs on the CPU)
+fn tone_map(radiance: vec3<f32>) -> vec3<f32> {
+    var c = radiance * exp2(constants.exposure);
+    if (constants.false_color == 1u) {
+        // Inferno over middle grey +- 6 stops
+        var stops = log2(max(luminance(c), 1.0e-8) / 0.18);
+        return srgb_to_linear(colormap(2u, clamp((stops + 6.0) / 12.0, 0.0, 1.0)));
+    }
+    if (constants.tone_map_operator == 1u) {
+        c = c / (1.0 + c);
+    } else if (constants.tone_map_operator == 2u) {
+        c = aces(c);
+    } else if (constants.tone_map_operator == 3u) {
+        c = hable(c) / hable(vec3<f32>(11.2));
+    }
+    return clamp(c, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
 // Ray Hit/Intersection Functions 
 
 // Attribution: https://gamedev.stackexchange.com/a/18459
@@ -665,6 +1044,7 @@ fn sphere_hit(sphere_index: u32, ray: ptr<function, Ray>, t_min: f32, t_max: f32
     (*hit_record).p = ray_at(ray, (*hit_record).t);
     var outward_normal = ((*hit_record).p - sphere.center) / sphere.radius;
     set_face_normal(hit_record, ray, outward_normal);
+    (*hit_record).patch_index = 6u * arrayLength(&scene_cuboids.vals) + sphere_index;
 
     set_material_data(hit_record, &material);
 
@@ -718,13 +1098,16 @@ fn cuboid_hit(cuboid_index: u32, ray: ptr<function, Ray>, t_min: f32, t_max: f32
             return false;
         }
 
-        // compute normal (in world space)
+        // compute normal (in world space) and face (0: +x, 1: -x, 2: +y, 3: -y, 4: +z, 5: -z)
         if (t1.x > t1.y && t1.x > t1.z) {
             (*hit_record).normal = cuboid.txi[0].xyz * s.x * 1.0;
+            (*hit_record).patch_index = 6u * cuboid_index + select(1u, 0u, s.x > 0.0);
         } else if (t1.y > t1.z) {
             (*hit_record).normal = cuboid.txi[1].xyz * s.y * 1.0;
+            (*hit_record).patch_index = 6u * cuboid_index + select(3u, 2u, s.y > 0.0);
         } else {
             (*hit_record).normal = cuboid.txi[2].xyz * s.z * 1.0;
+            (*hit_record).patch_index = 6u * cuboid_index + select(5u, 4u, s.z > 0.0);
         }
 
         // intersection point (in world space)
@@ -743,10 +1126,13 @@ fn cuboid_hit(cuboid_index: u32, ray: ptr<function, Ray>, t_min: f32, t_max: f32
         // WHY IS THING WRONG?! - verifyed on paper to be correct.
         if (t2.x < t2.y && t2.x < t2.z) {
             (*hit_record).normal = cuboid.txi[0].xyz * s.x * -1.0;
+            (*hit_record).patch_index = 6u * cuboid_index + select(1u, 0u, s.x < 0.0);
         } else if (t2.y < t2.z) {
             (*hit_record).normal = cuboid.txi[1].xyz * s.y * -1.0;
+            (*hit_record).patch_index = 6u * cuboid_index + select(3u, 2u, s.y < 0.0);
         } else {
             (*hit_record).normal = cuboid.txi[2].xyz * s.z * -1.0;
+            (*hit_record).patch_index = 6u * cuboid_index + select(5u, 4u, s.z < 0.0);
         }
 
         // intersection point (in world space)
@@ -795,6 +1181,7 @@ fn triangle_hit(triangle_index: u32, ray: ptr<function, Ray>, t_min: f32, t_max:
     (*hit_record).normal = n;
     (*hit_record).t = t;
     (*hit_record).p = ray_at(ray, (*hit_record).t);
+    (*hit_record).patch_index = 6u * arrayLength(&scene_cuboids.vals) + arrayLength(&scene_spheres.vals) + triangle_index;
 
     set_material_data(hit_record, &material);
 
@@ -868,6 +1255,7 @@ fn constant_medium_hit(constant_medium_index: u32, ray: ptr<function, Ray>, t_mi
 }
 
 /// Global ray hit function for all scene primitives (using bvh stack traversal).
+/// Mesh instances traverse their mesh bvh with the ray in object space (the direction is not normalised, so hit distances are the same in both spaces).
 fn scene_hits(ray: ptr<function, Ray>, t_min: f32, t_max: f32, rec: ptr<function, HitRecord>, entropy: u32) -> bool {
     var hit_anything = false;
     var closest_so_far = t_max;
@@ -887,6 +1275,10 @@ fn scene_hits(ray: ptr<function, Ray>, t_min: f32, t_max: f32, rec: ptr<function
     // Push the root node index onto the stack (which is the first value in the scene array)
     stack[stack_top] = 0u;
 
+    // Ray in the space of the bvh being traversed and the mesh instance it belongs to (if any)
+    var current_ray = *ray;
+    var current_instance = bvh_node_null_ptr;
+
     // While the stack is not empty
     for (;stack_top >= 0;) {
         // Check for stack depth exceeded
@@ -894,6 +1286,14 @@ fn scene_hits(ray: ptr<function, Ray>, t_min: f32, t_max: f32, rec: ptr<function
             return false; // TODO - add better error signal
         }
 
+        // End of a mesh instance, back to world space
+        if (stack[stack_top] == instance_exit_ptr) {
+            current_ray = *ray;
+            current_instance = bvh_node_null_ptr;
+            stack_top = stack_top - 1;
+            continue;
+        }
+
         // Get hittable from top of stack 
         var current_hittable = scene_hittables.vals[ stack[stack_top] ];
 
@@ -903,7 +1303,7 @@ fn scene_hits(ray: ptr<function, Ray>, t_min: f32, t_max: f32, rec: ptr<function
 
             // Does this BVH node intersect the ray?
             var t = 0.0;
-            var hit = aabb_hit(stack[stack_top], ray, &t);
+            var hit = aabb_hit(stack[stack_top], &current_ray, &t);
 
             // Pop the stack (aabb hit check done).
             stack_top = stack_top - 1;
@@ -925,16 +1325,38 @@ fn scene_hits(ray: ptr<function, Ray>, t_min: f32, t_max: f32, rec: ptr<function
             continue;
         } 
 
+        // Is this a mesh instance
+        if (current_hittable.geometry_type == 5u) {
+            // Replace the instance with the exit marker and traverse the mesh bvh in object space
+            var instance = scene_instances.vals[ current_hittable.scene_index ];
+            stack[stack_top] = instance_exit_ptr;
+            stack_top = stack_top + 1;
+            stack[stack_top] = instance.root;
+            current_ray = Ray((instance.world_to_object * vec4<f32>((*ray).origin, 1.0)).xyz, (instance.world_to_object * vec4<f32>((*ray).direction, 0.0)).xyz);
+            current_instance = current_hittable.scene_index;
+            continue;
+        }
+
         // Is this a primitive
         if (is_primitive(current_hittable.geometry_type)) {
             // Primitive
-            var hit = primitive_hit(current_hittable.geometry_type, scene_hittables.vals[ stack[stack_top] ].scene_index, ray, t_min, closest_so_far, rec);
+            var primitive_index = scene_hittables.vals[ stack[stack_top] ].scene_index;
+            var hit = primitive_hit(current_hittable.geometry_type, primitive_index, &current_ray, t_min, closest_so_far, rec);
 
             // Pop the stack primitive hit check done.
             stack_top = stack_top - 1;
             if (hit) {
                 hit_anything = true;
                 closest_so_far = (*rec).t;
+
+                // Mesh instance hits back to world space (normals by the inverse transpose) and to the patch of the instance triangle
+                if (current_instance != bvh_node_null_ptr) {
+                    var instance = scene_instances.vals[current_instance];
+                    var w = instance.world_to_object;
+                    (*rec).p = ray_at(ray, (*rec).t);
+                    (*rec).normal = normalize(transpose(mat3x3<f32>(w[0].xyz, w[1].xyz, w[2].xyz)) * (*rec).normal);
+                    (*rec).patch_index = 6u * arrayLength(&scene_cuboids.vals) + arrayLength(&scene_spheres.vals) + instance.first_patch + primitive_index - instance.first_triangle;
+                }
             }
             continue;
         }
@@ -963,11 +1385,33 @@ fn scene_hits(ray: ptr<function, Ray>, t_min: f32, t_max: f32, rec: ptr<function
 
 // Ray trace
 fn camera_get_ray(s: f32, t: f32, entropy: u32) -> Ray {
-    var rd = camera.lens_radius * random_in_unit_disk(entropy);
+    // Orthographic, parallel rays from the view plane through look_from (no depth of field)
+    if (camera.projection == 1u) {
+        return Ray(camera.lower_left_corner + s * camera.horizontal + t * camera.vertical, -camera.w);
+    }
+    // Equirectangular, longitude across the width and latitude up the height around the view direction
+    if (camera.projection == 2u) {
+        var phi = (s - 0.5) * 2.0 * constants.pi;
+        var theta = (t - 0.5) * constants.pi;
+        return Ray(camera.origin, cos(theta) * (sin(phi) * camera.u - cos(phi) * camera.w) + sin(theta) * camera.v);
+    }
+    // Fisheye, angle from the view direction proportional to the distance from the centre (180 degrees across the height)
+    if (camera.projection == 3u) {
+        var aspect_ratio = length(camera.horizontal) / length(camera.vertical);
+        var p = vec2<f32>((s - 0.5) * aspect_ratio, t - 0.5) * 2.0;
+        var r = length(p);
+        var angle = r * constants.pi / 2.0;
+        var d = select(vec2<f32>(0.0, 0.0), p / r, r > 0.0);
+        return Ray(camera.origin, sin(angle) * (d.x * camera.u + d.y * camera.v) - cos(angle) * camera.w);
+    }
+    var rd = camera.lens_radius * random_in_aperture(camera.bokeh_blades, entropy);
     var offset = camera.u * rd.x + camera.v * rd.y;
     return Ray(camera.origin + offset, camera.lower_left_corner + s * camera.horizontal + t * camera.vertical - camera.origin - offset);
 }
 
+// Scalar wos result and coverage of the colour mapped section sampled by the current camera ray
+var<private> section_sample: vec2<f32>;
+
 // This is a loop version of the recursive reference implmentation.
 fn ray_color(ray: ptr<function, Ray>, depth: i32, entropy: u32) -> vec3<f32> {
     var hit_record = new_hit_record();
@@ -984,7 +1428,11 @@ fn ray_color(ray: ptr<function, Ray>, depth: i32, entropy: u32) -> vec3<f32> {
         }
 
         if (hit) {
-            if (hit_record.material_type == 0u) {
+            if (i == 0 && constants.radiative_flux_display == 1u && hit_record.material_type != 4u && scene_patch_colors.vals[hit_record.patch_index].w > 0.0) {
+                // Directly visible surface with a radiative flux colour
+                current_ray_color = scene_patch_colors.vals[hit_record.patch_index].xyz;
+                break;
+            } else if (hit_record.material_type == 0u) {
                 // Lambertian material
                 var scattered = hit_record.p + random_in_hemisphere(hit_record.normal, (entropy * u32(i + 1)));
 
@@ -1040,10 +1488,14 @@ fn ray_color(ray: ptr<function, Ray>, depth: i32, entropy: u32) -> vec3<f32> {
 
                 current_ray = Ray(hit_record.p, scattered - hit_record.p);
                 current_ray_color = current_ray_color * hit_record.albedo;
+            } else if (hit_record.material_type == 5u && i == 0 && constants.wos_colormap != 0u && constants.wos_gradient_display != 2u) {
+                // Directly visible colour mapped section, the scalar result is accumulated separately and colour mapped for display
+                section_sample = vec2<f32>(wos_scalar(hit_record.p, entropy * u32(i + 5)), 1.0);
+                current_ray_color = vec3<f32>(0.0);
+                break;
             } else if (hit_record.material_type == 5u) {
                 // WoS blend material
-                var mat_sample_rec = wos(hit_record.p, entropy * u32(i + 5));
-                current_ray_color = current_ray_color * mat_sample_rec.albedo;
+                current_ray_color = current_ray_color * wos_blend_color(hit_record.p, entropy * u32(i + 5));
 
                 // Lambertian material (much more expensive than breaking to simulate emissive material)
                 var scattered = hit_record.p + random_in_hemisphere(hit_record.normal, (entropy * u32(i + 1)));
@@ -1077,46 +1529,215 @@ var texture: texture_storage_2d<rgba32float, read_write>;
 // Result uniforms  
 struct ResultUniforms {
     pass_index: u32; // TODO - what happens after we reach u32 max number of passes (we would need to leave running for 136 years at 1fps though :D)?
+    /// Accumulation stopped (the stop criterion has been met), the result is only displayed. 0: Sampling, 1: Paused
+    paused: u32;
 };
 
 [[group(3), binding(1)]]
 var<uniform> result_uniforms: ResultUniforms;
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    // // Read the current sampled colour of the pixel from the texture
-    var texture_coords = vec2<i32>(i32(in.tex_coords.x * f32(window.width_pixels)), i32(in.tex_coords.y * f32(window.height_pixels)));
+// Result section storage texture (accumulated scalar wos result multiplied by coverage, and coverage of the colour mapped sections)
+[[group(3), binding(3)]]
+var section_texture: texture_storage_2d<rgba32float, read_write>;
+
+// Result moments storage texture (x: mean sample luminance, y: mean squared sample luminance, z: number of samples of the pixel)
+[[group(3), binding(4)]]
+var moments_texture: texture_storage_2d<rgba32float, read_write>;
+
+/// Relative standard error of the pixel mean luminance from its moments
+fn relative_error(moments: vec4<f32>) -> f32 {
+    var variance = max(moments.y - moments.x * moments.x, 0.0);
+    return sqrt(variance / max(moments.z, 1.0)) / max(moments.x, 0.01);
+}
+
+/// Number of samples of the pixel in this pass, 0 once it has converged to the target error
+fn pass_samples(moments: vec4<f32>) -> i32 {
+    if (constants.adaptive_sampling == 0u || moments.z < f32(constants.adaptive_min_samples)) {
+        return constants.pass_samples_per_pixel;
+    }
+    var error = relative_error(moments);
+    if (error < constants.adaptive_target_error) {
+        return 0;
+    }
+    // Noisier pixels (relative to the target) get more samples
+    var samples = ceil(f32(constants.pass_samples_per_pixel) * error / constants.adaptive_target_error);
+    return i32(clamp(samples, 1.0, f32(constants.adaptive_max_pass_samples)));
+}
+
+/// Contour band index of the scalar value (adjacent pixels in different bands are drawn as an isocontour line)
+fn contour_band(t: f32) -> i32 {
+    return i32(floor(t * f32(constants.wos_colormap_contours)));
+}
+
+/// Normalized colour map position of the accumulated section scalar at the texture coordinates
+fn section_t(section: vec4<f32>) -> f32 {
+    var range = constants.wos_colormap_max - constants.wos_colormap_min;
+    if (abs(range) < constants.epsilon) {
+        return 0.5;
+    }
+    return (section.x / section.y - constants.wos_colormap_min) / range;
+}
+
+/// Display colour of a pixel, compositing the colour mapped sections, isocontours and legend colour bar onto the accumulated ray colour
+fn display_color(texture_coords: vec2<i32>, color: vec4<f32>) -> vec4<f32> {
+    var display = vec4<f32>(tone_map(color.xyz), color.w);
+    if (constants.wos_colormap == 0u) {
+        return display;
+    }
+
+    var size = vec2<i32>(i32(window.width_pixels), i32(window.height_pixels));
+    var section = textureLoad(section_texture, texture_coords);
+    if (section.y > 0.0) {
+        var t = section_t(section);
+        var section_color = srgb_to_linear(colormap(constants.wos_colormap, t));
+        if (constants.wos_colormap_contours > 0u) {
+            var band = contour_band(t);
+            var right = textureLoad(section_texture, min(texture_coords + vec2<i32>(1, 0), size - vec2<i32>(1)));
+            var up = textureLoad(section_texture, min(texture_coords + vec2<i32>(0, 1), size - vec2<i32>(1)));
+            if ((right.y > 0.0 && contour_band(section_t(right)) != band) || (up.y > 0.0 && contour_band(section_t(up)) != band)) {
+                section_color = section_color * 0.1;
+            }
+        }
+        display = vec4<f32>(display.xyz + section.y * section_color, display.w);
+    }
+
+    // Legend colour bar (the range is printed to stdout and saved with images)
+    var y0 = size.y / 10;
+    var y1 = size.y - size.y / 10;
+    if (texture_coords.x >= size.x - 40 && texture_coords.x < size.x - 20 && texture_coords.y >= y0 && texture_coords.y < y1) {
+        display = vec4<f32>(srgb_to_linear(colormap(constants.wos_colormap, f32(texture_coords.y - y0) / f32(y1 - y0))), 1.0);
+    }
+    return display;
+}
+
+// ** Tiled accumulation (compute) **
+
+// Square tiles of pixels, each dispatched as (tile_size / workgroup_size)^2 workgroups (see compute_renderer.rs)
+let tile_size: u32 = 64u;
+
+/// Number of tiles covering the window
+fn tile_count() -> vec2<u32> {
+    return (vec2<u32>(window.width_pixels, window.height_pixels) + vec2<u32>(tile_size - 1u)) / tile_size;
+}
+
+/// Number of tiles accumulated in each pass (all of the tiles are covered in vertical_render_slices passes)
+fn tiles_per_pass() -> u32 {
+    var count = tile_count();
+    var slices = u32(constants.vertical_render_slices);
+    return (count.x * count.y + slices - 1u) / slices;
+}
+
+/// Index of the tile containing the pixel
+fn pixel_tile(texture_coords: vec2<u32>) -> u32 {
+    var tile = texture_coords / tile_size;
+    return tile.x + tile.y * tile_count().x;
+}
+
+/// Whether the tile is accumulated in the current pass
+fn in_current_pass(tile: u32) -> bool {
+    return tile / tiles_per_pass() == result_uniforms.pass_index % u32(constants.vertical_render_slices);
+}
+
+/// Sample the pixel and accumulate into the result textures
+fn accumulate_pixel(texture_coords: vec2<i32>) {
     var existing_pixel_color_with_alpha = textureLoad(texture, texture_coords);
+    var existing_section = textureLoad(section_texture, texture_coords);
+    var existing_moments = textureLoad(moments_texture, texture_coords);
 
-    // Return early (if we are not in the current vertical render slice region or first pass to prevent a full screen render first frame, which can be very slow for a complex scene)
-    if ((result_uniforms.pass_index % u32(constants.vertical_render_slices)) != u32((1.0 - in.tex_coords.y) * f32(constants.vertical_render_slices))) {
-        return existing_pixel_color_with_alpha;
+    // Skip pixels that have converged (adaptive sampling)
+    var num_samples = pass_samples(existing_moments);
+    if (num_samples == 0) {
+        return;
     }
 
-    // Calculate the ray for the current pixel
+    // Calculate the ray for the current pixel (from the centre of the pixel, as the full screen quad was sampled)
+    var tex_coords = (vec2<f32>(texture_coords) + vec2<f32>(0.5)) / vec2<f32>(f32(window.width_pixels), f32(window.height_pixels));
     var new_sampled_pixel_color = vec3<f32>(0.0, 0.0, 0.0);
-    var num_samples = constants.pass_samples_per_pixel;
+    var new_section = vec2<f32>(0.0, 0.0);
+    var new_moments = vec2<f32>(0.0, 0.0);
     for (var s = 0; s < num_samples; s = s + 1) {
-        var pixel_entropy = hash(entropy_window_space(in.tex_coords) + result_uniforms.pass_index);
+        var pixel_entropy = hash(entropy_window_space(tex_coords) + result_uniforms.pass_index);
         var pixel_sample_entropy = hash(pixel_entropy * u32(s + 1));
-        var u = in.tex_coords.x + random_float(hash(pixel_sample_entropy + 1u)) / f32(window.width_pixels);
-        var v = in.tex_coords.y + random_float(hash(pixel_sample_entropy + 2u)) / f32(window.height_pixels);
+        var u = tex_coords.x + random_float(hash(pixel_sample_entropy + 1u)) / f32(window.width_pixels);
+        var v = tex_coords.y + random_float(hash(pixel_sample_entropy + 2u)) / f32(window.height_pixels);
         var ray = camera_get_ray(u, v, hash(pixel_sample_entropy + 3u));
-        new_sampled_pixel_color = new_sampled_pixel_color + ray_color(&ray, constants.max_depth, hash(pixel_sample_entropy + 4u));
+        section_sample = vec2<f32>(0.0, 0.0);
+        var sample_color = ray_color(&ray, constants.max_depth, hash(pixel_sample_entropy + 4u));
+        new_sampled_pixel_color = new_sampled_pixel_color + sample_color;
+        new_section = new_section + vec2<f32>(section_sample.x * section_sample.y, section_sample.y);
+        var sample_luminance = luminance(sample_color);
+        new_moments = new_moments + vec2<f32>(sample_luminance, sample_luminance * sample_luminance);
     }
     new_sampled_pixel_color = new_sampled_pixel_color / f32(num_samples);
     var new_pixel_color_with_alpha = vec4<f32>(new_sampled_pixel_color, 1.0);
+    var new_section_with_padding = vec4<f32>(new_section / f32(num_samples), 0.0, 1.0);
 
-    // Weighted average with existing pixel color in result storage texture.
-    var averaged_pixel_color_with_alpha = (1.0 / (1.0 + f32(result_uniforms.pass_index) / f32(constants.vertical_render_slices))) * new_pixel_color_with_alpha + ((f32(result_uniforms.pass_index) / f32(constants.vertical_render_slices)) / (1.0 + (f32(result_uniforms.pass_index) / f32(constants.vertical_render_slices))) * existing_pixel_color_with_alpha);
+    // Weighted average (by the number of samples, which varies per pixel with adaptive sampling) with the existing pixel color in result storage texture.
+    var total_samples = existing_moments.z + f32(num_samples);
+    var new_weight = f32(num_samples) / total_samples;
+    var averaged_pixel_color_with_alpha = new_weight * new_pixel_color_with_alpha + (1.0 - new_weight) * existing_pixel_color_with_alpha;
     textureStore(texture, texture_coords, averaged_pixel_color_with_alpha);
 
-    // Optionally draw the current vertical render slice region
-    if (constants.draw_vertical_render_slice_region == 1u && (result_uniforms.pass_index % u32(constants.vertical_render_slices)) == u32((1.0 - in.tex_coords.y) * f32(constants.vertical_render_slices))) {
-        return vec4<f32>(1.0, 0.156, 0.949, 1.0); // Nice pink
+    // Same weighted average for the colour mapped section scalar and coverage
+    var averaged_section = new_weight * new_section_with_padding + (1.0 - new_weight) * existing_section;
+    textureStore(section_texture, texture_coords, averaged_section);
+
+    // And the luminance moments (with the sample count)
+    var averaged_moments = new_weight * (new_moments / f32(num_samples)) + (1.0 - new_weight) * existing_moments.xy;
+    textureStore(moments_texture, texture_coords, vec4<f32>(averaged_moments, total_samples, 1.0));
+}
+
+/// Accumulation pass, the z of the dispatch selects the tile (of the tiles in the current pass) and x, y the pixel in the tile
+[[stage(compute), workgroup_size(8, 8, 1)]]
+fn cs_main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    // Nothing more is accumulated once the stop criterion has been met
+    if (result_uniforms.paused == 1u) {
+        return;
+    }
+
+    var count = tile_count();
+    var tile = (result_uniforms.pass_index % u32(constants.vertical_render_slices)) * tiles_per_pass() + id.z;
+    if (tile >= count.x * count.y) {
+        return;
     }
+    var texture_coords = vec2<u32>(tile % count.x, tile / count.x) * tile_size + id.xy;
+    if (texture_coords.x >= window.width_pixels || texture_coords.y >= window.height_pixels) {
+        return;
+    }
+    accumulate_pixel(vec2<i32>(texture_coords));
+}
 
-    return averaged_pixel_color_with_alpha;
+// ** Display (fragment) **
+
+/// Bilinear filtered display colour at a position in the result texture (in pixels), scaling the render resolution to the surface
+fn display_color_filtered(position: vec2<f32>) -> vec4<f32> {
+    var max_coords = vec2<i32>(i32(window.width_pixels) - 1, i32(window.height_pixels) - 1);
+    var p = position - vec2<f32>(0.5);
+    var base = floor(p);
+    var f = p - base;
+    var c00 = clamp(vec2<i32>(base), vec2<i32>(0), max_coords);
+    var c11 = clamp(vec2<i32>(base) + vec2<i32>(1), vec2<i32>(0), max_coords);
+    var c10 = vec2<i32>(c11.x, c00.y);
+    var c01 = vec2<i32>(c00.x, c11.y);
+    var bottom = mix(display_color(c00, textureLoad(texture, c00)), display_color(c10, textureLoad(texture, c10)), f.x);
+    var top = mix(display_color(c01, textureLoad(texture, c01)), display_color(c11, textureLoad(texture, c11)), f.x);
+    return mix(bottom, top, f.y);
 }
 
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Position in the result texture (the window uniform is the render resolution, which can differ from the surface)
+    var position = in.tex_coords * vec2<f32>(f32(window.width_pixels), f32(window.height_pixels));
+    var texture_coords = vec2<i32>(position);
 
+    // Optionally draw the tiles accumulated in the current pass
+    if (constants.draw_vertical_render_slice_region == 1u && result_uniforms.paused == 0u && in_current_pass(pixel_tile(vec2<u32>(texture_coords)))) {
+        return vec4<f32>(1.0, 0.156, 0.949, 1.0); // Nice pink
+    }
+
+    var display = display_color_filtered(position);
+    if (constants.display_encode_srgb == 1u) {
+        return vec4<f32>(linear_to_srgb(display.xyz), display.w);
+    }
+    return display;
+}
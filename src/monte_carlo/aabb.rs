@@ -1,4 +1,6 @@
-use cgmath::Point3;
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::ray::Ray;
 
 ///
 /// Axis aligned bounding box
@@ -41,6 +43,51 @@ impl Aabb {
     pub fn max(&self) -> &[f32; 3] {
         &self.max
     }
+
+    /// Surface area of the box (the cost of visiting a bvh node is proportional to it)
+    pub fn surface_area(&self) -> f32 {
+        let d = [0, 1, 2].map(|a| (self.max[a] - self.min[a]).max(0.0));
+        2.0 * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+    }
+
+    ///
+    /// Does the ray intersect the box within the range (CPU version of `aabb_hit` in the shader)
+    ///
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d = 1.0 / ray.direction[a];
+            let mut t_0 = (self.min[a] - ray.origin[a]) * inv_d;
+            let mut t_1 = (self.max[a] - ray.origin[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t_0, &mut t_1);
+            }
+            t_min = t_min.max(t_0);
+            t_max = t_max.min(t_1);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    ///
+    /// Signed distance from a point to the box (CPU version of `aabb_sd` in the shader)
+    /// Attribution: https://iquilezles.org/articles/distfunctions/
+    ///
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        let min = Point3::from(self.min);
+        let max = Point3::from(self.max);
+
+        // Box is defined by half lengths, with the point relative to the center of the aabb.
+        let b = (max - min) / 2.0;
+        let p = point - min - b;
+
+        let q = Vector3::new(p.x.abs() - b.x, p.y.abs() - b.y, p.z.abs() - b.z);
+        Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude()
+            + q.x.max(q.y.max(q.z)).min(0.0)
+    }
 }
 
 ///
@@ -0,0 +1,335 @@
+use std::{f32::consts::PI, path::Path};
+
+use cgmath::{InnerSpace, Matrix3, Rad, Vector3};
+
+use super::camera_bookmarks::{parse_vector, CameraState};
+
+/// Default samples per pixel rendered for each frame of a sequence
+pub const DEFAULT_FRAME_SPP: u32 = 16;
+
+/// Camera parameters at a frame of the sequence
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    pub frame: f32,
+    pub state: CameraState,
+}
+
+/// World space offset of an interactive section (from its initial position) at a frame of the sequence
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SectionKeyframe {
+    pub frame: f32,
+    pub section: usize,
+    pub offset: Vector3<f32>,
+}
+
+///
+/// Keyframed camera path and interactive section offsets, rendered as an image sequence by the headless renderer.
+/// Keyframes are interpolated linearly, except the camera orbits look_at (the direction from look_at to look_from is interpolated on the sphere), so a turntable only needs a keyframe every quarter turn.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    /// Number of frames rendered (frames 0 to frames - 1)
+    pub frames: u32,
+    /// Samples per pixel rendered for each frame
+    pub spp: u32,
+    /// Camera keyframes in frame order
+    pub camera: Vec<CameraKeyframe>,
+    /// Section keyframes in frame order
+    pub sections: Vec<SectionKeyframe>,
+}
+
+impl Animation {
+    /// Full turn of the camera around look_at (about v_up), the last frame stops a step short of the first so the sequence loops
+    pub fn turntable(state: CameraState, frames: u32, spp: u32) -> Self {
+        let camera = (0..=4)
+            .map(|quarter| {
+                let rotation = Matrix3::from_axis_angle(
+                    state.v_up.normalize(),
+                    Rad(quarter as f32 * PI / 2.0),
+                );
+                CameraKeyframe {
+                    frame: quarter as f32 * frames as f32 / 4.0,
+                    state: CameraState {
+                        look_from: state.look_at + rotation * (state.look_from - state.look_at),
+                        ..state
+                    },
+                }
+            })
+            .collect();
+        Animation {
+            frames,
+            spp,
+            camera,
+            sections: vec![],
+        }
+    }
+
+    ///
+    /// Load an animation file, one keyframe or setting per line (# comments are ignored):
+    /// `frames <count>`, `spp <samples>`, `camera <frame> <camera state>` (as in the bookmarks file) and `section <frame> <index> <x,y,z offset>`.
+    /// Frames defaults to one past the last keyframe.
+    ///
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let mut frames = None;
+        let mut spp = DEFAULT_FRAME_SPP;
+        let mut camera = vec![];
+        let mut sections = vec![];
+        for (line_index, line) in contents.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: String| format!("{} line {}: {}", path.display(), line_index + 1, e);
+            let mut tokens = line.splitn(2, char::is_whitespace);
+            let key = tokens.next().unwrap_or_default();
+            let rest = tokens.next().unwrap_or_default().trim();
+            match key {
+                "frames" => frames = Some(parse_count(rest).map_err(error)?),
+                "spp" => spp = parse_count(rest).map_err(error)?,
+                "camera" => {
+                    let (frame, state) = split_frame(rest).map_err(error)?;
+                    let state: CameraState = state.parse().map_err(error)?;
+                    if (state.look_from - state.look_at).magnitude2() == 0.0 {
+                        return Err(error("Expect look_from and look_at to differ".to_string()));
+                    }
+                    camera.push(CameraKeyframe { frame, state });
+                }
+                "section" => {
+                    let (frame, rest) = split_frame(rest).map_err(error)?;
+                    let (section, offset) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| error("Expect a section index and offset".to_string()))?;
+                    sections.push(SectionKeyframe {
+                        frame,
+                        section: parse_count(section).map_err(error)? as usize,
+                        offset: Vector3::from(parse_vector(offset.trim()).map_err(error)?),
+                    });
+                }
+                _ => return Err(error(format!("Unknown animation setting '{}'", key))),
+            }
+        }
+        camera.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        sections.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+
+        let last_keyframe = camera
+            .iter()
+            .map(|k| k.frame)
+            .chain(sections.iter().map(|k| k.frame))
+            .fold(0.0, f32::max);
+        Ok(Animation {
+            frames: frames.unwrap_or(last_keyframe as u32 + 1),
+            spp,
+            camera,
+            sections,
+        })
+    }
+
+    /// Check the section keyframes only use the sections in the scene
+    pub fn validate_sections(&self, section_count: usize) -> Result<(), String> {
+        match self.sections.iter().find(|k| k.section >= section_count) {
+            Some(keyframe) => Err(format!(
+                "Section keyframe at frame {} uses section {}, the scene has {} sections",
+                keyframe.frame, keyframe.section, section_count
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Interpolated camera at a frame (None without camera keyframes)
+    pub fn camera_at(&self, frame: f32) -> Option<CameraState> {
+        let (a, b, t) = bracket(&self.camera, frame, |k| k.frame)?;
+        let (a, b) = (a.state, b.state);
+        let look_at = a.look_at + (b.look_at - a.look_at) * t;
+
+        // Orbit from one keyframe to the next (slerp of the view direction, lerp of the distance)
+        let (offset_a, offset_b) = (a.look_from - a.look_at, b.look_from - b.look_at);
+        let distance = offset_a.magnitude() + (offset_b.magnitude() - offset_a.magnitude()) * t;
+        let direction = slerp(offset_a.normalize(), offset_b.normalize(), t, a.v_up);
+
+        Some(CameraState {
+            look_from: look_at + direction * distance,
+            look_at,
+            v_up: (a.v_up + (b.v_up - a.v_up) * t).normalize(),
+            v_fov: a.v_fov + (b.v_fov - a.v_fov) * t,
+            projection: a.projection,
+            aperture: a.aperture + (b.aperture - a.aperture) * t,
+            focus_dist: a.focus_dist + (b.focus_dist - a.focus_dist) * t,
+        })
+    }
+
+    /// Interpolated offsets of the keyframed sections at a frame, as (section index, offset)
+    pub fn section_offsets_at(&self, frame: f32) -> Vec<(usize, Vector3<f32>)> {
+        let mut indices: Vec<usize> = self.sections.iter().map(|k| k.section).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+            .into_iter()
+            .filter_map(|section| {
+                let keyframes: Vec<SectionKeyframe> = self
+                    .sections
+                    .iter()
+                    .filter(|k| k.section == section)
+                    .copied()
+                    .collect();
+                let (a, b, t) = bracket(&keyframes, frame, |k| k.frame)?;
+                Some((section, a.offset + (b.offset - a.offset) * t))
+            })
+            .collect()
+    }
+}
+
+/// Keyframes either side of the frame and the interpolation weight (clamped to the first and last keyframes)
+fn bracket<T: Copy>(keyframes: &[T], frame: f32, time: impl Fn(&T) -> f32) -> Option<(T, T, f32)> {
+    let first = *keyframes.first()?;
+    let last = *keyframes.last()?;
+    if frame <= time(&first) {
+        return Some((first, first, 0.0));
+    }
+    keyframes
+        .windows(2)
+        .find(|w| frame < time(&w[1]))
+        .map(|w| {
+            let span = time(&w[1]) - time(&w[0]);
+            let t = if span > 0.0 {
+                (frame - time(&w[0])) / span
+            } else {
+                1.0
+            };
+            (w[0], w[1], t)
+        })
+        .or(Some((last, last, 0.0)))
+}
+
+/// Spherical interpolation between unit vectors, vectors half a turn apart are interpolated about the axis
+/// (or any axis orthogonal to a, if the axis is parallel to it)
+fn slerp(a: Vector3<f32>, b: Vector3<f32>, t: f32, axis: Vector3<f32>) -> Vector3<f32> {
+    let angle = a.dot(b).clamp(-1.0, 1.0).acos();
+    if angle < 1.0e-4 {
+        return (a + (b - a) * t).normalize();
+    }
+    if PI - angle < 1.0e-3 {
+        let mut orthogonal = axis.cross(a);
+        if orthogonal.magnitude2() < 1.0e-8 {
+            let other = if a.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            orthogonal = a.cross(other);
+        }
+        let orthogonal = orthogonal.normalize();
+        return a * (t * angle).cos() + orthogonal * (t * angle).sin();
+    }
+    (a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / angle.sin()
+}
+
+fn parse_count(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Expect a positive integer, found '{}'", value))
+}
+
+/// Frame of a keyframe line and the rest of the line
+fn split_frame(value: &str) -> Result<(f32, &str), String> {
+    let (frame, rest) = value
+        .split_once(char::is_whitespace)
+        .ok_or(format!("Expect a frame and keyframe, found '{}'", value))?;
+    let frame = frame
+        .parse()
+        .map_err(|_| format!("Expect a frame number, found '{}'", frame))?;
+    Ok((frame, rest.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::camera::Projection;
+    use super::*;
+    use cgmath::Point3;
+
+    #[test]
+    fn test_animation_keyframes() {
+        let distance = |a: Point3<f32>, b: Point3<f32>| (a - b).magnitude();
+        let state = CameraState {
+            look_from: Point3::new(0.0, 0.5, 1.0),
+            look_at: Point3::new(0.0, 0.5, 0.0),
+            v_up: Vector3::new(0.0, 1.0, 0.0),
+            v_fov: 25.0,
+            projection: Projection::Perspective,
+            aperture: 0.0,
+            focus_dist: 1.0,
+        };
+
+        // A turntable orbits at a constant distance and is half way round at half the frames
+        let turntable = Animation::turntable(state, 100, 8);
+        for frame in [0.0, 12.5, 37.0, 99.0] {
+            let camera = turntable.camera_at(frame).unwrap();
+            assert!((distance(camera.look_from, camera.look_at) - 1.0).abs() < 1.0e-4);
+        }
+        let half = turntable.camera_at(50.0).unwrap();
+        assert!(distance(half.look_from, Point3::new(0.0, 0.5, -1.0)) < 1.0e-4);
+
+        // Loaded keyframes are sorted and clamped outside of their range
+        let path =
+            std::env::temp_dir().join(format!("monte_carlo_animation_{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            format!(
+                "# sweep\nspp 4\nsection 10 0 0,0,0.2\nsection 0 0 0,0,-0.2\ncamera 0 {}\n",
+                state
+            ),
+        )
+        .unwrap();
+        let animation = Animation::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(animation.frames, 11);
+        assert_eq!(animation.spp, 4);
+        assert_eq!(animation.camera_at(5.0), Some(state));
+        let offsets = animation.section_offsets_at(5.0);
+        assert_eq!(offsets.len(), 1);
+        assert!(offsets[0].1.magnitude() < 1.0e-6);
+        assert_eq!(
+            animation.section_offsets_at(20.0),
+            vec![(0, Vector3::new(0.0, 0.0, 0.2))]
+        );
+        assert!(animation.validate_sections(1).is_ok());
+        assert!(animation.validate_sections(0).is_err());
+
+        // Keyframes half a turn apart orbit about v_up
+        let behind = CameraState {
+            look_from: Point3::new(0.0, 0.5, -1.0),
+            ..state
+        };
+        let half_turn = Animation {
+            frames: 3,
+            spp: 1,
+            camera: vec![
+                CameraKeyframe { frame: 0.0, state },
+                CameraKeyframe {
+                    frame: 2.0,
+                    state: behind,
+                },
+            ],
+            sections: vec![],
+        };
+        let camera = half_turn.camera_at(1.0).unwrap();
+        assert!((distance(camera.look_from, camera.look_at) - 1.0).abs() < 1.0e-4);
+        assert!((camera.look_from.y - 0.5).abs() < 1.0e-4);
+
+        // Cameras looking at their own position are rejected
+        std::fs::write(
+            &path,
+            format!(
+                "camera 0 {}\n",
+                CameraState {
+                    look_at: state.look_from,
+                    ..state
+                }
+            ),
+        )
+        .unwrap();
+        let result = Animation::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use std::{sync::mpsc, thread};
+
+/// CPU work (solvers and benchmarks) run on a worker thread so the window keeps rendering, the result is polled from the render loop
+pub struct BackgroundJob<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T: Send + 'static> BackgroundJob<T> {
+    pub fn spawn(work: impl FnOnce() -> T + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The receiver is gone if the job was dropped (e.g. cancelled), the result is then discarded
+            let _ = sender.send(work());
+        });
+        BackgroundJob { receiver }
+    }
+
+    /// The result once the work has finished (without blocking)
+    pub fn poll(&self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_poll() {
+        // Not ready while the work is running, then the result is returned once
+        let (start, started) = mpsc::channel::<()>();
+        let job = BackgroundJob::spawn(move || {
+            started.recv().unwrap();
+            42
+        });
+        assert_eq!(job.poll(), None);
+        start.send(()).unwrap();
+
+        let mut result = None;
+        for _ in 0..1000 {
+            result = job.poll();
+            if result.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(result, Some(42));
+        assert_eq!(job.poll(), None);
+    }
+}
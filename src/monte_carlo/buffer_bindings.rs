@@ -17,7 +17,7 @@ pub fn create_device_buffer_binding<T: bytemuck::Pod>(
         entries: &[wgpu::BindGroupLayoutEntry {
             binding: 0,
             count: None,
-            visibility: wgpu::ShaderStages::FRAGMENT,
+            visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
             ty: wgpu::BindingType::Buffer {
                 ty: binding_type,
                 has_dynamic_offset: false,
@@ -20,7 +20,7 @@ impl BvhNode {
     /// Helper to get the convention for the null value
     ///
     pub fn null_hittable_ptr() -> u32 {
-        u32::max_value()
+        u32::MAX
     }
 
     ///
@@ -39,8 +39,8 @@ impl BvhNode {
 
     pub fn empty() -> Self {
         BvhNode {
-            left_hittable: u32::max_value(),
-            right_hittable: u32::max_value(),
+            left_hittable: u32::MAX,
+            right_hittable: u32::MAX,
             _pad_1: BvhNode::null_hittable_ptr(),
             _pad_2: BvhNode::null_hittable_ptr(),
             aabb: Aabb::empty(),
@@ -55,7 +55,19 @@ impl BvhNode {
         self.right_hittable = right;
     }
 
+    pub fn left(&self) -> u32 {
+        self.left_hittable
+    }
+
+    pub fn right(&self) -> u32 {
+        self.right_hittable
+    }
+
     pub fn bounding_box(&self) -> Aabb {
         self.aabb
     }
+
+    pub fn set_bounding_box(&mut self, aabb: Aabb) {
+        self.aabb = aabb;
+    }
 }
@@ -1,15 +1,15 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, fmt, str::FromStr};
 
 use cgmath::{prelude::*, Matrix4, Point3, Rad, Vector2, Vector3};
 
-use super::{buffer_bindings, result, util, window};
+use super::{buffer_bindings, camera_bookmarks::CameraState, ray::Ray, result, util, window};
 
 // Note: Due to wgsl uniforms requiring 16 byte (4 float) spacing, we need to use a padding fields here.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraRaw {
     pub origin: [f32; 3],
-    _padding1: f32,
+    pub projection: u32,
     pub lower_left_corner: [f32; 3],
     _padding2: f32,
     pub horizontal: [f32; 3],
@@ -22,11 +22,16 @@ struct CameraRaw {
     _padding6: f32,
     pub w: [f32; 3],
     pub lens_radius: f32,
+    /// Number of aperture blades shaping the bokeh (0: round)
+    pub bokeh_blades: u32,
+    _padding7: [u32; 3],
 }
 
 impl CameraRaw {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         origin: Point3<f32>,
+        projection: Projection,
         lower_left_corner: Point3<f32>,
         horizontal: Vector3<f32>,
         vertical: Vector3<f32>,
@@ -37,7 +42,7 @@ impl CameraRaw {
     ) -> CameraRaw {
         CameraRaw {
             origin: origin.into(),
-            _padding1: 0.0,
+            projection: projection as u32,
             lower_left_corner: lower_left_corner.into(),
             _padding2: 0.0,
             horizontal: horizontal.into(),
@@ -50,18 +55,119 @@ impl CameraRaw {
             _padding6: 0.0,
             w: w.into(),
             lens_radius,
+            bokeh_blades: 0,
+            _padding7: [0; 3],
+        }
+    }
+
+    /// Ray from the centre of the lens through the point on the screen (CPU version of `camera_get_ray` in the shader)
+    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
+        let origin = Point3::from(self.origin);
+        let horizontal = Vector3::from(self.horizontal);
+        let vertical = Vector3::from(self.vertical);
+        let (u, v, w) = (
+            Vector3::from(self.u),
+            Vector3::from(self.v),
+            Vector3::from(self.w),
+        );
+        let view_plane = Point3::from(self.lower_left_corner) + s * horizontal + t * vertical;
+        match self.projection {
+            1 => Ray::new(view_plane, -w),
+            2 => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let theta = (t - 0.5) * PI;
+                let direction = theta.cos() * (phi.sin() * u - phi.cos() * w) + theta.sin() * v;
+                Ray::new(origin, direction)
+            }
+            3 => {
+                let aspect_ratio = horizontal.magnitude() / vertical.magnitude();
+                let p = Vector2::new((s - 0.5) * aspect_ratio, t - 0.5) * 2.0;
+                let r = p.magnitude();
+                let angle = r * PI / 2.0;
+                let d = if r > 0.0 { p / r } else { p };
+                Ray::new(origin, angle.sin() * (d.x * u + d.y * v) - angle.cos() * w)
+            }
+            _ => Ray::new(origin, view_plane - origin),
         }
     }
 }
 
+/// Projection of the camera rays (projection in the shader)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection {
+    /// Thin lens perspective (field of view, aperture and focus distance)
+    Perspective,
+    /// Parallel rays with true scale, the view height is the perspective view height at look_at
+    Orthographic,
+    /// 360 degree longitude / latitude around the view direction
+    Equirectangular,
+    /// 180 degree equidistant fisheye across the height of the view
+    Fisheye,
+}
+
+impl Projection {
+    /// Next projection (cycled with the 'J' key)
+    pub fn next(self) -> Self {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Equirectangular,
+            Projection::Equirectangular => Projection::Fisheye,
+            Projection::Fisheye => Projection::Perspective,
+        }
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Projection::Perspective => "perspective",
+            Projection::Orthographic => "orthographic",
+            Projection::Equirectangular => "equirectangular",
+            Projection::Fisheye => "fisheye",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            "fisheye" => Ok(Projection::Fisheye),
+            _ => Err(format!("Unknown camera projection '{}'", s)),
+        }
+    }
+}
+
+/// Smallest non zero aperture (halving below it closes the aperture, doubling from closed opens it)
+const MIN_APERTURE: f32 = 0.005;
+
+/// Aperture blade counts cycled through for the bokeh shape (0: round)
+const BOKEH_BLADES: [u32; 4] = [0, 5, 6, 8];
+
+/// How mouse drags and the keyboard move the camera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraMode {
+    /// Right drag orbits around look_at
+    Arcball,
+    /// Right drag looks around from look_from, arrow / page up / page down keys move through the scene
+    Fly,
+}
+
 pub struct Camera {
     look_from: Point3<f32>, // eye
     look_at: Point3<f32>,   // target
     v_up: Vector3<f32>,     // orientation of the camera
     v_fov: f32,
+    projection: Projection,
     window: window::Window,
     aperture: f32,
     focus_dist: f32,
+    bokeh_blades: u32,
     zoom_speed: f32,
 
     view_matrix: Matrix4<f32>,
@@ -73,24 +179,26 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         look_from: Point3<f32>,
         look_at: Point3<f32>,
         v_up: Vector3<f32>,
         v_fov: f32,
+        projection: Projection,
         window: window::Window,
         aperture: f32,
         focus_dist: f32,
         zoom_speed: f32,
     ) -> Self {
         let raw = Self::generate_raw(
-            &look_from, &look_at, &v_up, v_fov, window, aperture, focus_dist,
+            &look_from, &look_at, &v_up, v_fov, projection, window, aperture, focus_dist,
         );
 
         let (bind_group_layout, bind_group, buffer) = buffer_bindings::create_device_buffer_binding(
             &[raw],
-            &device,
+            device,
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             wgpu::BufferBindingType::Uniform,
         );
@@ -100,9 +208,11 @@ impl Camera {
             look_at,
             v_up,
             v_fov,
+            projection,
             window,
             aperture,
             focus_dist,
+            bokeh_blades: 0,
             zoom_speed,
 
             view_matrix: Matrix4::identity(),
@@ -117,11 +227,13 @@ impl Camera {
         new_cam
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn generate_raw(
         look_from: &Point3<f32>,
         look_at: &Point3<f32>,
         v_up: &Vector3<f32>,
         v_fov: f32,
+        projection: Projection,
         window: window::Window,
         aperture: f32,
         focus_dist: f32,
@@ -136,14 +248,38 @@ impl Camera {
         let u = v_up.cross(w).normalize();
         let v = w.cross(u);
 
-        let origin = look_from.clone();
-        let horizontal = u * viewport_width * focus_dist;
-        let vertical = v * viewport_height * focus_dist;
-        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - w * focus_dist;
-        let lens_radius = aperture / 2.0;
+        let origin = *look_from;
+        let (horizontal, vertical, lower_left_corner) = match projection {
+            // View plane through look_from, sized to the perspective view at look_at (so switching keeps the framing)
+            Projection::Orthographic => {
+                let distance = (look_at - look_from).magnitude();
+                let horizontal = u * viewport_width * distance;
+                let vertical = v * viewport_height * distance;
+                (
+                    horizontal,
+                    vertical,
+                    origin - horizontal / 2.0 - vertical / 2.0,
+                )
+            }
+            _ => {
+                let horizontal = u * viewport_width * focus_dist;
+                let vertical = v * viewport_height * focus_dist;
+                (
+                    horizontal,
+                    vertical,
+                    origin - horizontal / 2.0 - vertical / 2.0 - w * focus_dist,
+                )
+            }
+        };
+        // Depth of field is only modelled by the perspective thin lens
+        let lens_radius = match projection {
+            Projection::Perspective => aperture / 2.0,
+            _ => 0.0,
+        };
 
         CameraRaw::new(
             origin,
+            projection,
             lower_left_corner,
             horizontal,
             vertical,
@@ -154,9 +290,11 @@ impl Camera {
         )
     }
 
-    // pub fn set_window(&mut self, window: window::Window) {
-    //     self.window = window;
-    // }
+    /// Update the aspect ratio for a new window size
+    pub fn set_window(&mut self, queue: &wgpu::Queue, window: window::Window) {
+        self.window = window;
+        self.update(queue);
+    }
 
     pub fn update(&mut self, queue: &wgpu::Queue) {
         let raw = Self::generate_raw(
@@ -164,11 +302,15 @@ impl Camera {
             &self.look_at,
             &self.v_up,
             self.v_fov,
+            self.projection,
             self.window,
             self.aperture,
             self.focus_dist,
         );
-        self.raw = raw;
+        self.raw = CameraRaw {
+            bokeh_blades: self.bokeh_blades,
+            ..raw
+        };
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.raw]));
     }
 
@@ -180,7 +322,6 @@ impl Camera {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         result: &mut result::Result,
-        size: winit::dpi::PhysicalSize<u32>,
         mouse_wheel_y: f32,
     ) {
         self.set_camera_view(
@@ -192,7 +333,7 @@ impl Camera {
         // Push the changes to the GPU
         self.update(queue);
         // Reset the accumulation ray color result texture
-        result.reset_texture(device, queue, size);
+        result.reset_texture(device, queue);
     }
 
     ///
@@ -243,7 +384,265 @@ impl Camera {
         // Push the changes to the GPU
         self.update(queue);
         // Reset the accumulation ray color result texture
-        result.reset_texture(device, queue, size);
+        result.reset_texture(device, queue);
+    }
+
+    ///
+    /// Pan (move look_from and look_at together) in the view plane, so the scene follows the mouse at the look_at distance
+    ///
+    pub fn pan(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        size: winit::dpi::PhysicalSize<u32>,
+        mouse_prev: Vector2<f32>,
+        mouse_cur: Vector2<f32>,
+    ) {
+        // World distance of a pixel at the look_at distance
+        let distance = (self.look_at - self.look_from).magnitude();
+        let pixel = 2.0 * (util::degrees_to_radians(self.v_fov) / 2.0).tan() * distance
+            / size.height as f32;
+
+        // Y is reversed as the screen has the origin in the top left corner
+        let delta = mouse_cur - mouse_prev;
+        let up = self.get_right_vector().cross(self.get_view_dir());
+        let offset = (-delta.x * self.get_right_vector() + delta.y * up) * pixel;
+        self.move_by(device, queue, result, offset);
+    }
+
+    ///
+    /// First person movement (right, up, forward) in the camera frame
+    ///
+    pub fn fly(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        movement: Vector3<f32>,
+    ) {
+        let offset = movement.x * self.get_right_vector()
+            + movement.y * self.v_up
+            + movement.z * self.get_view_dir();
+        self.move_by(device, queue, result, offset);
+    }
+
+    ///
+    /// First person look around, rotating look_at about look_from (yaw about v_up and pitch about the right vector)
+    ///
+    pub fn look_around(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        size: winit::dpi::PhysicalSize<u32>,
+        mouse_prev: Vector2<f32>,
+        mouse_cur: Vector2<f32>,
+    ) {
+        // A movement across the screen turns by the field of view
+        let fov = util::degrees_to_radians(self.v_fov);
+        let yaw = (mouse_prev.x - mouse_cur.x) * fov / size.height as f32;
+        let mut pitch = (mouse_prev.y - mouse_cur.y) * fov / size.height as f32;
+
+        // Stop pitching past the up vector
+        let cos_angle: f32 = self.get_view_dir().dot(self.v_up);
+        if cos_angle * pitch.signum() > 0.99 {
+            pitch = 0.0;
+        }
+
+        let rotation = Matrix4::from_axis_angle(self.get_right_vector(), Rad(pitch))
+            * Matrix4::from_axis_angle(self.v_up, Rad(yaw));
+        let target = self.look_at - self.look_from;
+        let target = (rotation * target.extend(0.0)).truncate();
+        self.set_camera_view(self.look_from, self.look_from + target, self.v_up);
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+    }
+
+    ///
+    /// Look at a point from the current direction, at a distance framing a sphere of the radius (or at the current distance)
+    ///
+    pub fn focus(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        target: Point3<f32>,
+        radius: Option<f32>,
+    ) {
+        let distance = match radius {
+            Some(radius) => radius / (util::degrees_to_radians(self.v_fov) / 2.0).sin(),
+            None => (self.look_at - self.look_from).magnitude(),
+        };
+        let look_from = target - self.get_view_dir() * distance;
+        self.set_camera_view(look_from, target, self.v_up);
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+    }
+
+    ///
+    /// Switch the projection of the camera rays
+    ///
+    pub fn set_projection(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        projection: Projection,
+    ) {
+        self.projection = projection;
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+    }
+
+    ///
+    /// View look_at along a world axis from the current distance (e.g. front, top and side orthographic views)
+    ///
+    pub fn view_along(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        axis: Vector3<f32>,
+        v_up: Vector3<f32>,
+    ) {
+        let distance = (self.look_at - self.look_from).magnitude();
+        self.set_camera_view(
+            self.look_at + axis.normalize() * distance,
+            self.look_at,
+            v_up,
+        );
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+    }
+
+    ///
+    /// Set the focus distance so the focus plane passes through a point (e.g. picked in the scene)
+    ///
+    pub fn focus_on_point(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        point: Point3<f32>,
+    ) -> f32 {
+        // The focus plane is perpendicular to the view direction
+        self.focus_dist = (point - self.look_from)
+            .dot(self.get_view_dir())
+            .max(f32::EPSILON);
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+        self.focus_dist
+    }
+
+    ///
+    /// Scale the aperture (depth of field), returns the new aperture
+    ///
+    pub fn scale_aperture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        factor: f32,
+    ) -> f32 {
+        self.aperture = if self.aperture == 0.0 && factor > 1.0 {
+            MIN_APERTURE
+        } else if self.aperture * factor < MIN_APERTURE {
+            0.0
+        } else {
+            self.aperture * factor
+        };
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+        self.aperture
+    }
+
+    ///
+    /// Cycle the number of aperture blades shaping the bokeh, returns the new count (0: round)
+    ///
+    pub fn cycle_bokeh_blades(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+    ) -> u32 {
+        let index = BOKEH_BLADES
+            .iter()
+            .position(|&b| b == self.bokeh_blades)
+            .unwrap_or(0);
+        self.bokeh_blades = BOKEH_BLADES[(index + 1) % BOKEH_BLADES.len()];
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+        self.bokeh_blades
+    }
+
+    ///
+    /// Ray from the centre of the lens through the point on the screen (u, v in [0, 1] from the bottom left)
+    ///
+    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+        self.raw.get_ray(u, v)
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn look_at(&self) -> Point3<f32> {
+        self.look_at
+    }
+
+    /// Current camera parameters (for bookmarks and printing)
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            look_from: self.look_from,
+            look_at: self.look_at,
+            v_up: self.v_up,
+            v_fov: self.v_fov,
+            projection: self.projection,
+            aperture: self.aperture,
+            focus_dist: self.focus_dist,
+        }
+    }
+
+    /// Jump to the camera parameters (e.g. a bookmark)
+    pub fn set_state(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        state: CameraState,
+    ) {
+        self.v_fov = state.v_fov;
+        self.projection = state.projection;
+        self.aperture = state.aperture;
+        self.focus_dist = state.focus_dist;
+        self.set_camera_view(state.look_from, state.look_at, state.v_up);
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
     }
 
     pub fn get_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
@@ -266,6 +665,22 @@ impl Camera {
         self.update_view_matrix();
     }
 
+    /// Move look_from and look_at together
+    fn move_by(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        result: &mut result::Result,
+        offset: Vector3<f32>,
+    ) {
+        self.set_camera_view(self.look_from + offset, self.look_at + offset, self.v_up);
+
+        // Push the changes to the GPU
+        self.update(queue);
+        // Reset the accumulation ray color result texture
+        result.reset_texture(device, queue);
+    }
+
     fn update_view_matrix(&mut self) {
         self.view_matrix = Matrix4::look_at_rh(self.look_from, self.look_at, self.v_up);
     }
@@ -279,3 +694,56 @@ impl Camera {
         self.view_matrix.transpose()[0].truncate()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_projection_rays() {
+        let look_from = Point3::new(0.0, 0.0, 2.0);
+        let look_at = Point3::new(0.0, 0.0, 0.0);
+        let v_up = Vector3::new(0.0, 1.0, 0.0);
+        let window = window::Window {
+            width_pixels: 200,
+            height_pixels: 100,
+        };
+        let raw = |projection| {
+            Camera::generate_raw(
+                &look_from, &look_at, &v_up, 90.0, projection, window, 0.0, 2.0,
+            )
+        };
+        let forward = Vector3::new(0.0, 0.0, -1.0);
+        let close = |a: Vector3<f32>, b: Vector3<f32>| (a - b).magnitude() < 1.0e-5;
+
+        // Every projection looks at look_at through the centre of the screen
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Equirectangular,
+            Projection::Fisheye,
+        ] {
+            let ray = raw(projection).get_ray(0.5, 0.5);
+            assert!(close(ray.direction.normalize(), forward), "{}", projection);
+            assert_eq!(projection.to_string().parse(), Ok(projection));
+        }
+
+        // Orthographic rays are parallel, with the view height at the look_at distance (2 tan(45) 2 = 4)
+        let orthographic = raw(Projection::Orthographic);
+        let (bottom, top) = (
+            orthographic.get_ray(0.5, 0.0),
+            orthographic.get_ray(0.5, 1.0),
+        );
+        assert!(close(bottom.direction, forward) && close(top.direction, forward));
+        assert!(((top.origin - bottom.origin).magnitude() - 4.0).abs() < 1.0e-5);
+
+        // Equirectangular covers the full sphere, the left and right edges look backwards
+        let equirectangular = raw(Projection::Equirectangular);
+        assert!(close(equirectangular.get_ray(0.0, 0.5).direction, -forward));
+        assert!(close(equirectangular.get_ray(0.5, 1.0).direction, v_up));
+
+        // Fisheye is 180 degrees across the height
+        let fisheye = raw(Projection::Fisheye);
+        assert!(close(fisheye.get_ray(0.5, 1.0).direction, v_up));
+    }
+}
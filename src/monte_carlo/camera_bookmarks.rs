@@ -0,0 +1,195 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use cgmath::{Point3, Vector3};
+
+use super::camera::Projection;
+
+/// Sidecar file the bookmarks are kept in (in the working directory)
+pub const BOOKMARKS_PATH: &str = "camera_bookmarks.txt";
+
+/// Number of bookmark slots (the number keys, 0 is also the camera used at startup)
+pub const BOOKMARK_SLOTS: usize = 10;
+
+/// Serialisable camera parameters, written as a single line of `key=value` pairs (so it can be pasted into the bookmarks file)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraState {
+    pub look_from: Point3<f32>,
+    pub look_at: Point3<f32>,
+    pub v_up: Vector3<f32>,
+    pub v_fov: f32,
+    pub projection: Projection,
+    pub aperture: f32,
+    pub focus_dist: f32,
+}
+
+impl fmt::Display for CameraState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "look_from={},{},{} look_at={},{},{} v_up={},{},{} v_fov={} projection={} aperture={} focus_dist={}",
+            self.look_from.x,
+            self.look_from.y,
+            self.look_from.z,
+            self.look_at.x,
+            self.look_at.y,
+            self.look_at.z,
+            self.v_up.x,
+            self.v_up.y,
+            self.v_up.z,
+            self.v_fov,
+            self.projection,
+            self.aperture,
+            self.focus_dist
+        )
+    }
+}
+
+impl FromStr for CameraState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut look_from = None;
+        let mut look_at = None;
+        let mut v_up = None;
+        let mut v_fov = None;
+        let mut projection = None;
+        let mut aperture = None;
+        let mut focus_dist = None;
+        for token in s.split_whitespace() {
+            let (key, value) = token
+                .split_once('=')
+                .ok_or(format!("Expect key=value, found '{}'", token))?;
+            match key {
+                "look_from" => look_from = Some(parse_vector(value)?),
+                "look_at" => look_at = Some(parse_vector(value)?),
+                "v_up" => v_up = Some(parse_vector(value)?),
+                "v_fov" => v_fov = Some(parse_float(value)?),
+                "projection" => projection = Some(value.parse()?),
+                "aperture" => aperture = Some(parse_float(value)?),
+                "focus_dist" => focus_dist = Some(parse_float(value)?),
+                _ => return Err(format!("Unknown camera parameter '{}'", key)),
+            }
+        }
+        let missing = |name: &str| format!("Missing camera parameter '{}'", name);
+        Ok(CameraState {
+            look_from: Point3::from(look_from.ok_or_else(|| missing("look_from"))?),
+            look_at: Point3::from(look_at.ok_or_else(|| missing("look_at"))?),
+            v_up: Vector3::from(v_up.ok_or_else(|| missing("v_up"))?),
+            v_fov: v_fov.ok_or_else(|| missing("v_fov"))?,
+            // Optional, bookmarks saved before projections were added are perspective
+            projection: projection.unwrap_or(Projection::Perspective),
+            aperture: aperture.ok_or_else(|| missing("aperture"))?,
+            focus_dist: focus_dist.ok_or_else(|| missing("focus_dist"))?,
+        })
+    }
+}
+
+pub fn parse_float(value: &str) -> Result<f32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Expect a number, found '{}'", value))
+}
+
+pub fn parse_vector(value: &str) -> Result<[f32; 3], String> {
+    let components = value
+        .split(',')
+        .map(parse_float)
+        .collect::<Result<Vec<f32>, String>>()?;
+    <[f32; 3]>::try_from(components)
+        .map_err(|_| format!("Expect 3 comma separated numbers, found '{}'", value))
+}
+
+///
+/// Numbered camera bookmarks kept in a sidecar text file, one `<slot> <camera state>` line per bookmark (# comments are ignored).
+///
+pub struct CameraBookmarks {
+    path: PathBuf,
+    slots: [Option<CameraState>; BOOKMARK_SLOTS],
+}
+
+impl CameraBookmarks {
+    /// Load the bookmarks from the file (none if the file doesn't exist yet, invalid lines are reported and skipped)
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut slots = [None; BOOKMARK_SLOTS];
+        if let Ok(contents) = std::fs::read_to_string(&path) {
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let parsed = line
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| "Expect a slot and camera state".to_string())
+                    .and_then(|(slot, state)| {
+                        let slot: usize = slot
+                            .parse()
+                            .ok()
+                            .filter(|&s| s < BOOKMARK_SLOTS)
+                            .ok_or(format!("Invalid bookmark slot '{}'", slot))?;
+                        Ok((slot, state.parse::<CameraState>()?))
+                    });
+                match parsed {
+                    Ok((slot, state)) => slots[slot] = Some(state),
+                    Err(e) => println!("Skipping camera bookmark '{}': {}", line, e),
+                }
+            }
+        }
+        CameraBookmarks { path, slots }
+    }
+
+    pub fn get(&self, slot: usize) -> Option<CameraState> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Set the bookmark and write all of the bookmarks to the file
+    pub fn set(&mut self, slot: usize, state: CameraState) -> std::io::Result<()> {
+        self.slots[slot] = Some(state);
+        let mut contents =
+            String::from("# slot look_from look_at v_up v_fov projection aperture focus_dist\n");
+        for (slot, state) in self.slots.iter().enumerate() {
+            if let Some(state) = state {
+                contents += &format!("{} {}\n", slot, state);
+            }
+        }
+        std::fs::write(&self.path, contents)
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bookmarks_round_trip() {
+        let state = CameraState {
+            look_from: Point3::new(0.2, 0.5, 0.9),
+            look_at: Point3::new(0.0, 0.1, 0.0),
+            v_up: Vector3::new(0.0, 1.0, 0.0),
+            v_fov: 25.0,
+            projection: Projection::Orthographic,
+            aperture: 0.05,
+            focus_dist: 4.0,
+        };
+        assert_eq!(state.to_string().parse::<CameraState>(), Ok(state));
+        assert!("look_from=1,2 look_at=0,0,0"
+            .parse::<CameraState>()
+            .is_err());
+
+        let path = std::env::temp_dir().join(format!(
+            "monte_carlo_camera_bookmarks_{}.txt",
+            std::process::id()
+        ));
+        let mut bookmarks = CameraBookmarks::load(&path);
+        assert_eq!(bookmarks.get(3), None);
+        bookmarks.set(3, state).unwrap();
+
+        let loaded = CameraBookmarks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get(3), Some(state));
+        assert_eq!(loaded.get(0), None);
+    }
+}
@@ -0,0 +1,209 @@
+use cgmath::Vector3;
+
+use super::tone_mapping::ToneMapping;
+
+/// Number of colour maps (including 0: Off)
+pub const COLORMAP_COUNT: u32 = 4;
+
+/// Name of the colour map. 0: Off, 1: Viridis, 2: Inferno, 3: Coolwarm
+pub fn colormap_name(map: u32) -> &'static str {
+    match map {
+        0 => "off",
+        1 => "viridis",
+        2 => "inferno",
+        3 => "coolwarm",
+        _ => panic!("Unsupported colour map"),
+    }
+}
+
+/// Colour map lookup (CPU version of `colormap` in the shader), t is clamped to [0, 1] and the returned colour is sRGB encoded.
+/// Viridis and inferno are polynomial fits (attribution: https://www.shadertoy.com/view/WlfXRN, CC0),
+/// coolwarm is linearly interpolated from the Moreland diverging map control points.
+pub fn colormap(map: u32, t: f32) -> Vector3<f32> {
+    let t = t.clamp(0.0, 1.0);
+    let polynomial = |c: [[f32; 3]; 7]| {
+        let c = c.map(Vector3::from);
+        c[0] + t * (c[1] + t * (c[2] + t * (c[3] + t * (c[4] + t * (c[5] + t * c[6])))))
+    };
+    match map {
+        1 => polynomial([
+            [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+            [0.105_093_04, 1.404_613_5, 1.384_590_2],
+            [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+            [-4.634_230_5, -5.799_101, -19.332_441],
+            [6.228_27, 14.179_933, 56.690_55],
+            [4.776_385, -13.745_145, -65.353_03],
+            [-5.435_456, 4.645_852_6, 26.312_435],
+        ]),
+        2 => polynomial([
+            [0.000_218_940_37, 0.001_651_004_6, -0.019_480_899],
+            [0.106_513_42, 0.563_956_45, 3.932_712_3],
+            [11.602_493, -3.972_854, -15.942_394],
+            [-41.703_995, 17.436_4, 44.354_145],
+            [77.162_94, -33.402_36, -81.807_31],
+            [-71.319_43, 32.626_064, 73.209_52],
+            [25.131_126, -12.242_669, -23.070_325],
+        ]),
+        3 => {
+            let points = [
+                Vector3::new(0.230, 0.299, 0.754),
+                Vector3::new(0.552, 0.690, 0.996),
+                Vector3::new(0.865, 0.865, 0.865),
+                Vector3::new(0.958, 0.603, 0.482),
+                Vector3::new(0.706, 0.016, 0.150),
+            ];
+            let x = t * 4.0;
+            let i = (x as usize).min(3);
+            points[i] + (points[i + 1] - points[i]) * (x - i as f32)
+        }
+        _ => panic!("Unsupported colour map"),
+    }
+    .map(|c| c.clamp(0.0, 1.0))
+}
+
+/// sRGB encoded to linear colour channel
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear to sRGB encoded colour channel
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Colour mapping of the scalar wos result on the sections (CPU version of the display in the shader)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColormapDisplay {
+    /// 0: Off, 1: Viridis, 2: Inferno, 3: Coolwarm
+    pub map: u32,
+    /// Scalar value mapped to the start of the colour map
+    pub min: f32,
+    /// Scalar value mapped to the end of the colour map
+    pub max: f32,
+    /// Number of isocontour lines between min and max (0: Off)
+    pub contours: u32,
+}
+
+impl ColormapDisplay {
+    /// Scalar value mapped to [0, 1] over the range
+    pub fn normalize(&self, value: f32) -> f32 {
+        let range = self.max - self.min;
+        if range.abs() < f32::EPSILON {
+            return 0.5;
+        }
+        (value - self.min) / range
+    }
+
+    /// Contour band index of the scalar value (adjacent pixels in different bands are drawn as an isocontour line)
+    pub fn contour_band(&self, value: f32) -> i32 {
+        (self.normalize(value) * self.contours as f32).floor() as i32
+    }
+
+    /// Linear colour of a scalar value
+    pub fn linear_color(&self, value: f32) -> Vector3<f32> {
+        colormap(self.map, self.normalize(value)).map(srgb_to_linear)
+    }
+
+    /// Legend (shown with the on screen colour bar and saved next to images)
+    pub fn legend(&self) -> String {
+        format!(
+            "colormap: {}\nmin: {}\nmax: {}\ncontours: {}\n",
+            colormap_name(self.map),
+            self.min,
+            self.max,
+            self.contours
+        )
+    }
+}
+
+/// Composite the accumulated ray colour (tone mapped) and section (scalar sum, coverage) textures into an sRGB image with the legend colour bar
+/// (CPU version of `display_color` in the shader). Texture rows start at the bottom of the screen.
+pub fn composite_image(
+    width: u32,
+    height: u32,
+    color: &[[f32; 4]],
+    section: &[[f32; 4]],
+    display: &ColormapDisplay,
+    tone_mapping: &ToneMapping,
+) -> image::RgbaImage {
+    let index = |x: u32, y: u32| (y.min(height - 1) * width + x.min(width - 1)) as usize;
+    let scalar = |i: usize| section[i][0] / section[i][1];
+
+    image::RgbaImage::from_fn(width, height, |x, row| {
+        let y = height - 1 - row;
+        let i = index(x, y);
+        let mut c = tone_mapping.apply(Vector3::new(color[i][0], color[i][1], color[i][2]));
+
+        if display.map != 0 {
+            if section[i][1] > 0.0 {
+                let value = scalar(i);
+                let mut section_color = display.linear_color(value);
+                if display.contours > 0 {
+                    let band = display.contour_band(value);
+                    let neighbours = [index(x + 1, y), index(x, y + 1)];
+                    if neighbours
+                        .iter()
+                        .any(|&n| section[n][1] > 0.0 && display.contour_band(scalar(n)) != band)
+                    {
+                        section_color *= 0.1;
+                    }
+                }
+                c += section[i][1] * section_color;
+            }
+            if let Some(t) = legend_bar(width, height, x, y) {
+                c = colormap(display.map, t).map(srgb_to_linear);
+            }
+        }
+
+        let c = c.map(|v| (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8);
+        image::Rgba([c.x, c.y, c.z, 255])
+    })
+}
+
+/// Position along the legend colour bar (0 at the bottom) if the pixel (rows from the bottom) is inside it
+fn legend_bar(width: u32, height: u32, x: u32, y: u32) -> Option<f32> {
+    let (x0, x1) = (width.saturating_sub(40), width.saturating_sub(20));
+    let (y0, y1) = (height / 10, height - height / 10);
+    if x >= x0 && x < x1 && y >= y0 && y < y1 {
+        return Some((y - y0) as f32 / (y1 - y0) as f32);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::*;
+
+    fn color_distance(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+        (a - b).magnitude()
+    }
+
+    #[test]
+    fn test_colormap_end_points() {
+        assert!(color_distance(colormap(1, 0.0), Vector3::new(0.267, 0.005, 0.329)) < 0.02);
+        assert!(color_distance(colormap(1, 1.0), Vector3::new(0.993, 0.906, 0.144)) < 0.02);
+        assert!(color_distance(colormap(2, 0.0), Vector3::new(0.001, 0.0, 0.014)) < 0.02);
+        assert!(color_distance(colormap(2, 1.0), Vector3::new(0.988, 0.998, 0.645)) < 0.02);
+        assert!(color_distance(colormap(3, 0.5), Vector3::new(0.865, 0.865, 0.865)) < 0.001);
+        // Clamped outside of [0, 1]
+        assert_eq!(colormap(3, -1.0), colormap(3, 0.0));
+    }
+
+    #[test]
+    fn test_srgb_round_trip() {
+        for i in 0..=10 {
+            let c = i as f32 / 10.0;
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1.0e-5);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+/// Side of the square tiles of pixels accumulated by the compute pass (tile_size in the shader)
+pub const TILE_SIZE: u32 = 64;
+
+/// Side of the square compute workgroups (workgroup_size of cs_main in the shader)
+pub const WORKGROUP_SIZE: u32 = 8;
+
+///
+/// Tiled compute shader accumulation of the result (cs_main), separate from the display pass.
+/// Each pass dispatches a batch of tiles, so a full frame is spread over vertical_render_slices passes (keeping each dispatch short enough to avoid GPU timeouts).
+///
+pub struct ComputeRenderer {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ComputeRenderer {
+    /// Pipeline using the same bind group layouts as the display pass (constants & window, camera, scene, result)
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Compute Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: shader,
+            entry_point: "cs_main",
+        });
+        ComputeRenderer { pipeline }
+    }
+
+    /// Number of tiles accumulated in each pass
+    pub fn tiles_per_pass(size: winit::dpi::PhysicalSize<u32>, slices: u32) -> u32 {
+        let tiles = size.width.div_ceil(TILE_SIZE) * size.height.div_ceil(TILE_SIZE);
+        tiles.div_ceil(slices.max(1))
+    }
+
+    /// Record the accumulation of the tiles of the current pass (selected by the result pass index in the shader)
+    pub fn encode_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[&wgpu::BindGroup],
+        size: winit::dpi::PhysicalSize<u32>,
+        slices: u32,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            compute_pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        let workgroups = TILE_SIZE / WORKGROUP_SIZE;
+        compute_pass.dispatch(workgroups, workgroups, Self::tiles_per_pass(size, slices));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_per_pass() {
+        // 1280x1024 is 20x16 tiles, over 64 passes
+        let size = winit::dpi::PhysicalSize::new(1280, 1024);
+        assert_eq!(ComputeRenderer::tiles_per_pass(size, 64), 5);
+        assert_eq!(ComputeRenderer::tiles_per_pass(size, 1), 320);
+        // Partial tiles at the edges, and more passes than tiles
+        let size = winit::dpi::PhysicalSize::new(100, 65);
+        assert_eq!(ComputeRenderer::tiles_per_pass(size, 3), 2);
+        assert_eq!(ComputeRenderer::tiles_per_pass(size, 64), 1);
+    }
+}
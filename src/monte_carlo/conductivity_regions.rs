@@ -0,0 +1,91 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{linear_scene_bvh::LinearSceneBvh, util};
+
+/// Offset used for the finite difference normal of the region interfaces
+const NORMAL_OFFSET: f32 = 1.0e-4;
+
+/// Piecewise constant conductivity over the walk on spheres domain, with regions bounded by closed primitives
+/// Regions may be nested (e.g. a core inside a housing) but their surfaces must not intersect, the innermost region containing a point sets its conductivity.
+pub struct ConductivityRegions {
+    /// Primitives bounding the regions (not boundaries of the domain), the material index of each primitive selects its conductivity
+    pub scene: LinearSceneBvh,
+    /// Conductivity inside the regions using each material index
+    pub conductivities: Vec<f32>,
+    /// Conductivity outside of all the regions
+    pub background: f32,
+    /// Distance from an interface a walk is moved to on the side it crosses to
+    pub interface_step: f32,
+}
+
+impl ConductivityRegions {
+    pub fn new(
+        scene: LinearSceneBvh,
+        conductivities: Vec<f32>,
+        background: f32,
+        interface_step: f32,
+    ) -> Self {
+        assert_eq!(
+            scene.materials.len(),
+            conductivities.len(),
+            "Expect a conductivity for each region material"
+        );
+        ConductivityRegions {
+            scene,
+            conductivities,
+            background,
+            interface_step,
+        }
+    }
+
+    /// Distance from the point to the closest region interface
+    pub fn interface_distance(&self, point: Point3<f32>) -> f32 {
+        self.scene.scene_sd(point).0.abs()
+    }
+
+    /// Conductivity at the point, of the innermost region containing it
+    /// (a region nested in another is closer to its own surface than to the surface of the outer region)
+    pub fn conductivity(&self, point: Point3<f32>) -> f32 {
+        self.scene
+            .primitive_sds(point)
+            .into_iter()
+            .filter(|(sd, _)| *sd < 0.0)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or(self.background, |(_, material_index)| {
+                self.conductivities[material_index as usize]
+            })
+    }
+
+    /// Move a point on (within the tolerance of) an interface to one of its sides, chosen with probability proportional to the
+    /// conductivity of that side (first order walk on spheres interface scheme, a skew brownian motion across the interface)
+    /// The step off the interface is limited by the distance to the domain boundary, the conductivity of each side is found at the stepped points (so nested regions cross to the enclosing region).
+    pub fn cross_interface(&self, point: Point3<f32>, boundary_distance: f32) -> Point3<f32> {
+        let sd = self.scene.scene_sd(point).0;
+
+        // Outward normal of the region from the gradient of the signed distance
+        let gradient = |axis: Vector3<f32>| {
+            self.scene.scene_sd(point + axis * NORMAL_OFFSET).0
+                - self.scene.scene_sd(point - axis * NORMAL_OFFSET).0
+        };
+        let normal = Vector3::new(
+            gradient(Vector3::unit_x()),
+            gradient(Vector3::unit_y()),
+            gradient(Vector3::unit_z()),
+        )
+        .normalize();
+
+        let step = self.interface_step.min(0.5 * boundary_distance);
+        let on_interface = point - sd * normal;
+        let (inside_point, outside_point) =
+            (on_interface - step * normal, on_interface + step * normal);
+        let (inside, outside) = (
+            self.conductivity(inside_point),
+            self.conductivity(outside_point),
+        );
+        if util::random_float() * (inside + outside) < inside {
+            inside_point
+        } else {
+            outside_point
+        }
+    }
+}
@@ -1,6 +1,11 @@
 use cgmath::Vector3;
 
-use super::material::Material;
+use super::{
+    colormap::{ColormapDisplay, COLORMAP_COUNT},
+    material::Material,
+    tone_mapping::{ToneMapping, TONE_MAP_OPERATOR_COUNT},
+    wos::WosSettings,
+};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -11,7 +16,7 @@ pub struct Constants {
     pass_samples_per_pixel: i32,
     /// Maximum depth of bounced ray.
     max_depth: i32,
-    /// Number of vertical subdivision for single frame passes.
+    /// Number of passes a full frame of tiles is accumulated over (the tiles of each pass are dispatched together).
     vertical_render_slices: i32,
     /// 0: Off, 1: On
     draw_vertical_render_slice_region: u32,
@@ -21,8 +26,53 @@ pub struct Constants {
     draw_bvh_attenuation: f32,
     /// WoS Tolerance Distance (e.g. distance to surface before the walk is halted and surface sampled)
     wos_tolerance: f32,
+    /// WoS blend display. 0: Solution value, 1: Flux (gradient) magnitude, 2: Flux (gradient) direction
+    wos_gradient_display: u32,
+    /// Scale applied to the gradient before it is displayed
+    wos_gradient_scale: f32,
+    /// WoS domain. 0: Exterior (outside of all primitives), 1: Interior (inside the closed primitives)
+    wos_domain: u32,
+    /// Maximum number of sphere steps in a single walk
+    wos_max_steps: u32,
+    /// WoS epsilon shell. 0: Absolute (wos_tolerance), 1: Relative (wos_tolerance * distance to the boundary at the start of the walk)
+    wos_epsilon_policy: u32,
+    /// Number of steps before russian roulette is applied to a walk (0: Off)
+    wos_roulette_steps: u32,
+    /// Probability of a walk surviving each russian roulette step
+    wos_roulette_survival: f32,
+    /// Radius of the far field boundary sphere around the origin for exterior problems (0: Off, i.e. at infinity)
+    wos_far_field_radius: f32,
+    /// Colour map of the scalar wos result (mean value or flux magnitude) on directly visible sections. 0: Off (albedo blend), 1: Viridis, 2: Inferno, 3: Coolwarm
+    wos_colormap: u32,
+    /// Number of isocontour lines drawn on the colour mapped sections (0: Off)
+    wos_colormap_contours: u32,
+    /// Boundary value on the far field boundary sphere (and of exterior walks that reach the maximum steps, i.e. the value at infinity)
+    wos_far_field_value: [f32; 3],
+    /// Scalar value mapped to the start of the colour map
+    wos_colormap_min: f32,
+    /// Scalar value mapped to the end of the colour map
+    wos_colormap_max: f32,
+    /// Colour map range. 0: Manual (wos_colormap_min/max), 1: Auto (fitted to the sections after the first full frame)
+    wos_colormap_auto: u32,
+    /// Radiative flux display of directly visible surfaces from the scene patch colours. 0: Off, 1: On
+    radiative_flux_display: u32,
+    /// Per pixel adaptive sampling from the sample variance. 0: Off (pass_samples_per_pixel everywhere), 1: On
+    adaptive_sampling: u32,
+    /// Target relative standard error of the pixel mean luminance, pixels below it stop sampling
+    adaptive_target_error: f32,
+    /// Samples of a pixel before its variance is trusted (sampled uniformly until then)
+    adaptive_min_samples: u32,
+    /// Maximum samples of a pixel in a single pass (noisy pixels get up to this many)
+    adaptive_max_pass_samples: u32,
+    /// Tone map operator of the display pass. 0: None (clamp), 1: Reinhard, 2: ACES (fitted), 3: Filmic (Hable)
+    tone_map_operator: u32,
+    /// Display exposure in stops (the accumulated radiance is scaled by 2^exposure)
+    exposure: f32,
+    /// False colour display of the exposed luminance (inferno over middle grey +- 6 stops). 0: Off, 1: On
+    false_color: u32,
+    /// Apply the sRGB encoding in the display pass (when the render target format doesn't). 0: Off, 1: On
+    display_encode_srgb: u32,
     _pad1: u32,
-    _pad2: u32,
     /// Background color
     background: Material,
 }
@@ -40,9 +90,159 @@ impl Constants {
             draw_bvh: 0,
             draw_bvh_attenuation: 0.8,
             wos_tolerance: 0.005,
+            wos_gradient_display: 0,
+            wos_gradient_scale: 0.1,
+            wos_domain: 0,
+            wos_max_steps: 32,
+            wos_epsilon_policy: 0,
+            wos_roulette_steps: 0,
+            wos_roulette_survival: 0.9,
+            wos_far_field_radius: 0.0,
+            wos_colormap: 0,
+            wos_colormap_contours: 0,
+            wos_far_field_value: [0.0; 3],
+            wos_colormap_min: 0.0,
+            wos_colormap_max: 1.0,
+            wos_colormap_auto: 1,
+            radiative_flux_display: 0,
+            adaptive_sampling: 0,
+            adaptive_target_error: 0.02,
+            adaptive_min_samples: 16,
+            adaptive_max_pass_samples: 8,
+            tone_map_operator: 0,
+            exposure: 0.0,
+            false_color: 0,
+            display_encode_srgb: 0,
             _pad1: 0,
-            _pad2: 0,
             background: Material::new(0, 0.0, 0.0, Vector3::new(0.70, 0.80, 1.00)),
         }
     }
+
+    /// Number of passes for a full frame
+    pub fn vertical_render_slices(&self) -> u32 {
+        self.vertical_render_slices as u32
+    }
+
+    /// Samples per pixel accumulated by a full frame (without adaptive sampling)
+    pub fn pass_samples_per_pixel(&self) -> u32 {
+        self.pass_samples_per_pixel as u32
+    }
+
+    /// Walk on spheres settings for the CPU solver (matching the shader)
+    pub fn wos_settings(&self) -> WosSettings {
+        WosSettings {
+            tolerance: self.wos_tolerance,
+            max_steps: self.wos_max_steps,
+            epsilon_policy: self.wos_epsilon_policy,
+            domain: self.wos_domain,
+            roulette_steps: self.wos_roulette_steps,
+            roulette_survival: self.wos_roulette_survival,
+            far_field_radius: self.wos_far_field_radius,
+            far_field_value: self.wos_far_field_value.into(),
+        }
+    }
+
+    /// Toggle the WoS domain between the exterior and interior of the scene primitives
+    pub fn toggle_wos_domain(&mut self) -> u32 {
+        self.wos_domain = 1 - self.wos_domain;
+        self.wos_domain
+    }
+
+    /// Cycle the WoS blend display between the solution value, flux magnitude and flux direction
+    pub fn cycle_wos_gradient_display(&mut self) -> u32 {
+        self.wos_gradient_display = (self.wos_gradient_display + 1) % 3;
+        self.wos_gradient_display
+    }
+
+    /// Colour mapping of the sections for the CPU image composition
+    pub fn colormap_display(&self) -> ColormapDisplay {
+        ColormapDisplay {
+            map: self.wos_colormap,
+            min: self.wos_colormap_min,
+            max: self.wos_colormap_max,
+            contours: self.wos_colormap_contours,
+        }
+    }
+
+    /// Cycle the section colour map between off, viridis, inferno and coolwarm
+    pub fn cycle_wos_colormap(&mut self) -> u32 {
+        self.wos_colormap = (self.wos_colormap + 1) % COLORMAP_COUNT;
+        self.wos_colormap
+    }
+
+    /// Toggle the section isocontour lines
+    pub fn toggle_wos_colormap_contours(&mut self) -> u32 {
+        self.wos_colormap_contours = if self.wos_colormap_contours == 0 {
+            10
+        } else {
+            0
+        };
+        self.wos_colormap_contours
+    }
+
+    /// Toggle the colour map range between manual and auto
+    pub fn toggle_wos_colormap_auto(&mut self) -> bool {
+        self.wos_colormap_auto = 1 - self.wos_colormap_auto;
+        self.wos_colormap_auto == 1
+    }
+
+    /// Whether the colour map range should be fitted automatically
+    pub fn wos_colormap_auto(&self) -> bool {
+        self.wos_colormap != 0 && self.wos_colormap_auto == 1
+    }
+
+    /// Set the scalar range of the colour map
+    pub fn set_wos_colormap_range(&mut self, min: f32, max: f32) {
+        self.wos_colormap_min = min;
+        self.wos_colormap_max = max;
+    }
+
+    /// Display transform of the accumulated radiance
+    pub fn tone_mapping(&self) -> ToneMapping {
+        ToneMapping {
+            operator: self.tone_map_operator,
+            exposure: self.exposure,
+            false_color: self.false_color == 1,
+        }
+    }
+
+    /// Cycle the tone map operator between none, reinhard, aces and filmic
+    pub fn cycle_tone_map_operator(&mut self) -> u32 {
+        self.tone_map_operator = (self.tone_map_operator + 1) % TONE_MAP_OPERATOR_COUNT;
+        self.tone_map_operator
+    }
+
+    /// Change the display exposure by a number of stops
+    pub fn add_exposure(&mut self, stops: f32) -> f32 {
+        self.exposure += stops;
+        self.exposure
+    }
+
+    /// Toggle the false colour luminance display, returns whether it is now on
+    pub fn toggle_false_color(&mut self) -> bool {
+        self.false_color = 1 - self.false_color;
+        self.false_color == 1
+    }
+
+    /// Whether the display pass needs to apply the sRGB encoding (the render target format is not sRGB)
+    pub fn set_display_encode_srgb(&mut self, encode: bool) {
+        self.display_encode_srgb = encode as u32;
+    }
+
+    /// Toggle the per pixel adaptive sampling, returns whether it is now on
+    pub fn toggle_adaptive_sampling(&mut self) -> bool {
+        self.adaptive_sampling = 1 - self.adaptive_sampling;
+        self.adaptive_sampling == 1
+    }
+
+    /// Target relative standard error of the pixels (for adaptive sampling)
+    pub fn adaptive_target_error(&self) -> f32 {
+        self.adaptive_target_error
+    }
+
+    /// Toggle the radiative flux display, returns whether it is now on
+    pub fn toggle_radiative_flux_display(&mut self) -> bool {
+        self.radiative_flux_display = 1 - self.radiative_flux_display;
+        self.radiative_flux_display == 1
+    }
 }
@@ -1,27 +1,85 @@
+use cgmath::{Matrix4, SquareMatrix};
+
 use super::{
-    construction_scene_bvh_node::SceneBvhConstructionNode, hittable_primitive::HittablePrimitive,
-    linear_constant_medium::LinearConstantMedium, linear_hittable::LinearHittable,
-    linear_scene_bvh::LinearSceneBvh, material::Material, sphere::Sphere, triangle::Triangle,
+    bvh_node::BvhNode,
+    construction_scene_bvh_node::SceneBvhConstructionNode,
+    hittable_primitive::HittablePrimitive,
+    linear_constant_medium::LinearConstantMedium,
+    linear_hittable::LinearHittable,
+    linear_scene_bvh::{LinearSceneBvh, MeshRange},
+    material::Material,
+    mesh_instance::MeshInstance,
+    sphere::Sphere,
 };
 
+/// Rebuild the top level bvh from its leaves, in the reserved top level ranges of the scene hittables and bvh nodes
+/// (the mesh bvhs after them are kept as they are)
 pub fn recompute_bvh(scene: &mut LinearSceneBvh) {
-    // Clear existing scene bvh nodes (and hittables reference)
-    scene.bvh_nodes.clear();
-    scene.hittables = scene
-        .hittables
+    // Top level leaves (the bvh nodes and padding are dropped)
+    let leaves: Vec<LinearHittable> = scene.hittables[..scene.top_level_capacity]
         .iter()
         .cloned()
         .filter(|h| h.geometry_type != 0)
         .collect();
 
-    // Source objects are cloned at the array slice is used within the following recursive bvh construction function
-    let source_objects = scene.hittables.clone();
+    // Build the bvh after the existing nodes (mesh instances need their mesh bvh in place for their bounding box)
+    let first_node = scene.bvh_nodes.len();
+    let top_level = build_bvh(scene, &leaves, 0);
+    let nodes = scene.bvh_nodes.split_off(first_node);
+    assert!(
+        nodes.len() < scene.top_level_node_capacity && top_level.len() <= scene.top_level_capacity,
+        "Top level bvh exceeds its reserved capacity"
+    );
+
+    // Move it into the top level ranges, the padding references the last node (which is always spare)
+    scene.bvh_nodes[..nodes.len()].copy_from_slice(&nodes);
+    scene.bvh_nodes[nodes.len()..scene.top_level_node_capacity].fill(BvhNode::empty());
+    let padding = LinearHittable {
+        geometry_type: 0,
+        scene_index: (scene.top_level_node_capacity - 1) as u32,
+    };
+    scene.hittables[..scene.top_level_capacity].fill(padding);
+    for (i, hittable) in top_level.into_iter().enumerate() {
+        scene.hittables[i] = if hittable.geometry_type == 0 {
+            LinearHittable {
+                geometry_type: 0,
+                scene_index: hittable.scene_index - first_node as u32,
+            }
+        } else {
+            hittable
+        };
+    }
+
+    // Baseline for the refitted bvh quality
+    scene.built_bvh_cost = scene.bvh_cost();
+}
+
+/// Build a bvh of the objects, appending its nodes to the scene bvh nodes
+/// Returns the flattened bvh hittables (root first), with the child pointers offset for the hittables to be placed at the hittable offset
+fn build_bvh(
+    scene: &mut LinearSceneBvh,
+    objects: &[LinearHittable],
+    hittable_offset: usize,
+) -> Vec<LinearHittable> {
+    let first_node = scene.bvh_nodes.len();
 
     // Build a referenced structure bvh tree from the scene
-    let bvh_construction = SceneBvhConstructionNode::new(scene, &source_objects[..]);
+    let bvh_construction = SceneBvhConstructionNode::new(scene, objects);
 
-    // Flatten the bvh tree into a linearized structure and update the scene accordingly
+    // Flatten the bvh tree into a linearized structure (the flattening replaces the scene hittables)
+    let hittables = std::mem::take(&mut scene.hittables);
     bvh_construction.flatten(scene);
+    let flattened = std::mem::replace(&mut scene.hittables, hittables);
+
+    for node in scene.bvh_nodes[first_node..].iter_mut() {
+        if node.left() != BvhNode::null_hittable_ptr() {
+            node.set_left(node.left() + hittable_offset as u32);
+        }
+        if node.right() != BvhNode::null_hittable_ptr() {
+            node.set_right(node.right() + hittable_offset as u32);
+        }
+    }
+    flattened
 }
 
 /// Primary scene construction function
@@ -29,12 +87,24 @@ pub fn build_from_hittable_primitives(
     materials: &[Material],
     primitives: &[HittablePrimitive],
 ) -> LinearSceneBvh {
+    build_with_hittables(materials, primitives).0
+}
+
+/// Scene construction, also returning the top level hittable of each primitive (in the order of the primitives),
+/// for referencing the primitives in the scene after construction
+pub fn build_with_hittables(
+    materials: &[Material],
+    primitives: &[HittablePrimitive],
+) -> (LinearSceneBvh, Vec<LinearHittable>) {
     // First create a new scene which will be assembled in the follow steps
     let mut scene = LinearSceneBvh::new();
 
     // Materials are directly added
     scene.materials = materials.to_vec();
 
+    // Instance triangles placed so far (the first patch of the next instance)
+    let mut instance_triangles = 0;
+
     // Convert the hittable primitives to individual vectors of each primitives type
     // And update the vector of the linear hittable primitives (that now have an indexed reference
     // to the appropriate vector index for the appropriate primitive type
@@ -91,6 +161,7 @@ pub fn build_from_hittable_primitives(
                 println!("Building construction scene from mesh");
                 // This is the offset to apply to the mesh_tris indices
                 let offset = scene.tri_verts.len() as u32;
+                let first_tri = scene.tris.len();
 
                 let (mut mesh_tri_verts, mesh_tris) = mesh.get_default_first_mesh();
 
@@ -98,24 +169,100 @@ pub fn build_from_hittable_primitives(
                 scene.tri_verts.append(&mut mesh_tri_verts);
 
                 // Append the mesh tri ints the scene tri indices
-                for tri in mesh_tris {
-                    scene.tris.push(Triangle::new(
-                        mesh.material_index,
-                        [
-                            tri.indices[0] + offset,
-                            tri.indices[1] + offset,
-                            tri.indices[2] + offset,
-                        ],
-                    ));
-                    scene.hittables.push(LinearHittable {
-                        geometry_type: 4,
-                        scene_index: (scene.tris.len() - 1) as u32,
-                    });
+                for mut tri in mesh_tris {
+                    tri.indices = [
+                        tri.indices[0] + offset,
+                        tri.indices[1] + offset,
+                        tri.indices[2] + offset,
+                    ];
+                    scene.tris.push(tri);
                 }
+                scene.meshes.push(MeshRange {
+                    tris: first_tri..scene.tris.len(),
+                    tri_verts: offset as usize..scene.tri_verts.len(),
+                    root: 0,
+                });
+
+                // The mesh is placed once where it is, the bvh root is set once the mesh bvh is built
+                scene.instances.push(MeshInstance::new(
+                    0,
+                    first_tri as u32,
+                    instance_triangles,
+                    Matrix4::identity(),
+                ));
+                instance_triangles += (scene.tris.len() - first_tri) as u32;
+                scene.hittables.push(LinearHittable {
+                    geometry_type: 5,
+                    scene_index: (scene.instances.len() - 1) as u32,
+                });
+            }
+            HittablePrimitive::MeshInstance(mesh_index, object_to_world) => {
+                let mesh = scene
+                    .meshes
+                    .get(*mesh_index)
+                    .expect("Mesh instance of a mesh that has not been added")
+                    .clone();
+                scene.instances.push(MeshInstance::new(
+                    0,
+                    mesh.tris.start as u32,
+                    instance_triangles,
+                    *object_to_world,
+                ));
+                instance_triangles += mesh.tris.len() as u32;
+                scene.hittables.push(LinearHittable {
+                    geometry_type: 5,
+                    scene_index: (scene.instances.len() - 1) as u32,
+                });
             }
         }
     }
 
+    // One top level hittable has been added for each primitive
+    let primitive_hittables = scene.hittables.clone();
+
+    // Reserve the top level ranges of the hittables and bvh nodes (upper bounds of the top level bvh size for the leaves)
+    let leaves = scene.hittables.len();
+    scene.top_level_capacity = 3 * leaves;
+    scene.top_level_node_capacity = 2 * leaves;
+    scene.hittables.resize(
+        scene.top_level_capacity,
+        LinearHittable {
+            geometry_type: 0,
+            scene_index: 0,
+        },
+    );
+    scene.bvh_nodes = vec![BvhNode::empty(); scene.top_level_node_capacity];
+
+    // Build the bvh of each mesh after the top level ranges, then point the instances at their mesh bvh
+    for mesh in 0..scene.meshes.len() {
+        let tris: Vec<LinearHittable> = scene.meshes[mesh]
+            .tris
+            .clone()
+            .map(|tri| LinearHittable {
+                geometry_type: 4,
+                scene_index: tri as u32,
+            })
+            .collect();
+        let root = scene.hittables.len();
+        let mesh_bvh = build_bvh(&mut scene, &tris, root);
+        scene.hittables.extend(mesh_bvh);
+        scene.meshes[mesh].root = root;
+    }
+    let roots: Vec<usize> = scene
+        .instances
+        .iter()
+        .map(|instance| {
+            scene
+                .mesh_of(instance.first_triangle as usize)
+                .unwrap()
+                .root
+        })
+        .collect();
+    for (instance, root) in scene.instances.iter_mut().zip(roots) {
+        instance.root = root as u32;
+    }
+
+    // Build the top level bvh
     recompute_bvh(&mut scene);
 
     // Finally, validate the scene and ensure that it has no empty arrays (otherwise throws error in the wgpu binding)
@@ -125,7 +272,7 @@ pub fn build_from_hittable_primitives(
     // scene.debug_print();
 
     // Return the constructed scene
-    scene
+    (scene, primitive_hittables)
 }
 
 /// Build a LinearSceneBvh from just materials and spheres
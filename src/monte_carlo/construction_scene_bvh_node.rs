@@ -75,13 +75,12 @@ impl SceneBvhConstructionNode {
         }
 
         let box_left = left.as_ref().unwrap().hittable.bounding_box(scene);
-        let box_surround: Aabb;
-        if right.is_some() {
-            let box_right = right.as_ref().unwrap().hittable.bounding_box(scene);
-            box_surround = surrounding_box(&box_left, &box_right);
+        let box_surround: Aabb = if let Some(right) = &right {
+            let box_right = right.hittable.bounding_box(scene);
+            surrounding_box(&box_left, &box_right)
         } else {
-            box_surround = box_left;
-        }
+            box_left
+        };
 
         // In the case where we are creating a new bvh node primitive, we need to push this value to a bvh storage vector
         scene.bvh_nodes.push(BvhNode::new(
@@ -116,7 +115,7 @@ impl SceneBvhConstructionNode {
             let current_ref = current.as_ref().unwrap();
 
             // Create a flattened hittable
-            let flat_hittable = current_ref.hittable.clone();
+            let flat_hittable = current_ref.hittable;
 
             if current_ref.left.is_some() {
                 // Add the left child to the bfs queue to process later
@@ -0,0 +1,197 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use super::result::NoiseStats;
+
+/// When to stop accumulating the result, the first criterion met stops it (None: not used)
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct StopCriterion {
+    /// Mean samples per pixel
+    pub target_spp: Option<f32>,
+    /// Wall clock time since the accumulation started
+    pub time_budget: Option<Duration>,
+    /// Mean relative standard error of the pixels
+    pub noise_threshold: Option<f32>,
+}
+
+/// Progress of the accumulation, updated after each full frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub passes: u32,
+    pub noise: NoiseStats,
+    pub elapsed: Duration,
+    /// Estimated time until the stop criterion is met (None: no criterion)
+    pub eta: Option<Duration>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} passes, {}, {:.1}s",
+            self.passes,
+            self.noise,
+            self.elapsed.as_secs_f32()
+        )?;
+        if let Some(eta) = self.eta {
+            write!(f, ", eta {:.1}s", eta.as_secs_f32())?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Tracks the accumulation of the result against the stop criterion.
+/// The noise is assumed to fall with 1/sqrt(spp) for the time estimates.
+///
+pub struct Convergence {
+    pub criterion: StopCriterion,
+    enabled: bool,
+    start: Instant,
+    stopped: bool,
+    progress: Option<Progress>,
+}
+
+impl Convergence {
+    pub fn new(criterion: StopCriterion) -> Self {
+        Convergence {
+            criterion,
+            enabled: true,
+            start: Instant::now(),
+            stopped: false,
+            progress: None,
+        }
+    }
+
+    /// Start timing a new accumulation (after the result has been reset)
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+        self.stopped = false;
+        self.progress = None;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        self.progress
+    }
+
+    /// Toggle the stop criterion (a stopped accumulation resumes when disabled), returns whether it is now enabled
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        if !self.enabled {
+            self.stopped = false;
+        }
+        self.enabled
+    }
+
+    /// Update the progress after a full frame, returns true when the stop criterion has just been met
+    pub fn update(&mut self, passes: u32, noise: NoiseStats, elapsed: Duration) -> bool {
+        let criterion = &self.criterion;
+        let mut etas = vec![];
+        let mut met = false;
+        if let Some(target_spp) = criterion.target_spp {
+            met |= noise.mean_samples >= target_spp;
+            etas.push(scale_duration(
+                elapsed,
+                target_spp / noise.mean_samples.max(f32::EPSILON) - 1.0,
+            ));
+        }
+        if let Some(time_budget) = criterion.time_budget {
+            met |= elapsed >= time_budget;
+            etas.push(time_budget.saturating_sub(elapsed));
+        }
+        if let Some(noise_threshold) = criterion.noise_threshold {
+            met |= noise.mean_relative_error <= noise_threshold;
+            etas.push(scale_duration(
+                elapsed,
+                (noise.mean_relative_error / noise_threshold).powi(2) - 1.0,
+            ));
+        }
+        self.progress = Some(Progress {
+            passes,
+            noise,
+            elapsed,
+            eta: etas.into_iter().min(),
+        });
+
+        let just_stopped = self.enabled && met && !self.stopped;
+        self.stopped |= just_stopped;
+        just_stopped
+    }
+
+    /// Window title with the progress
+    pub fn title(&self) -> String {
+        match (&self.progress, self.stopped) {
+            (None, _) => "monte carlo".to_string(),
+            (Some(progress), false) => format!("monte carlo - {}", progress),
+            (Some(progress), true) => format!("monte carlo - {} (converged)", progress),
+        }
+    }
+}
+
+/// Duration scaled by a factor (non negative), saturating at the maximum duration (e.g. for infinite or very large factors)
+fn scale_duration(duration: Duration, factor: f32) -> Duration {
+    Duration::try_from_secs_f32(duration.as_secs_f32() * factor.max(0.0)).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_criterion() {
+        let noise = |mean_samples, mean_relative_error| NoiseStats {
+            mean_samples,
+            mean_relative_error,
+            converged_fraction: 0.0,
+        };
+        let mut convergence = Convergence::new(StopCriterion {
+            target_spp: Some(64.0),
+            time_budget: Some(Duration::from_secs(100)),
+            noise_threshold: Some(0.01),
+        });
+
+        // Noise halves from 16 to 64 spp, so 4x the time to go from 0.04 to 0.01 (the spp target is closer)
+        assert!(!convergence.update(16, noise(16.0, 0.04), Duration::from_secs(10)));
+        let eta = convergence.progress().unwrap().eta.unwrap();
+        assert!((eta.as_secs_f32() - 30.0).abs() < 1.0e-3);
+        assert!(!convergence.is_stopped());
+
+        // Stops once (and reports it once)
+        assert!(convergence.update(64, noise(64.0, 0.02), Duration::from_secs(40)));
+        assert!(convergence.is_stopped());
+        assert!(!convergence.update(64, noise(64.0, 0.02), Duration::from_secs(41)));
+
+        // Resumes when disabled, and when restarted
+        convergence.toggle();
+        assert!(!convergence.is_stopped());
+        convergence.toggle();
+        assert!(convergence.update(64, noise(64.0, 0.02), Duration::from_secs(42)));
+        convergence.restart();
+        assert!(!convergence.is_stopped());
+        assert!(convergence.progress().is_none());
+
+        // Far from the target the eta saturates rather than overflowing
+        assert_eq!(
+            scale_duration(Duration::from_secs(10), 1.0e30),
+            Duration::MAX
+        );
+        assert_eq!(
+            scale_duration(Duration::from_secs(10), f32::INFINITY),
+            Duration::MAX
+        );
+        assert_eq!(
+            scale_duration(Duration::from_secs(10), -1.0),
+            Duration::ZERO
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use cgmath::{
-    EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, SquareMatrix, Vector3, Vector4,
+    EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Vector3, Vector4,
 };
 
-use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh};
+use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh, ray::Ray, util};
 
 /// POD Rectangle ready to ship to GPU
 #[repr(C)]
@@ -113,4 +113,113 @@ impl Cuboid {
             Point3::from_vec(pos_wrld + max.to_vec()),
         )
     }
+
+    /// Signed distance from a point (in world space) to the surface of the cuboid (negative inside)
+    /// Attribution: https://iquilezles.org/articles/distfunctions/
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        // Point in the object space of the unit cuboid
+        let p = (Matrix4::from(self.txx) * point.to_homogeneous()).truncate();
+
+        // Scale the object space distances back to world space
+        let q = Vector3::new(
+            (p.x.abs() - 1.0) * Vector4::from(self.txi[0]).truncate().magnitude(),
+            (p.y.abs() - 1.0) * Vector4::from(self.txi[1]).truncate().magnitude(),
+            (p.z.abs() - 1.0) * Vector4::from(self.txi[2]).truncate().magnitude(),
+        );
+        Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).magnitude()
+            + q.x.max(q.y.max(q.z)).min(0.0)
+    }
+
+    /// Closest ray hit distance within the range and the outward normal, including rays starting inside the cuboid (CPU version of `cuboid_hit` in the shader)
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vector3<f32>)> {
+        // Convert from world to box space (the ray parameter t is unchanged)
+        let txx = Matrix4::from(self.txx);
+        let ro = (txx * ray.origin.to_homogeneous()).truncate();
+        let rd = (txx * ray.direction.extend(0.0)).truncate();
+
+        // Ray-box (slab) intersection in box space, tracking the axis of the entry and exit faces
+        let (mut t_near, mut near_axis) = (f32::NEG_INFINITY, 0);
+        let (mut t_far, mut far_axis) = (f32::INFINITY, 0);
+        for a in 0..3 {
+            if rd[a] == 0.0 {
+                if ro[a].abs() > 1.0 {
+                    return None;
+                }
+                continue;
+            }
+            let t_1 = (-1.0 - ro[a]) / rd[a];
+            let t_2 = (1.0 - ro[a]) / rd[a];
+            if t_1.min(t_2) > t_near {
+                t_near = t_1.min(t_2);
+                near_axis = a;
+            }
+            if t_1.max(t_2) < t_far {
+                t_far = t_1.max(t_2);
+                far_axis = a;
+            }
+        }
+        if t_near > t_far {
+            return None;
+        }
+
+        let (t, axis) = if t_near >= t_min && t_near <= t_max {
+            // Ray originates from outside cuboid
+            (t_near, near_axis)
+        } else if t_far >= t_min && t_far <= t_max {
+            // Ray originates from inside cuboid
+            (t_far, far_axis)
+        } else {
+            return None;
+        };
+
+        let mut local_normal = Vector3::new(0.0, 0.0, 0.0);
+        local_normal[axis] = (ro[axis] + rd[axis] * t).signum();
+        Some((t, self.face_normal_from_local(local_normal)))
+    }
+
+    /// Face of the unit cuboid closest to the point. 0: +x, 1: -x, 2: +y, 3: -y, 4: +z, 5: -z
+    pub fn face_at(&self, point: Point3<f32>) -> u32 {
+        let p = (Matrix4::from(self.txx) * point.to_homogeneous()).truncate();
+        let axis = if p.x.abs() >= p.y.abs() && p.x.abs() >= p.z.abs() {
+            0
+        } else if p.y.abs() >= p.z.abs() {
+            1
+        } else {
+            2
+        };
+        2 * axis + (p[axis as usize] < 0.0) as u32
+    }
+
+    /// World space area of a face (0: +x, 1: -x, 2: +y, 3: -y, 4: +z, 5: -z)
+    pub fn face_area(&self, face: u32) -> f32 {
+        let axis = (face / 2) as usize;
+        let u = Vector4::from(self.txi[(axis + 1) % 3]).truncate();
+        let v = Vector4::from(self.txi[(axis + 2) % 3]).truncate();
+        4.0 * u.cross(v).magnitude()
+    }
+
+    /// Uniformly distributed random point on a face (0: +x, 1: -x, 2: +y, 3: -y, 4: +z, 5: -z) and its outward normal
+    pub fn random_face_point(&self, face: u32) -> (Point3<f32>, Vector3<f32>) {
+        let axis = (face / 2) as usize;
+        let sign = if face.is_multiple_of(2) { 1.0 } else { -1.0 };
+
+        let mut local_point = Vector3::new(0.0, 0.0, 0.0);
+        local_point[axis] = sign;
+        local_point[(axis + 1) % 3] = 2.0 * util::random_float() - 1.0;
+        local_point[(axis + 2) % 3] = 2.0 * util::random_float() - 1.0;
+        let mut local_normal = Vector3::new(0.0, 0.0, 0.0);
+        local_normal[axis] = sign;
+
+        (
+            Point3::from_homogeneous(Matrix4::from(self.txi) * local_point.extend(1.0)),
+            self.face_normal_from_local(local_normal),
+        )
+    }
+
+    /// World space unit normal from an object space normal (using the inverse transpose of the object to world transform)
+    fn face_normal_from_local(&self, local_normal: Vector3<f32>) -> Vector3<f32> {
+        let txx = Matrix4::from(self.txx);
+        let linear = Matrix3::from_cols(txx.x.truncate(), txx.y.truncate(), txx.z.truncate());
+        (linear.transpose() * local_normal).normalize()
+    }
 }
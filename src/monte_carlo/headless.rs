@@ -0,0 +1,199 @@
+use super::{
+    animation::Animation, camera, camera_bookmarks, compute_renderer, constants,
+    interactive_section, result, uniforms_bindings, window, BvhRaytracing,
+};
+
+/// Scene, bindings and compute pipeline of a render without a window (compute passes only, no render target)
+struct Headless {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    size: winit::dpi::PhysicalSize<u32>,
+    constants: constants::Constants,
+    uniforms_bindings: uniforms_bindings::UniformsBindings,
+    camera: camera::Camera,
+    scene_bvh: super::LinearSceneBvh,
+    scene_bvh_bind_group: wgpu::BindGroup,
+    interactive_sections: interactive_section::InteractiveSections,
+    result: result::Result,
+    compute_renderer: compute_renderer::ComputeRenderer,
+}
+
+impl Headless {
+    async fn new(width: u32, height: u32) -> Self {
+        let size = winit::dpi::PhysicalSize::new(width, height);
+
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .expect("Unable to find an adapter");
+        let (device, queue) = BvhRaytracing::request_device(&adapter).await;
+
+        // Same bindings as the windowed renderer
+        let constants = constants::Constants::new();
+        let window = window::Window::new(&size);
+        let uniforms_bindings =
+            uniforms_bindings::UniformsBindings::new(&device, &[constants], &[window]);
+        let camera = BvhRaytracing::create_camera(
+            &device,
+            window,
+            &camera_bookmarks::CameraBookmarks::load(camera_bookmarks::BOOKMARKS_PATH),
+        );
+        let (scene_bvh, scene_bvh_bind_group_layout, scene_bvh_bind_group, interactive_sections) =
+            BvhRaytracing::create_scene(&device, &queue);
+        let result = result::Result::new(&device, &queue, window);
+
+        let shader = device.create_shader_module(&wgpu::include_wgsl!("../monte_carlo.wgsl"));
+        let compute_renderer = compute_renderer::ComputeRenderer::new(
+            &device,
+            &shader,
+            &[
+                uniforms_bindings.get_bind_group_layout(),
+                camera.get_bind_group_layout(),
+                &scene_bvh_bind_group_layout,
+                result.get_bind_group_layout(),
+            ],
+        );
+
+        Headless {
+            device,
+            queue,
+            size,
+            constants,
+            uniforms_bindings,
+            camera,
+            scene_bvh,
+            scene_bvh_bind_group,
+            interactive_sections,
+            result,
+            compute_renderer,
+        }
+    }
+
+    /// Accumulate a full frame, each pass is submitted (and waited for) separately to keep the GPU responsive
+    fn render_frame(&mut self) {
+        let slices = self.constants.vertical_render_slices();
+        for _ in 0..slices {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            self.compute_renderer.encode_pass(
+                &mut encoder,
+                &[
+                    self.uniforms_bindings.get_bind_group(),
+                    self.camera.get_bind_group(),
+                    &self.scene_bvh_bind_group,
+                    self.result.get_bind_group(),
+                ],
+                self.size,
+                slices,
+            );
+            self.queue.submit(std::iter::once(encoder.finish()));
+            self.device.poll(wgpu::Maintain::Wait);
+            self.result.increment_pass_index(&self.queue);
+        }
+    }
+
+    fn noise(&self) -> result::NoiseStats {
+        self.result.read_noise_stats(
+            &self.device,
+            &self.queue,
+            self.constants.adaptive_target_error(),
+        )
+    }
+}
+
+/// Render a number of full frames of the scene without a window and save the image in the working directory
+pub async fn render_headless(width: u32, height: u32, frames: u32) -> String {
+    let mut headless = Headless::new(width, height).await;
+    for frame in 0..frames {
+        headless.render_frame();
+        println!("Frame {}/{}: {}", frame + 1, frames, headless.noise());
+    }
+
+    headless.result.write_texture_to_working_dir(
+        &headless.device,
+        &headless.queue,
+        &headless.constants.colormap_display(),
+        &headless.constants.tone_mapping(),
+    )
+}
+
+/// Render each frame of the animation to its spp without a window, saving a numbered image sequence (frame_0000.png, ...) in a new directory in the working directory
+/// Fails before rendering anything if the animation doesn't fit the scene (e.g. a section keyframe of a section that doesn't exist)
+pub async fn render_sequence(
+    width: u32,
+    height: u32,
+    animation: &Animation,
+) -> Result<String, String> {
+    let mut headless = Headless::new(width, height).await;
+    animation.validate_sections(headless.interactive_sections.count())?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let directory = format!("monte_carlo_sequence_{}", timestamp);
+    std::fs::create_dir_all(&directory).expect("Unable to create the image sequence directory");
+    let full_frames = animation
+        .spp
+        .div_ceil(headless.constants.pass_samples_per_pixel())
+        .max(1);
+
+    for frame in 0..animation.frames {
+        // Move the camera and sections to the frame, restarting the accumulation
+        if let Some(state) = animation.camera_at(frame as f32) {
+            headless.camera.set_state(
+                &headless.device,
+                &headless.queue,
+                &mut headless.result,
+                state,
+            );
+        }
+        for (section, offset) in animation.section_offsets_at(frame as f32) {
+            headless.interactive_sections.set_offset(
+                &headless.queue,
+                &mut headless.scene_bvh,
+                section,
+                offset,
+            );
+        }
+        headless
+            .result
+            .reset_texture(&headless.device, &headless.queue);
+
+        for _ in 0..full_frames {
+            headless.render_frame();
+        }
+
+        let path = format!("{}/frame_{:04}.png", directory, frame);
+        headless.result.write_texture(
+            &headless.device,
+            &headless.queue,
+            &headless.constants.colormap_display(),
+            &headless.constants.tone_mapping(),
+            &path,
+        );
+        println!(
+            "Frame {}/{}: {} ({})",
+            frame + 1,
+            animation.frames,
+            path,
+            headless.noise()
+        );
+    }
+    Ok(directory)
+}
+
+/// Render a turntable of the startup camera (bookmark 0) around its target as an image sequence
+pub async fn render_turntable(width: u32, height: u32, frames: u32, spp: u32) -> String {
+    let bookmarks = camera_bookmarks::CameraBookmarks::load(camera_bookmarks::BOOKMARKS_PATH);
+    let animation = Animation::turntable(BvhRaytracing::startup_camera(&bookmarks), frames, spp);
+    render_sequence(width, height, &animation)
+        .await
+        .expect("A turntable has no section keyframes")
+}
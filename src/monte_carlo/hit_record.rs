@@ -0,0 +1,20 @@
+use cgmath::{Point3, Vector3};
+
+use super::linear_hittable::LinearHittable;
+
+/// Closest ray hit found by the CPU scene queries (CPU version of `HitRecord` in the shader)
+#[derive(Copy, Clone, Debug)]
+pub struct HitRecord {
+    /// Distance along the ray (in units of the ray direction length)
+    pub t: f32,
+    /// Hit position in world space
+    pub p: Point3<f32>,
+    /// Unit outward surface normal (not flipped to face the ray)
+    pub normal: Vector3<f32>,
+    /// Hit primitive
+    pub hittable: LinearHittable,
+    /// Mesh instance a triangle was hit through (None for the other primitives)
+    pub instance: Option<usize>,
+    /// Index of the material of the hit primitive
+    pub material_index: u32,
+}
@@ -1,3 +1,5 @@
+use cgmath::Matrix4;
+
 use super::{constant_medium::ConstantMedium, cuboid::Cuboid, mesh::Mesh, sphere::Sphere};
 
 pub enum HittablePrimitive {
@@ -5,4 +7,6 @@ pub enum HittablePrimitive {
     Cuboid(Cuboid),
     ConstantMedium(ConstantMedium),
     Mesh(Mesh),
+    /// Another placement of a mesh added earlier (the index of the mesh in the order the meshes were added), sharing its triangles and bvh
+    MeshInstance(usize, Matrix4<f32>),
 }
@@ -1,34 +1,410 @@
-use cgmath::{Matrix4, Vector2, Vector3};
+use cgmath::{Deg, InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector2, Vector3};
 
 use super::{linear_hittable::LinearHittable, linear_scene_bvh::LinearSceneBvh, result};
 
+/// Geometry of an interactive section (both are cuboids using the wos albedo blend material)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Thin cuboid for sampling the solution over a plane
+    Plane,
+    /// Thin rod cuboid for sampling the solution along a line
+    Line,
+}
+
+/// How mouse drags transform the target (along or about the current axis)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Manipulation {
+    Translate,
+    Rotate,
+    /// Scale along the primitive's own axis closest to the axis, so cuboids stay boxes (spheres scale uniformly)
+    Scale,
+    /// Scale uniformly (the axis is not used)
+    ScaleUniform,
+}
+
+impl Manipulation {
+    /// Next manipulation (cycled with the 'R' key)
+    fn next(self) -> Self {
+        match self {
+            Manipulation::Translate => Manipulation::Rotate,
+            Manipulation::Rotate => Manipulation::Scale,
+            Manipulation::Scale => Manipulation::ScaleUniform,
+            Manipulation::ScaleUniform => Manipulation::Translate,
+        }
+    }
+
+    /// Drag increment when snapping (world units, degrees and natural log of the scale factor)
+    fn snap_increment(self) -> f32 {
+        match self {
+            Manipulation::Translate => 0.01,
+            Manipulation::Rotate => 15.0,
+            Manipulation::Scale | Manipulation::ScaleUniform => 0.1,
+        }
+    }
+
+    /// World space transform of a drag amount (about the origin, along or about the unit axis)
+    fn transform(self, axis: Vector3<f32>, amount: f32) -> Matrix4<f32> {
+        match self {
+            Manipulation::Translate => Matrix4::from_translation(axis * amount),
+            Manipulation::Rotate => Matrix4::from_axis_angle(axis, Deg(amount)),
+            Manipulation::Scale => {
+                // Directional scale I + (s - 1) a a^T
+                let outer = Matrix3::from_cols(axis * axis.x, axis * axis.y, axis * axis.z);
+                Matrix4::from(Matrix3::identity() + outer * (amount.exp() - 1.0))
+            }
+            Manipulation::ScaleUniform => Matrix4::from_scale(amount.exp()),
+        }
+    }
+}
+
+/// Named section (probe plane or line) that can be moved and toggled interactively
 pub struct InteractiveSection {
+    name: String,
+    kind: SectionKind,
     hittable: LinearHittable,
+    enabled: bool,
+    /// World space translation from the initial position
+    offset: Vector3<f32>,
 }
 
 impl InteractiveSection {
-    pub fn new(hittable: LinearHittable) -> Self {
-        InteractiveSection { hittable }
+    pub fn new(name: &str, kind: SectionKind, hittable: LinearHittable, enabled: bool) -> Self {
+        InteractiveSection {
+            name: name.to_string(),
+            kind,
+            hittable,
+            enabled,
+            offset: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Move the section by a world space transform (applied about the section's own origin)
+    fn transform_world_by(
+        &mut self,
+        queue: &wgpu::Queue,
+        scene: &mut LinearSceneBvh,
+        world_transform: Matrix4<f32>,
+    ) {
+        transform_hittable_world_by(queue, scene, &self.hittable, world_transform);
+    }
+}
+
+/// Move a hittable by a world space transform (applied about the hittable's own origin)
+fn transform_hittable_world_by(
+    queue: &wgpu::Queue,
+    scene: &mut LinearSceneBvh,
+    hittable: &LinearHittable,
+    world_transform: Matrix4<f32>,
+) {
+    let transform = object_space_transform(scene, hittable, world_transform);
+    scene.transform_hittable_by(queue, hittable, transform);
+}
+
+/// Convert a world space transform (applied about the hittable's own origin) into the object space of the hittable
+fn object_space_transform(
+    scene: &LinearSceneBvh,
+    hittable: &LinearHittable,
+    world_transform: Matrix4<f32>,
+) -> Matrix4<f32> {
+    let object_to_world = hittable.object_to_world(scene);
+    let translation = Matrix4::from_translation(object_to_world.w.truncate());
+    object_to_world.invert().unwrap()
+        * translation
+        * world_transform
+        * translation.invert().unwrap()
+        * object_to_world
+}
+
+/// Axis of the hittable's own frame closest to the world space axis (scaling along it keeps the frame orthogonal, a world axis
+/// would shear a rotated cuboid, which its signed distance doesn't support)
+fn closest_object_axis(
+    scene: &LinearSceneBvh,
+    hittable: &LinearHittable,
+    axis: Vector3<f32>,
+) -> Vector3<f32> {
+    let object_to_world = hittable.object_to_world(scene);
+    [object_to_world.x, object_to_world.y, object_to_world.z]
+        .iter()
+        .map(|column| column.truncate().normalize())
+        .max_by(|a, b| a.dot(axis).abs().total_cmp(&b.dot(axis).abs()))
+        .unwrap()
+}
+
+/// Collection of the interactive sections in the scene, with the currently active section and manipulation settings
+pub struct InteractiveSections {
+    sections: Vec<InteractiveSection>,
+    /// Index of the section manipulated by mouse drags
+    active: usize,
+    /// Picked primitive (that isn't a section) manipulated by mouse drags instead of the active section
+    picked: Option<LinearHittable>,
+    /// World space axis to translate along, rotate about or scale along
+    axis: Vector3<f32>,
+    /// Transform applied by mouse drags
+    manipulation: Manipulation,
+    /// Apply mouse drags in whole snap increments
+    snap: bool,
+    /// Drag amount not yet applied when snapping
+    snap_residual: f32,
+    /// World space distance moved per pixel of mouse drag
+    translate_speed: f32,
+    /// Degrees rotated per pixel of mouse drag
+    rotate_speed: f32,
+    /// Natural log of the scale factor per pixel of mouse drag
+    scale_speed: f32,
+}
+
+impl InteractiveSections {
+    pub fn new(
+        sections: Vec<InteractiveSection>,
+        translate_speed: f32,
+        rotate_speed: f32,
+        scale_speed: f32,
+    ) -> Self {
+        InteractiveSections {
+            sections,
+            active: 0,
+            picked: None,
+            axis: Vector3::unit_z(),
+            manipulation: Manipulation::Translate,
+            snap: false,
+            snap_residual: 0.0,
+            translate_speed,
+            rotate_speed,
+            scale_speed,
+        }
+    }
+
+    /// Apply the enabled state of each section to the scene (sections are expected to be in the scene when its buffers are created)
+    pub fn apply_enabled(&self, queue: &wgpu::Queue, scene: &mut LinearSceneBvh) {
+        for section in self.sections.iter().filter(|s| !s.enabled) {
+            scene.set_hittable_enabled(queue, &section.hittable, false);
+        }
+    }
+
+    /// Number of sections
+    pub fn count(&self) -> usize {
+        self.sections.len()
+    }
+
+    /// Move a section to a world space offset from its initial position (e.g. keyframed sweeps)
+    pub fn set_offset(
+        &mut self,
+        queue: &wgpu::Queue,
+        scene: &mut LinearSceneBvh,
+        index: usize,
+        offset: Vector3<f32>,
+    ) {
+        let section = self
+            .sections
+            .get_mut(index)
+            .expect("Interactive section index out of range");
+        let translation = Matrix4::from_translation(offset - section.offset);
+        section.transform_world_by(queue, scene, translation);
+        section.offset = offset;
+    }
+
+    /// Make a picked primitive the target of mouse drags (a picked section becomes the active section, None returns to the active section)
+    /// Picked mesh triangles select their mesh instance
+    pub fn select(&mut self, hittable: Option<LinearHittable>) {
+        self.picked = None;
+        self.snap_residual = 0.0;
+        if let Some(hittable) = hittable {
+            if let Some(index) = self.sections.iter().position(|s| s.hittable == hittable) {
+                self.active = index;
+            } else {
+                self.picked = Some(hittable);
+            }
+        }
+        self.print_legend();
+    }
+
+    /// Select the next section as the active one
+    pub fn cycle_active(&mut self) {
+        self.picked = None;
+        self.snap_residual = 0.0;
+        if !self.sections.is_empty() {
+            self.active = (self.active + 1) % self.sections.len();
+        }
+        self.print_legend();
     }
 
-    pub fn translate(
+    /// Show or hide the active section
+    pub fn toggle_active(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &mut LinearSceneBvh,
+        result: &mut result::Result,
+    ) {
+        if let Some(section) = self.sections.get_mut(self.active) {
+            section.enabled = !section.enabled;
+            scene.set_hittable_enabled(queue, &section.hittable, section.enabled);
+
+            // Reset the accumulation ray color result texture
+            result.reset_texture(device, queue);
+        }
+        self.print_legend();
+    }
+
+    /// Set the world space axis used for translation, rotation and scaling
+    pub fn set_axis(&mut self, axis: Vector3<f32>) {
+        self.axis = axis;
+        self.snap_residual = 0.0;
+        self.print_legend();
+    }
+
+    /// Cycle the transform applied by mouse drags (translate, rotate, scale along the axis and scale uniformly)
+    pub fn cycle_manipulation(&mut self) {
+        self.manipulation = self.manipulation.next();
+        self.snap_residual = 0.0;
+        self.print_legend();
+    }
+
+    /// Toggle snapping mouse drags to whole increments
+    pub fn toggle_snap(&mut self) {
+        self.snap = !self.snap;
+        self.snap_residual = 0.0;
+        self.print_legend();
+    }
+
+    /// Transform the picked primitive (or the active section) along/about the current axis from the vertical mouse movement
+    pub fn drag(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         scene: &mut LinearSceneBvh,
         result: &mut result::Result,
-        size: winit::dpi::PhysicalSize<u32>,
         mouse_prev: Vector2<f32>,
         mouse_cur: Vector2<f32>,
     ) {
+        let delta = mouse_cur.y - mouse_prev.y;
+        let speed = match self.manipulation {
+            Manipulation::Translate => self.translate_speed,
+            Manipulation::Rotate => self.rotate_speed,
+            Manipulation::Scale | Manipulation::ScaleUniform => self.scale_speed,
+        };
+        let mut amount = delta * speed;
+        if self.snap {
+            // Apply whole increments, keeping the rest for the next drag
+            let increment = self.manipulation.snap_increment();
+            self.snap_residual += amount;
+            amount = (self.snap_residual / increment).trunc() * increment;
+            self.snap_residual -= amount;
+        }
+        if amount == 0.0 {
+            return;
+        }
+        let target = match (self.picked, self.sections.get(self.active)) {
+            (Some(picked), _) => picked,
+            (None, Some(section)) if section.enabled => section.hittable,
+            _ => return,
+        };
+        let transform = self.drag_transform(scene, &target, amount);
+
         // Update the scene
-        scene.transform_hittable_by(
-            queue,
-            &self.hittable,
-            Matrix4::from_translation(Vector3::new(0.0, 0.0, (mouse_cur.y - mouse_prev.y) * 0.1)),
-        );
+        transform_hittable_world_by(queue, scene, &target, transform);
+        if self.picked.is_none() && self.manipulation == Manipulation::Translate {
+            self.sections[self.active].offset += self.axis * amount;
+        }
 
         // Reset the accumulation ray color result texture
-        result.reset_texture(device, queue, size);
+        result.reset_texture(device, queue);
+    }
+
+    /// World space transform of a drag amount on the hittable (scaling is along the hittable's own axis closest to the current axis)
+    fn drag_transform(
+        &self,
+        scene: &LinearSceneBvh,
+        hittable: &LinearHittable,
+        amount: f32,
+    ) -> Matrix4<f32> {
+        let axis = if self.manipulation == Manipulation::Scale {
+            closest_object_axis(scene, hittable, self.axis)
+        } else {
+            self.axis
+        };
+        self.manipulation.transform(axis, amount)
+    }
+
+    /// Print the sections and the current manipulation settings
+    pub fn print_legend(&self) {
+        println!(
+            "Interactive sections ({:?} along {:?}{}):",
+            self.manipulation,
+            self.axis,
+            if self.snap {
+                format!(", snap {}", self.manipulation.snap_increment())
+            } else {
+                String::new()
+            }
+        );
+        for (i, section) in self.sections.iter().enumerate() {
+            println!(
+                " {} {} ({:?}) {}",
+                if i == self.active && self.picked.is_none() {
+                    ">"
+                } else {
+                    " "
+                },
+                section.name,
+                section.kind,
+                if section.enabled { "on" } else { "off" }
+            );
+        }
+        if let Some(picked) = self.picked {
+            println!(
+                " > picked {} {}",
+                picked.geometry_name(),
+                picked.scene_index
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monte_carlo::{
+        construction_scene, cuboid::Cuboid, hittable_primitive::HittablePrimitive,
+        material::Material,
+    };
+    use cgmath::{Matrix, Point3};
+
+    #[test]
+    fn test_scale_rotated_cuboid() {
+        // Cuboid rotated 30 degrees about z, scaling along the world x axis scales it along its own x axis (no shear)
+        let object_to_world = Matrix4::from_angle_z(Deg(30.0)) * Matrix4::from_scale(0.5);
+        let mut scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(0.5, 0.5, 0.5))],
+            &[HittablePrimitive::Cuboid(Cuboid::new(object_to_world, 0))],
+        );
+        let hittable = LinearHittable {
+            geometry_type: 2,
+            scene_index: 0,
+        };
+        let mut sections = InteractiveSections::new(vec![], 0.0, 0.0, 0.0);
+        sections.manipulation = Manipulation::Scale;
+        sections.axis = Vector3::unit_x();
+
+        let world_transform = sections.drag_transform(&scene, &hittable, 4.0f32.ln());
+        let transform = object_space_transform(&scene, &hittable, world_transform);
+        hittable.transform_by(&mut scene, transform);
+
+        let txi = Matrix4::from(scene.cuboids[0].txi);
+        let columns = Matrix3::from_cols(txi.x.truncate(), txi.y.truncate(), txi.z.truncate());
+        let gram = columns.transpose() * columns;
+        assert!(gram.x.y.abs() < 1.0e-5 && gram.x.z.abs() < 1.0e-5 && gram.y.z.abs() < 1.0e-5);
+
+        // Half extents of 2 along its own x axis and 0.5 across, so the signed distance is exact
+        let dir = (Matrix4::from_angle_z(Deg(30.0)) * Vector3::unit_x().extend(0.0)).truncate();
+        let normal = (Matrix4::from_angle_z(Deg(30.0)) * Vector3::unit_y().extend(0.0)).truncate();
+        let cuboid = &scene.cuboids[0];
+        assert!(
+            (cuboid.signed_distance(Point3::new(0.0, 0.0, 0.0) + dir * 3.0) - 1.0).abs() < 1.0e-4
+        );
+        assert!(
+            (cuboid.signed_distance(Point3::new(0.0, 0.0, 0.0) + normal * 1.5) - 1.0).abs()
+                < 1.0e-4
+        );
     }
 }
@@ -1,15 +1,15 @@
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Point3, SquareMatrix};
 
-use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh};
+use super::{aabb::Aabb, hit_record::HitRecord, linear_scene_bvh::LinearSceneBvh, ray::Ray};
 
 ///
 /// Experimental data structure to hold all bvh compatible data for a single hittable geometry to compose into the bvh tree
 /// This is the linearized form, expected to be part of the linear scene bvh
 ///
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LinearHittable {
-    /// 0: BvhNode, 1: Sphere, 2: Cuboid, 3: ConstantMedium, 4: Triangle
+    /// 0: BvhNode, 1: Sphere, 2: Cuboid, 3: ConstantMedium, 4: Triangle, 5: MeshInstance
     pub geometry_type: u32,
     /// Given the geometry type, the actual data is stored at the following index in the linear_scene_bvh vector (for the appropriate type).
     pub scene_index: u32,
@@ -20,6 +20,19 @@ impl LinearHittable {
         self.scene_index as usize
     }
 
+    /// Name of the geometry type (for printing)
+    pub fn geometry_name(&self) -> &'static str {
+        match self.geometry_type {
+            0 => "bvh node",
+            1 => "sphere",
+            2 => "cuboid",
+            3 => "constant medium",
+            4 => "triangle",
+            5 => "mesh instance",
+            _ => "unknown",
+        }
+    }
+
     /// Find the bounding box, looking up the underlying data from the scene
     pub fn bounding_box(&self, scene: &LinearSceneBvh) -> Aabb {
         // This will change to requiring a reference to the scenes current state so we can read from the linearized scene array.
@@ -34,26 +47,173 @@ impl LinearHittable {
             3 => scene.constant_mediums[self.get_scene_index()].bounding_box(scene),
             // Mesh Triangle
             4 => scene.tris[self.get_scene_index()].bounding_box(scene),
+            // MeshInstance
+            5 => scene.instances[self.get_scene_index()].bounding_box(scene),
             _ => panic!("Unsupported geometry type"),
         }
     }
 
-    /// Used by the interactive tooling
+    /// Object to world transform of the hittable (used by the interactive tooling)
+    /// Mesh instances use world axes at the centre of their bounding box
+    pub fn object_to_world(&self, scene: &LinearSceneBvh) -> Matrix4<f32> {
+        match self.geometry_type {
+            // Sphere
+            1 => Matrix4::from_translation(scene.spheres[self.get_scene_index()].center.into()),
+            // Cuboid
+            2 => scene.cuboids[self.get_scene_index()].txi.into(),
+            // ConstantMedium (the frame of its boundary)
+            3 => self.constant_medium_boundary(scene).object_to_world(scene),
+            // MeshInstance
+            5 => {
+                let bounding_box = self.bounding_box(scene);
+                let (min, max) = (
+                    Point3::from(*bounding_box.min()),
+                    Point3::from(*bounding_box.max()),
+                );
+                Matrix4::from_translation((min + (max - min) * 0.5) - Point3::new(0.0, 0.0, 0.0))
+            }
+            // Everything else not supported
+            _ => panic!("Unsupported geometry type"),
+        }
+    }
+
+    /// Used by the interactive tooling, the transform is in the object space of `object_to_world`
+    /// Spheres stay spheres (scaled by the cube root of the transform determinant) and mesh instances only change their placement
     pub fn transform_by(&self, scene: &mut LinearSceneBvh, transform: Matrix4<f32>) {
         match self.geometry_type {
             // Sphere
             1 => {
-                scene.spheres[self.get_scene_index()].center =
-                    (Matrix4::from_translation(scene.spheres[self.get_scene_index()].center.into())
-                        * transform)
-                        .w
-                        .truncate()
-                        .into()
+                let sphere = &mut scene.spheres[self.get_scene_index()];
+                sphere.center = (Matrix4::from_translation(sphere.center.into()) * transform)
+                    .w
+                    .truncate()
+                    .into();
+                sphere.radius *= transform.determinant().abs().cbrt();
             }
             // Cuboid
             2 => scene.cuboids[self.get_scene_index()].transform_txi_by(transform),
+            // ConstantMedium
+            3 => self
+                .constant_medium_boundary(scene)
+                .transform_by(scene, transform),
+            // MeshInstance (the mesh triangles and bvh are shared with the other instances so are untouched)
+            5 => {
+                let object_to_world = self.object_to_world(scene);
+                let world_transform =
+                    object_to_world * transform * object_to_world.invert().unwrap();
+                let instance = &mut scene.instances[self.get_scene_index()];
+                instance.set_object_to_world(world_transform * instance.object_to_world());
+            }
             // Everything else not supported
             _ => panic!("Unsupported geometry type"),
         }
     }
+
+    /// Hittables whose bounding box changes when this hittable is transformed
+    /// (a boundary primitive and the constant mediums it bounds, or just the mesh instance)
+    pub fn transformed_hittables(&self, scene: &LinearSceneBvh) -> Vec<LinearHittable> {
+        match self.geometry_type {
+            1..=3 => {
+                let boundary = if self.geometry_type == 3 {
+                    self.constant_medium_boundary(scene)
+                } else {
+                    *self
+                };
+                let mediums = (0..scene.constant_mediums.len() as u32)
+                    .map(|scene_index| LinearHittable {
+                        geometry_type: 3,
+                        scene_index,
+                    })
+                    .filter(|medium| medium.constant_medium_boundary(scene) == boundary);
+                std::iter::once(boundary).chain(mediums).collect()
+            }
+            5 => vec![*self],
+            _ => panic!("Unsupported geometry type"),
+        }
+    }
+
+    /// Boundary hittable of a constant medium
+    fn constant_medium_boundary(&self, scene: &LinearSceneBvh) -> LinearHittable {
+        let constant_medium = &scene.constant_mediums[self.get_scene_index()];
+        LinearHittable {
+            geometry_type: constant_medium.boundary_geometry_type,
+            scene_index: constant_medium.boundary_scene_index,
+        }
+    }
+
+    /// Ray hit of a primitive hittable within the range (CPU version of `primitive_hit` in the shader)
+    /// Returns None for non-primitive hittables (constant mediums are not surfaces) or when the ray misses
+    pub fn primitive_hit(
+        &self,
+        scene: &LinearSceneBvh,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        let ((t, normal), material_index) = match self.geometry_type {
+            // Sphere
+            1 => {
+                let sphere = &scene.spheres[self.get_scene_index()];
+                (sphere.hit(ray, t_min, t_max)?, sphere.material_index)
+            }
+            // Cuboid
+            2 => {
+                let cuboid = &scene.cuboids[self.get_scene_index()];
+                (cuboid.hit(ray, t_min, t_max)?, cuboid.material_index)
+            }
+            // Mesh Triangle
+            4 => {
+                let triangle = &scene.tris[self.get_scene_index()];
+                (
+                    triangle.hit(scene, ray, t_min, t_max)?,
+                    triangle.material_index,
+                )
+            }
+            _ => return None,
+        };
+
+        Some(HitRecord {
+            t,
+            p: ray.at(t),
+            normal,
+            hittable: *self,
+            instance: None,
+            material_index,
+        })
+    }
+
+    /// Distance from a point to a primitive hittable and the index of its material (CPU version of `primitive_distance` in the shader)
+    /// Returns None for non-primitive hittables and for primitives using the wos albedo blend material (as they are not part of the domain boundary)
+    pub fn primitive_distance(
+        &self,
+        scene: &LinearSceneBvh,
+        point: Point3<f32>,
+    ) -> Option<(f32, u32)> {
+        let (distance, material_index) = match self.geometry_type {
+            // Sphere
+            1 => {
+                let sphere = &scene.spheres[self.get_scene_index()];
+                (sphere.signed_distance(point), sphere.material_index)
+            }
+            // Cuboid
+            2 => {
+                let cuboid = &scene.cuboids[self.get_scene_index()];
+                (cuboid.signed_distance(point), cuboid.material_index)
+            }
+            // Mesh Triangle
+            4 => {
+                let triangle = &scene.tris[self.get_scene_index()];
+                (
+                    triangle.signed_distance(scene, point),
+                    triangle.material_index,
+                )
+            }
+            _ => return None,
+        };
+
+        if scene.materials[material_index as usize].material_type == 5 {
+            return None;
+        }
+        Some((distance, material_index))
+    }
 }
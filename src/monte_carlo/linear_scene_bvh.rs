@@ -1,28 +1,62 @@
-use cgmath::Matrix4;
+use std::{
+    collections::{BTreeSet, HashSet},
+    ops::Range,
+};
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Point3, Transform};
 use wgpu::util::DeviceExt;
 
 use super::{
+    aabb::surrounding_box,
     bvh_node::BvhNode,
     construction_scene::recompute_bvh,
     cuboid::Cuboid,
+    hit_record::HitRecord,
     linear_constant_medium::LinearConstantMedium,
     linear_hittable::*,
     material::Material,
+    mesh_instance::MeshInstance,
+    ray::Ray,
     sphere::Sphere,
     triangle::{Triangle, TriangleVertex},
 };
 
+/// Refitted bvh is rebuilt once the total surface area of its nodes grows past this ratio of the area when last built
+pub const BVH_REBUILD_COST_RATIO: f32 = 1.5;
+
+/// Triangles and vertices of a mesh in the scene (both are contiguous, in the order the mesh was added) and the root of its bvh
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MeshRange {
+    pub tris: Range<usize>,
+    pub tri_verts: Range<usize>,
+    /// Hittable index of the root of the (bottom level) bvh of the mesh triangles
+    pub root: usize,
+}
+
 /// The basic linearized version of the scene, each vector is separately bound to a different bind group entry in the scene layout group (due to their dynamic nature in length)
+/// The bvh has two levels: the top level (rooted at the first hittable) holds the spheres, cuboids, constant mediums and mesh instances,
+/// each mesh has a bottom level bvh of its triangles (stored after the top level capacity, so rebuilding the top level leaves them in place)
 #[derive(Debug)]
 pub struct LinearSceneBvh {
     pub materials: Vec<Material>,
     pub hittables: Vec<LinearHittable>,
     pub bvh_nodes: Vec<BvhNode>,
+    /// Hittables (and bvh nodes) reserved for the top level bvh, unused entries are padding
+    pub top_level_capacity: usize,
+    pub top_level_node_capacity: usize,
     pub spheres: Vec<Sphere>,
     pub cuboids: Vec<Cuboid>,
     pub constant_mediums: Vec<LinearConstantMedium>,
     pub tri_verts: Vec<TriangleVertex>,
     pub tris: Vec<Triangle>,
+    /// Triangles, vertices and bvh of each mesh (shared by its instances)
+    pub meshes: Vec<MeshRange>,
+    /// Placements of the meshes in the scene
+    pub instances: Vec<MeshInstance>,
+    /// Display colour of each primitive surface (w: 1 where set, see `patch_color_index`), e.g. the radiative flux of each patch
+    pub patch_colors: Vec<[f32; 4]>,
+    /// Total surface area of the bvh nodes when the bvh was last built (the baseline of `bvh_cost`)
+    pub built_bvh_cost: f32,
 
     pub materials_buffer: Option<wgpu::Buffer>,
     pub hittables_buffer: Option<wgpu::Buffer>,
@@ -32,11 +66,13 @@ pub struct LinearSceneBvh {
     pub constant_mediums_buffer: Option<wgpu::Buffer>,
     pub mesh_tri_verts_buffer: Option<wgpu::Buffer>,
     pub mesh_tris_buffer: Option<wgpu::Buffer>,
+    pub patch_colors_buffer: Option<wgpu::Buffer>,
+    pub instances_buffer: Option<wgpu::Buffer>,
 }
 
 impl LinearSceneBvh {
     pub fn null_index_ptr() -> u32 {
-        u32::max_value()
+        u32::MAX
     }
 
     /// Creates an empty scene
@@ -46,11 +82,17 @@ impl LinearSceneBvh {
             materials: vec![],
             hittables: vec![],
             bvh_nodes: vec![],
+            top_level_capacity: 0,
+            top_level_node_capacity: 0,
             spheres: vec![],
             cuboids: vec![],
             constant_mediums: vec![],
             tri_verts: vec![],
             tris: vec![],
+            meshes: vec![],
+            instances: vec![],
+            patch_colors: vec![],
+            built_bvh_cost: 0.0,
 
             materials_buffer: None,
             hittables_buffer: None,
@@ -60,35 +102,61 @@ impl LinearSceneBvh {
             constant_mediums_buffer: None,
             mesh_tri_verts_buffer: None,
             mesh_tris_buffer: None,
+            patch_colors_buffer: None,
+            instances_buffer: None,
+        }
+    }
+
+    /// Copy of the scene without its device buffers, for CPU queries on a worker thread while the scene keeps changing
+    pub fn cpu_copy(&self) -> Self {
+        LinearSceneBvh {
+            materials: self.materials.clone(),
+            hittables: self.hittables.clone(),
+            bvh_nodes: self.bvh_nodes.clone(),
+            top_level_capacity: self.top_level_capacity,
+            top_level_node_capacity: self.top_level_node_capacity,
+            spheres: self.spheres.clone(),
+            cuboids: self.cuboids.clone(),
+            constant_mediums: self.constant_mediums.clone(),
+            tri_verts: self.tri_verts.clone(),
+            tris: self.tris.clone(),
+            meshes: self.meshes.clone(),
+            instances: self.instances.clone(),
+            patch_colors: self.patch_colors.clone(),
+            built_bvh_cost: self.built_bvh_cost,
+            ..LinearSceneBvh::new()
         }
     }
 
     /// The WGPU binding groups must be non-empty, so place an empty/placeholder value in any empty array
     pub fn check_pad_empty_arrays(&mut self) {
-        if self.materials.len() == 0 {
+        if self.materials.is_empty() {
             panic!("Expect at least 1 material defined");
         }
-        if self.hittables.len() == 0 {
+        if self.hittables.is_empty() {
             panic!("Expect at least 1 hittable defined");
         }
-        if self.bvh_nodes.len() == 0 {
+        if self.bvh_nodes.is_empty() {
             self.bvh_nodes.push(BvhNode::empty());
         }
-        if self.spheres.len() == 0 {
+        if self.spheres.is_empty() {
             self.spheres.push(Sphere::empty());
         }
-        if self.cuboids.len() == 0 {
+        if self.cuboids.is_empty() {
             self.cuboids.push(Cuboid::empty());
         }
-        if self.constant_mediums.len() == 0 {
+        if self.constant_mediums.is_empty() {
             self.constant_mediums.push(LinearConstantMedium::empty());
         }
-        if self.tri_verts.len() == 0 {
+        if self.tri_verts.is_empty() {
             self.tri_verts.push(TriangleVertex::empty());
         }
-        if self.tris.len() == 0 {
+        if self.tris.is_empty() {
             self.tris.push(Triangle::empty());
         }
+        if self.instances.is_empty() {
+            self.instances.push(MeshInstance::empty());
+        }
     }
 
     #[allow(dead_code)]
@@ -119,6 +187,11 @@ impl LinearSceneBvh {
                     self.tri_verts[triangle.indices[1] as usize].position,
                     self.tri_verts[triangle.indices[2] as usize].position
                 );
+            } else if hittable.geometry_type == 5 {
+                println!(
+                    "\n Mesh Instance: {:?}",
+                    self.instances[hittable.get_scene_index()]
+                );
             }
         }
     }
@@ -128,12 +201,12 @@ impl LinearSceneBvh {
         &mut self,
         device: &wgpu::Device,
     ) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
-        // Create bind group layout. (This (8) is the maximum number of bindings for a group)
-        let bind_group_entries: Vec<wgpu::BindGroupLayoutEntry> = (0..8)
+        // Create bind group layout. (Storage buffers beyond the default limit of 8 per stage are requested with the device)
+        let bind_group_entries: Vec<wgpu::BindGroupLayoutEntry> = (0..10)
             .map(|i| wgpu::BindGroupLayoutEntry {
                 binding: i,
                 count: None,
-                visibility: wgpu::ShaderStages::FRAGMENT,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: (true) },
                     has_dynamic_offset: false,
@@ -191,6 +264,18 @@ impl LinearSceneBvh {
             contents: bytemuck::cast_slice(&self.tris[..]),
             usage: buffer_usage,
         });
+        self.patch_colors = vec![[0.0; 4]; self.patch_color_count()];
+        let patch_colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&self.patch_colors[..]),
+            usage: buffer_usage,
+        });
+
+        let instances_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&self.instances[..]),
+            usage: buffer_usage,
+        });
 
         // Finally create bind group
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -228,6 +313,14 @@ impl LinearSceneBvh {
                     binding: 7,
                     resource: triangles_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: patch_colors_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: instances_buffer.as_entire_binding(),
+                },
             ],
             label: None,
         });
@@ -241,56 +334,110 @@ impl LinearSceneBvh {
         self.constant_mediums_buffer = Some(constant_mediums_buffer);
         self.mesh_tri_verts_buffer = Some(triangle_vertices_buffer);
         self.mesh_tris_buffer = Some(triangles_buffer);
+        self.patch_colors_buffer = Some(patch_colors_buffer);
+        self.instances_buffer = Some(instances_buffer);
 
         // Return data
         (bind_group_layout, bind_group)
     }
 
-    pub fn update_buffers(&self, queue: &wgpu::Queue) {
-        queue.write_buffer(
-            &self.materials_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.materials[..]),
-        );
-        queue.write_buffer(
-            &self.hittables_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.hittables[..]),
+    /// Write the top level bvh to the device (the mesh bvhs are unchanged by a top level rebuild)
+    pub fn update_top_level_buffers(&self, queue: &wgpu::Queue) {
+        write_buffer_range(
+            queue,
+            &self.hittables_buffer,
+            &self.hittables,
+            0..self.top_level_capacity,
         );
-        queue.write_buffer(
-            &self.bvh_nodes_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.bvh_nodes[..]),
-        );
-        queue.write_buffer(
-            &self.spheres_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.spheres[..]),
-        );
-        queue.write_buffer(
-            &self.cuboids_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.cuboids[..]),
-        );
-        queue.write_buffer(
-            &self.constant_mediums_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.constant_mediums[..]),
-        );
-        queue.write_buffer(
-            &self.mesh_tri_verts_buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&self.tri_verts[..]),
+        write_buffer_range(
+            queue,
+            &self.bvh_nodes_buffer,
+            &self.bvh_nodes,
+            0..self.top_level_node_capacity,
         );
+    }
+
+    /// Number of primitive surfaces with a display colour (6 faces per cuboid, then spheres, then the triangles of each mesh instance)
+    pub fn patch_color_count(&self) -> usize {
+        6 * self.cuboids.len() + self.spheres.len() + self.instance_triangle_count()
+    }
+
+    /// Index of a primitive surface into the patch colours (CPU version of the `patch_index` set in the shader hit functions)
+    /// The face is only used for cuboids (0: +x, 1: -x, 2: +y, 3: -y, 4: +z, 5: -z) and triangles are indexed by instance triangle
+    pub fn patch_color_index(&self, geometry_type: u32, scene_index: u32, face: u32) -> usize {
+        match geometry_type {
+            1 => 6 * self.cuboids.len() + scene_index as usize,
+            2 => 6 * scene_index as usize + face as usize,
+            4 => 6 * self.cuboids.len() + self.spheres.len() + scene_index as usize,
+            _ => panic!("Unsupported geometry type for patch colours"),
+        }
+    }
+
+    /// Update the display colours of the primitive surfaces (pass all zeros to clear them)
+    pub fn set_patch_colors(&mut self, queue: &wgpu::Queue, patch_colors: Vec<[f32; 4]>) {
+        assert_eq!(patch_colors.len(), self.patch_color_count());
+        self.patch_colors = patch_colors;
         queue.write_buffer(
-            &self.mesh_tris_buffer.as_ref().unwrap(),
+            self.patch_colors_buffer.as_ref().unwrap(),
             0,
-            bytemuck::cast_slice(&self.tris[..]),
+            bytemuck::cast_slice(&self.patch_colors[..]),
         );
     }
 
+    /// Mesh the triangle belongs to
+    pub fn mesh_of(&self, tri_index: usize) -> Option<&MeshRange> {
+        self.meshes
+            .iter()
+            .find(|mesh| mesh.tris.contains(&tri_index))
+    }
+
+    /// Number of triangles placed in the scene (each mesh instance places all the triangles of its mesh)
+    pub fn instance_triangle_count(&self) -> usize {
+        self.instances
+            .iter()
+            .filter(|instance| instance.root != LinearSceneBvh::null_index_ptr())
+            .map(|instance| {
+                self.mesh_of(instance.first_triangle as usize)
+                    .unwrap()
+                    .tris
+                    .len()
+            })
+            .sum()
+    }
+
+    /// Instance triangles index the triangles of each mesh instance in turn, returns the mesh instance and triangle of one
+    pub fn instance_triangle(&self, index: u32) -> (usize, usize) {
+        let instance = self
+            .instances
+            .iter()
+            .rposition(|instance| instance.first_patch <= index)
+            .expect("Instance triangle out of range");
+        let first = &self.instances[instance];
+        (
+            instance,
+            (first.first_triangle + index - first.first_patch) as usize,
+        )
+    }
+
+    /// Instance triangle index of a triangle of a mesh instance
+    pub fn instance_triangle_index(&self, instance: usize, tri_index: usize) -> u32 {
+        let instance = &self.instances[instance];
+        instance.first_patch + tri_index as u32 - instance.first_triangle
+    }
+
+    /// World space vertices of an instance triangle
+    pub fn instance_triangle_vertices(&self, index: u32) -> [Point3<f32>; 3] {
+        let (instance, tri_index) = self.instance_triangle(index);
+        let object_to_world = self.instances[instance].object_to_world();
+        self.tris[tri_index]
+            .vertices(self)
+            .map(|vertex| object_to_world.transform_point(vertex))
+    }
+
     /// Helper function to update a hittable in the scenes
-    /// This will internally recompute the bvh and update all scene data in buffers that are bound to GPU
+    /// The top level bvh is refitted above the moved primitives and only the changed buffer ranges are written,
+    /// unless the refitted bvh has degraded past `BVH_REBUILD_COST_RATIO` (then the top level is rebuilt and written).
+    /// Moving a mesh instance only changes the top level, the mesh triangles and bvh are untouched
     pub fn transform_hittable_by(
         &mut self,
         queue: &wgpu::Queue,
@@ -299,11 +446,530 @@ impl LinearSceneBvh {
     ) {
         // Request the referenced hittable to update the underlying data in the scene.
         hittable.transform_by(self, transform);
+        let transformed = hittable.transformed_hittables(self);
+
+        // Refit the BVH, rebuilding it when refitting has made it too loose
+        let refitted = self.refit_bvh(&transformed);
+        let rebuild = self.bvh_cost() > BVH_REBUILD_COST_RATIO * self.built_bvh_cost;
+        if rebuild {
+            recompute_bvh(self);
+        }
+
+        // Push changes to device
+        for transformed_hittable in transformed {
+            self.update_primitive_buffers(queue, &transformed_hittable);
+        }
+        if rebuild {
+            self.update_top_level_buffers(queue);
+            return;
+        }
+        for node in refitted {
+            write_buffer_range(
+                queue,
+                &self.bvh_nodes_buffer,
+                &self.bvh_nodes,
+                node..node + 1,
+            );
+        }
+    }
+
+    /// Recompute the bounding boxes of the top level bvh nodes above the hittables (bottom up, stopping where a box is unchanged)
+    /// Returns the indices of the refitted bvh nodes
+    pub fn refit_bvh(&mut self, hittables: &[LinearHittable]) -> Vec<usize> {
+        // Parent of each hittable in the bvh (the flattened bvh is in breadth first order, so parents come before their children)
+        let mut parents = vec![LinearSceneBvh::null_index_ptr(); self.top_level_capacity];
+        for (index, hittable) in self.hittables[..self.top_level_capacity].iter().enumerate() {
+            if hittable.geometry_type == 0 {
+                let bvh = &self.bvh_nodes[hittable.get_scene_index()];
+                for child in [bvh.left(), bvh.right()] {
+                    if child != BvhNode::null_hittable_ptr() {
+                        parents[child as usize] = index as u32;
+                    }
+                }
+            }
+        }
+
+        // Visit the dirty nodes deepest (highest index) first, so both children are refitted before their parent
+        let hittables: HashSet<&LinearHittable> = hittables.iter().collect();
+        let mut dirty: BTreeSet<u32> = self.hittables[..self.top_level_capacity]
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| hittables.contains(h))
+            .map(|(index, _)| parents[index])
+            .filter(|&parent| parent != LinearSceneBvh::null_index_ptr())
+            .collect();
+        let mut refitted = vec![];
+        while let Some(index) = dirty.pop_last() {
+            let node = self.hittables[index as usize].get_scene_index();
+            let bvh = self.bvh_nodes[node];
+            let aabb = [bvh.left(), bvh.right()]
+                .into_iter()
+                .filter(|&child| child != BvhNode::null_hittable_ptr())
+                .map(|child| self.hittables[child as usize].bounding_box(self))
+                .reduce(|a, b| surrounding_box(&a, &b))
+                .unwrap_or(bvh.bounding_box());
+            if aabb.min() == bvh.bounding_box().min() && aabb.max() == bvh.bounding_box().max() {
+                continue;
+            }
+            self.bvh_nodes[node].set_bounding_box(aabb);
+            refitted.push(node);
+            if parents[index as usize] != LinearSceneBvh::null_index_ptr() {
+                dirty.insert(parents[index as usize]);
+            }
+        }
+        refitted
+    }
+
+    /// Total surface area of the top level bvh nodes, a measure of the cost of traversing the bvh
+    pub fn bvh_cost(&self) -> f32 {
+        self.bvh_nodes[..self.top_level_node_capacity]
+            .iter()
+            .map(|node| node.bounding_box().surface_area())
+            .sum()
+    }
+
+    /// Write the primitive data of a hittable to the device (the boundary of a constant medium, the placement of a mesh instance)
+    fn update_primitive_buffers(&self, queue: &wgpu::Queue, hittable: &LinearHittable) {
+        let index = hittable.get_scene_index();
+        match hittable.geometry_type {
+            1 => write_buffer_range(queue, &self.spheres_buffer, &self.spheres, index..index + 1),
+            2 => write_buffer_range(queue, &self.cuboids_buffer, &self.cuboids, index..index + 1),
+            3 => {
+                let constant_medium = &self.constant_mediums[index];
+                self.update_primitive_buffers(
+                    queue,
+                    &LinearHittable {
+                        geometry_type: constant_medium.boundary_geometry_type,
+                        scene_index: constant_medium.boundary_scene_index,
+                    },
+                );
+            }
+            5 => write_buffer_range(
+                queue,
+                &self.instances_buffer,
+                &self.instances,
+                index..index + 1,
+            ),
+            _ => panic!("Unsupported geometry type"),
+        }
+    }
+
+    /// Helper function to add or remove a top level hittable from the bvh (the underlying primitive data is kept so it can be added back)
+    /// Buffers are written in place, so the hittable should be present when the device buffers are created.
+    pub fn set_hittable_enabled(
+        &mut self,
+        queue: &wgpu::Queue,
+        hittable: &LinearHittable,
+        enabled: bool,
+    ) {
+        let present = self.hittables.contains(hittable);
+        if enabled == present {
+            return;
+        }
+        // The top level is rebuilt from its leaves, so the hittable only needs to be added to (or removed from) the top level range
+        if enabled {
+            let padding = self.hittables[..self.top_level_capacity]
+                .iter()
+                .position(|h| h.geometry_type == 0)
+                .expect("Expect a top level hittable to replace");
+            self.hittables[padding] = *hittable;
+        } else {
+            let index = self.hittables.iter().position(|h| h == hittable).unwrap();
+            self.hittables[index] = LinearHittable {
+                geometry_type: 0,
+                scene_index: 0,
+            };
+        }
 
         // Recompute the BVH
         recompute_bvh(self);
 
         // Push changes to device
-        self.update_buffers(queue);
+        self.update_top_level_buffers(queue);
+    }
+
+    /// Global signed distance function for all scene primitives using bvh stack traversal (CPU version of `scene_sd` in the shader)
+    /// The closest primitive is found by unsigned distance and its signed distance returned, so that the sign is correct for closed meshes
+    /// (where each triangle only knows the sign of its own closest feature) and non-overlapping primitives.
+    /// Returns the signed distance from the point to the closest primitive and the material index of that primitive (if any were found)
+    pub fn scene_sd(&self, point: Point3<f32>) -> (f32, Option<u32>) {
+        let mut closest = (f32::INFINITY, None);
+
+        // Precondition, return early if scene is empty
+        if self.hittables.is_empty() {
+            return closest;
+        }
+
+        // Traverse from the root of the top level (which is the first value in the scene array)
+        self.bvh_sd(0, point, 1.0, &mut closest);
+        closest
+    }
+
+    /// Signed distance from the point to each of the top level primitives (a mesh instance as a whole) and its material index
+    pub fn primitive_sds(&self, point: Point3<f32>) -> Vec<(f32, u32)> {
+        (0..self.top_level_capacity)
+            .filter(|&i| self.hittables[i].geometry_type != 0)
+            .filter_map(|i| {
+                let mut closest = (f32::INFINITY, None);
+                self.bvh_sd(i as u32, point, 1.0, &mut closest);
+                closest.1.map(|material_index| (closest.0, material_index))
+            })
+            .collect()
+    }
+
+    /// Signed distance traversal of the bvh below the root hittable, updating the closest signed distance and material
+    /// Distances in the space of the bvh are multiplied by the scale, mesh instances traverse their mesh bvh in object space
+    fn bvh_sd(&self, root: u32, point: Point3<f32>, scale: f32, closest: &mut (f32, Option<u32>)) {
+        let mut stack: Vec<u32> = vec![root];
+
+        while let Some(hittable_index) = stack.pop() {
+            let current_hittable = self.hittables[hittable_index as usize];
+
+            // If BVH
+            if current_hittable.geometry_type == 0 {
+                let bvh = &self.bvh_nodes[current_hittable.get_scene_index()];
+                if bvh.bounding_box().signed_distance(point) * scale < closest.0.abs() {
+                    // Push the left and right children onto the stack (if they exist)
+                    if bvh.left() != BvhNode::null_hittable_ptr() {
+                        stack.push(bvh.left());
+                    }
+                    if bvh.right() != BvhNode::null_hittable_ptr() {
+                        stack.push(bvh.right());
+                    }
+                }
+                continue;
+            }
+
+            // Mesh instance
+            if current_hittable.geometry_type == 5 {
+                let instance = &self.instances[current_hittable.get_scene_index()];
+                self.bvh_sd(
+                    instance.root,
+                    instance.world_to_object().transform_point(point),
+                    scale * instance.distance_scale,
+                    closest,
+                );
+                continue;
+            }
+
+            // Primitive (constant mediums are not part of the domain boundary)
+            if let Some((dist, material_index)) = current_hittable.primitive_distance(self, point) {
+                if (dist * scale).abs() < closest.0.abs() {
+                    *closest = (dist * scale, Some(material_index));
+                }
+            }
+        }
+    }
+
+    /// Closest ray hit with the scene primitives using bvh stack traversal (CPU version of `scene_hits` in the shader)
+    /// Constant mediums are skipped (they are not surfaces), everything else including the wos albedo blend sections can be hit
+    pub fn scene_hits(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // Precondition, return early if scene is empty
+        if self.hittables.is_empty() {
+            return None;
+        }
+
+        // Traverse from the root of the top level (which is the first value in the scene array)
+        self.bvh_hits(0, ray, t_min, t_max)
+    }
+
+    /// Closest ray hit traversal of the bvh below the root hittable, mesh instances traverse their mesh bvh with the ray in object space
+    /// (the ray direction is transformed without normalising, so the hit distance is the same in both spaces)
+    fn bvh_hits(&self, root: u32, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut closest_hit = None;
+        let mut closest_so_far = t_max;
+        let mut stack: Vec<u32> = vec![root];
+
+        while let Some(hittable_index) = stack.pop() {
+            let current_hittable = self.hittables[hittable_index as usize];
+
+            // If BVH
+            if current_hittable.geometry_type == 0 {
+                let bvh = &self.bvh_nodes[current_hittable.get_scene_index()];
+                if bvh.bounding_box().hit(ray, t_min, closest_so_far) {
+                    // Push the left and right children onto the stack (if they exist)
+                    if bvh.left() != BvhNode::null_hittable_ptr() {
+                        stack.push(bvh.left());
+                    }
+                    if bvh.right() != BvhNode::null_hittable_ptr() {
+                        stack.push(bvh.right());
+                    }
+                }
+                continue;
+            }
+
+            // Mesh instance (normals are transformed back to world space by the inverse transpose)
+            if current_hittable.geometry_type == 5 {
+                let instance = &self.instances[current_hittable.get_scene_index()];
+                let world_to_object = instance.world_to_object();
+                let object_ray = Ray::new(
+                    world_to_object.transform_point(ray.origin),
+                    world_to_object.transform_vector(ray.direction),
+                );
+                if let Some(hit) = self.bvh_hits(instance.root, &object_ray, t_min, closest_so_far)
+                {
+                    let normal_matrix = Matrix3::from_cols(
+                        world_to_object.x.truncate(),
+                        world_to_object.y.truncate(),
+                        world_to_object.z.truncate(),
+                    )
+                    .transpose();
+                    closest_so_far = hit.t;
+                    closest_hit = Some(HitRecord {
+                        p: ray.at(hit.t),
+                        normal: (normal_matrix * hit.normal).normalize(),
+                        instance: Some(current_hittable.get_scene_index()),
+                        ..hit
+                    });
+                }
+                continue;
+            }
+
+            // Primitive
+            if let Some(hit) = current_hittable.primitive_hit(self, ray, t_min, closest_so_far) {
+                closest_so_far = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
+        closest_hit
+    }
+
+    /// Is the point inside any of the (closed) scene primitives
+    pub fn is_inside(&self, point: Point3<f32>) -> bool {
+        self.scene_sd(point).0 < 0.0
+    }
+}
+
+/// Write a range of the elements to the same range of a device buffer
+fn write_buffer_range<T: bytemuck::Pod>(
+    queue: &wgpu::Queue,
+    buffer: &Option<wgpu::Buffer>,
+    data: &[T],
+    range: Range<usize>,
+) {
+    queue.write_buffer(
+        buffer.as_ref().unwrap(),
+        (range.start * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+        bytemuck::cast_slice(&data[range]),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monte_carlo::{
+        construction_scene, hittable_primitive::HittablePrimitive, mesh::Mesh,
+    };
+    use cgmath::Vector3;
+    use obj::Obj;
+
+    #[test]
+    fn test_closed_mesh_signed_distance() {
+        let obj: Obj = Obj::load("src/monte_carlo/resources/cube.obj").expect("Unable to load obj");
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[HittablePrimitive::Mesh(Mesh::new(obj, 0))],
+        );
+
+        // Inside the unit cube mesh (closest to face, edge and vertex features)
+        assert!((scene.scene_sd(Point3::new(0.0, 0.0, 0.0)).0 + 1.0).abs() < 1.0e-5);
+        assert!((scene.scene_sd(Point3::new(0.5, 0.5, 0.0)).0 + 0.5).abs() < 1.0e-5);
+        assert!((scene.scene_sd(Point3::new(0.9, 0.9, 0.9)).0 + 0.1).abs() < 1.0e-5);
+        assert!(scene.is_inside(Point3::new(0.2, -0.7, 0.4)));
+
+        // Outside the unit cube mesh (closest to face, edge and vertex features)
+        assert!((scene.scene_sd(Point3::new(2.0, 0.0, 0.0)).0 - 1.0).abs() < 1.0e-5);
+        assert!((scene.scene_sd(Point3::new(2.0, 2.0, 0.0)).0 - 2.0_f32.sqrt()).abs() < 1.0e-5);
+        assert!((scene.scene_sd(Point3::new(2.0, 2.0, 2.0)).0 - 3.0_f32.sqrt()).abs() < 1.0e-5);
+        assert!(!scene.is_inside(Point3::new(1.2, -0.7, 0.4)));
+    }
+
+    #[test]
+    fn test_transform_hittables() {
+        let obj: Obj = Obj::load("src/monte_carlo/resources/cube.obj").expect("Unable to load obj");
+        let mut scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[
+                HittablePrimitive::Mesh(Mesh::new(obj, 0)),
+                HittablePrimitive::Sphere(Sphere::new(Vector3::new(5.0, 0.0, 0.0), 0.5, 0)),
+            ],
+        );
+        assert_eq!(scene.meshes.len(), 1);
+        let instance = LinearHittable {
+            geometry_type: 5,
+            scene_index: 0,
+        };
+        let sphere = LinearHittable {
+            geometry_type: 1,
+            scene_index: 0,
+        };
+
+        // The mesh instance moves (and rotates about the centre of) the whole mesh, keeping its signed distance valid
+        instance.transform_by(
+            &mut scene,
+            Matrix4::from_angle_y(cgmath::Deg(45.0)) * Matrix4::from_translation(Vector3::unit_y()),
+        );
+        sphere.transform_by(&mut scene, Matrix4::from_scale(2.0));
+        recompute_bvh(&mut scene);
+        assert!((scene.scene_sd(Point3::new(0.0, 1.0, 0.0)).0 + 1.0).abs() < 1.0e-5);
+        assert!(
+            (scene
+                .scene_sd(Point3::new(0.0, 1.0, 2.0_f32.sqrt() + 1.0))
+                .0
+                - 1.0)
+                .abs()
+                < 1.0e-5
+        );
+        assert!((scene.spheres[0].radius - 1.0).abs() < 1.0e-6);
+        assert!((scene.scene_sd(Point3::new(5.0, 0.0, 0.0)).0 + 1.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn test_mesh_instances() {
+        let obj: Obj = Obj::load("src/monte_carlo/resources/cube.obj").expect("Unable to load obj");
+        let mut scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[
+                HittablePrimitive::Mesh(Mesh::new(obj, 0)),
+                HittablePrimitive::MeshInstance(
+                    0,
+                    Matrix4::from_translation(Vector3::new(4.0, 0.0, 0.0)),
+                ),
+                HittablePrimitive::MeshInstance(
+                    0,
+                    Matrix4::from_translation(Vector3::new(-4.0, 0.0, 0.0))
+                        * Matrix4::from_angle_y(cgmath::Deg(30.0))
+                        * Matrix4::from_scale(0.5),
+                ),
+            ],
+        );
+
+        // The triangles are stored once and placed by each instance
+        assert_eq!(scene.tris.len(), 12);
+        assert_eq!(scene.instance_triangle_count(), 36);
+        assert!((scene.scene_sd(Point3::new(4.0, 0.0, 0.0)).0 + 1.0).abs() < 1.0e-5);
+        assert!((scene.scene_sd(Point3::new(6.0, 0.0, 0.0)).0 - 1.0).abs() < 1.0e-5);
+        assert!((scene.scene_sd(Point3::new(-4.0, 0.0, 0.0)).0 + 0.5).abs() < 1.0e-5);
+        let hit = scene
+            .scene_hits(
+                &Ray::new(Point3::new(4.0, 0.0, 5.0), -Vector3::unit_z()),
+                0.001,
+                f32::MAX,
+            )
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1.0e-5);
+        assert!((hit.normal - Vector3::unit_z()).magnitude() < 1.0e-5);
+        assert_eq!(hit.instance, Some(1));
+        let (instance, tri) = scene
+            .instance_triangle(scene.instance_triangle_index(1, hit.hittable.get_scene_index()));
+        assert_eq!((instance, tri), (1, hit.hittable.get_scene_index()));
+
+        // Moving an instance only changes the top level (the mesh bvh is shared and untouched)
+        let mesh_hittables = scene.hittables[scene.top_level_capacity..].to_vec();
+        let mesh_nodes: Vec<u8> =
+            bytemuck::cast_slice(&scene.bvh_nodes[scene.top_level_node_capacity..]).to_vec();
+        let instance = LinearHittable {
+            geometry_type: 5,
+            scene_index: 1,
+        };
+        instance.transform_by(
+            &mut scene,
+            Matrix4::from_translation(Vector3::new(0.0, 3.0, 0.0)),
+        );
+        scene.refit_bvh(&instance.transformed_hittables(&scene));
+        recompute_bvh(&mut scene);
+        assert_eq!(
+            scene.hittables[scene.top_level_capacity..],
+            mesh_hittables[..]
+        );
+        assert_eq!(
+            bytemuck::cast_slice::<BvhNode, u8>(&scene.bvh_nodes[scene.top_level_node_capacity..]),
+            &mesh_nodes[..]
+        );
+        assert!((scene.scene_sd(Point3::new(4.0, 3.0, 0.0)).0 + 1.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn test_non_uniform_mesh_instance() {
+        let obj: Obj = Obj::load("src/monte_carlo/resources/cube.obj").expect("Unable to load obj");
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[
+                HittablePrimitive::Mesh(Mesh::new(obj, 0)),
+                HittablePrimitive::MeshInstance(
+                    0,
+                    Matrix4::from_angle_z(cgmath::Deg(20.0))
+                        * Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0),
+                ),
+            ],
+        );
+
+        // The instance is a 4 x 2 x 2 box (rotated about z), distances are never overestimated
+        let to_box: Matrix4<f32> = Matrix4::from_angle_z(cgmath::Deg(-20.0));
+        for point in [
+            Point3::new(0.0, 3.0, 0.0),
+            Point3::new(0.0, 0.0, -2.5),
+            Point3::new(5.0, 1.0, 0.0),
+            Point3::new(-3.0, -2.0, 1.0),
+        ] {
+            let p = to_box.transform_point(point);
+            let outside = Vector3::new(
+                (p.x.abs() - 2.0).max(0.0),
+                (p.y.abs() - 1.0).max(0.0),
+                (p.z.abs() - 1.0).max(0.0),
+            );
+            let sd = scene.scene_sd(point).0;
+            assert!(sd > 0.0 && sd <= outside.magnitude() + 1.0e-5);
+        }
+
+        // Exact along the unstretched axes
+        assert!((scene.scene_sd(Point3::new(0.0, 0.0, 3.0)).0 - 2.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn test_refit_bvh() {
+        let spheres: Vec<Sphere> = (0..8)
+            .map(|i| Sphere::new(Vector3::new(i as f32, 0.0, 0.0), 0.25, 0))
+            .collect();
+        let mut scene = construction_scene::build_from_spheres(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &spheres,
+        );
+        let sphere = LinearHittable {
+            geometry_type: 1,
+            scene_index: 3,
+        };
+
+        // A small move is refitted in place, and the moved sphere is hit through the refitted boxes
+        let transform = Matrix4::from_translation(Vector3::new(0.0, 0.5, 0.0));
+        sphere.transform_by(&mut scene, transform);
+        let refitted = scene.refit_bvh(&sphere.transformed_hittables(&scene));
+        assert!(!refitted.is_empty());
+        assert!(scene.bvh_cost() < BVH_REBUILD_COST_RATIO * scene.built_bvh_cost);
+        let ray = Ray::new(Point3::new(3.0, 0.5, -5.0), Vector3::unit_z());
+        assert!((scene.scene_hits(&ray, 0.001, f32::MAX).unwrap().t - 4.75).abs() < 1.0e-5);
+
+        // Every node bounds its children
+        for hittable in scene.hittables.iter().filter(|h| h.geometry_type == 0) {
+            let bvh = scene.bvh_nodes[hittable.get_scene_index()];
+            for child in [bvh.left(), bvh.right()] {
+                if child == BvhNode::null_hittable_ptr() {
+                    continue;
+                }
+                let child_box = scene.hittables[child as usize].bounding_box(&scene);
+                let union = surrounding_box(&bvh.bounding_box(), &child_box);
+                assert_eq!(union.min(), bvh.bounding_box().min());
+                assert_eq!(union.max(), bvh.bounding_box().max());
+            }
+        }
+
+        // Moving far from its neighbours degrades the bvh past the rebuild threshold
+        sphere.transform_by(
+            &mut scene,
+            Matrix4::from_translation(Vector3::new(0.0, 50.0, 0.0)),
+        );
+        scene.refit_bvh(&sphere.transformed_hittables(&scene));
+        assert!(scene.bvh_cost() > BVH_REBUILD_COST_RATIO * scene.built_bvh_cost);
     }
 }
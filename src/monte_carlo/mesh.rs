@@ -1,4 +1,4 @@
-use super::triangle::{Triangle, TriangleVertex};
+use super::triangle::{compute_pseudonormals, Triangle, TriangleVertex};
 use obj::Obj;
 
 #[derive(Clone, Debug)]
@@ -27,15 +27,15 @@ impl Mesh {
 
     /// Rather hacky code pulling the first mesh/object group out from the obj file.
     pub fn get_default_first_mesh(&self) -> (Vec<TriangleVertex>, Vec<Triangle>) {
-        let verts: Vec<TriangleVertex> = self
+        let mut verts: Vec<TriangleVertex> = self
             .obj
             .data
             .position
             .iter()
-            .map(|pos| TriangleVertex::new(pos.clone()))
+            .map(|pos| TriangleVertex::new(*pos))
             .collect();
 
-        let tris: Vec<Triangle> = self.obj.data.objects[0].groups[0]
+        let mut tris: Vec<Triangle> = self.obj.data.objects[0].groups[0]
             .polys
             .iter()
             .map(|poly| {
@@ -46,6 +46,9 @@ impl Mesh {
             })
             .collect();
 
+        // Pseudonormals are required for the sign of the mesh signed distance
+        compute_pseudonormals(&mut verts, &mut tris);
+
         (verts, tris)
     }
 }
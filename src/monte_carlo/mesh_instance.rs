@@ -0,0 +1,136 @@
+use cgmath::{Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Transform};
+
+use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh};
+
+///
+/// POD placement of a mesh in the scene ready to ship to GPU.
+/// The top level bvh holds the instances, each referencing the (bottom level) bvh of its mesh triangles in mesh space.
+///
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshInstance {
+    /// World to object space transform (computed automatically as inverse of object_to_world)
+    pub world_to_object: [[f32; 4]; 4],
+    /// Object to world space transform of the mesh
+    pub object_to_world: [[f32; 4]; 4],
+    /// Hittable index of the root of the mesh bvh
+    pub root: u32,
+    /// First triangle of the mesh in the linear scene bvh
+    pub first_triangle: u32,
+    /// Index of the first triangle of this instance into the triangle patch colours (see `LinearSceneBvh::instance_triangle`)
+    pub first_patch: u32,
+    /// Scale of object space distances to world space (the smallest singular value of the transform, so scaled distances never overestimate a non-uniformly scaled mesh)
+    pub distance_scale: f32,
+}
+
+impl MeshInstance {
+    ///
+    /// Construct a new mesh instance
+    /// * `root` - Hittable index of the root of the mesh bvh
+    /// * `first_triangle` - First triangle of the mesh in the linear scene bvh
+    /// * `first_patch` - Index of the first triangle of this instance into the triangle patch colours
+    /// * `object_to_world` - Placement of the mesh in the scene
+    pub fn new(
+        root: u32,
+        first_triangle: u32,
+        first_patch: u32,
+        object_to_world: Matrix4<f32>,
+    ) -> Self {
+        let mut instance = MeshInstance {
+            world_to_object: [[0.0; 4]; 4],
+            object_to_world: [[0.0; 4]; 4],
+            root,
+            first_triangle,
+            first_patch,
+            distance_scale: 1.0,
+        };
+        instance.set_object_to_world(object_to_world);
+        instance
+    }
+
+    pub fn empty() -> Self {
+        MeshInstance {
+            world_to_object: [[0.0; 4]; 4],
+            object_to_world: [[0.0; 4]; 4],
+            root: LinearSceneBvh::null_index_ptr(),
+            first_triangle: LinearSceneBvh::null_index_ptr(),
+            first_patch: LinearSceneBvh::null_index_ptr(),
+            distance_scale: 0.0,
+        }
+    }
+
+    pub fn object_to_world(&self) -> Matrix4<f32> {
+        self.object_to_world.into()
+    }
+
+    pub fn world_to_object(&self) -> Matrix4<f32> {
+        self.world_to_object.into()
+    }
+
+    /// Place the mesh (and update the inverse and distance scale also)
+    pub fn set_object_to_world(&mut self, object_to_world: Matrix4<f32>) {
+        self.object_to_world = object_to_world.into();
+        self.world_to_object = object_to_world
+            .invert()
+            .expect("Mesh instance transform is not invertible")
+            .into();
+        self.distance_scale = smallest_singular_value(Matrix3::from_cols(
+            object_to_world.x.truncate(),
+            object_to_world.y.truncate(),
+            object_to_world.z.truncate(),
+        ));
+    }
+
+    /// World space bounding box of the instance (the 8 corners of the mesh bvh root box in world space)
+    pub fn bounding_box(&self, scene: &LinearSceneBvh) -> Aabb {
+        let root = scene.hittables[self.root as usize].bounding_box(scene);
+        let (min, max) = (root.min(), root.max());
+        let corners: Vec<Point3<f32>> = (0..8)
+            .map(|i| {
+                self.object_to_world().transform_point(Point3::new(
+                    if i & 4 > 0 { max[0] } else { min[0] },
+                    if i & 2 > 0 { max[1] } else { min[1] },
+                    if i & 1 > 0 { max[2] } else { min[2] },
+                ))
+            })
+            .collect();
+        Aabb::new(
+            corners.iter().fold(corners[0], |a, c| {
+                Point3::new(a.x.min(c.x), a.y.min(c.y), a.z.min(c.z))
+            }),
+            corners.iter().fold(corners[0], |a, c| {
+                Point3::new(a.x.max(c.x), a.y.max(c.y), a.z.max(c.z))
+            }),
+        )
+    }
+}
+
+/// Smallest singular value of a 3x3 matrix (square root of the smallest eigenvalue of m^T m, closed form for symmetric matrices)
+fn smallest_singular_value(m: Matrix3<f32>) -> f32 {
+    let a = m.transpose() * m;
+    let a: [[f64; 3]; 3] = [
+        [a.x.x as f64, a.x.y as f64, a.x.z as f64],
+        [a.y.x as f64, a.y.y as f64, a.y.z as f64],
+        [a.z.x as f64, a.z.y as f64, a.z.z as f64],
+    ];
+    let off_diagonal = a[0][1].powi(2) + a[0][2].powi(2) + a[1][2].powi(2);
+    let q = (a[0][0] + a[1][1] + a[2][2]) / 3.0;
+    let p = (((a[0][0] - q).powi(2)
+        + (a[1][1] - q).powi(2)
+        + (a[2][2] - q).powi(2)
+        + 2.0 * off_diagonal)
+        / 6.0)
+        .sqrt();
+    let smallest_eigenvalue = if p < 1.0e-12 {
+        a[0][0].min(a[1][1]).min(a[2][2])
+    } else {
+        // b = (a - qI) / p, the eigenvalues are q + 2p cos(phi + 2k pi / 3) for cos(3 phi) = det(b) / 2
+        let b = |i: usize, j: usize| (a[i][j] - if i == j { q } else { 0.0 }) / p;
+        let det = b(0, 0) * (b(1, 1) * b(2, 2) - b(1, 2) * b(2, 1))
+            - b(0, 1) * (b(1, 0) * b(2, 2) - b(1, 2) * b(2, 0))
+            + b(0, 2) * (b(1, 0) * b(2, 1) - b(1, 1) * b(2, 0));
+        let phi = (det / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+        q + 2.0 * p * (phi + 2.0 * std::f64::consts::PI / 3.0).cos()
+    };
+    smallest_eigenvalue.max(0.0).sqrt() as f32
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{linear_hittable::LinearHittable, linear_scene_bvh::LinearSceneBvh, ray::Ray};
+
+/// Primitive under the cursor, found by casting the camera ray through the clicked pixel into the scene on the CPU
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pick {
+    pub hittable: LinearHittable,
+    pub material_index: u32,
+    /// Hit position in world space
+    pub position: Point3<f32>,
+    /// Unit outward surface normal at the hit
+    pub normal: Vector3<f32>,
+    /// World space distance from the ray origin
+    pub distance: f32,
+}
+
+impl fmt::Display for Pick {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (material {}) at ({:.4}, {:.4}, {:.4}), distance {:.4}",
+            self.hittable.geometry_name(),
+            self.hittable.scene_index,
+            self.material_index,
+            self.position.x,
+            self.position.y,
+            self.position.z,
+            self.distance
+        )
+    }
+}
+
+/// Closest primitive hit by the ray (None when it misses the scene), mesh triangles pick their mesh instance
+pub fn pick(scene: &LinearSceneBvh, ray: &Ray) -> Option<Pick> {
+    scene.scene_hits(ray, 0.001, f32::MAX).map(|hit| Pick {
+        hittable: hit
+            .instance
+            .map_or(hit.hittable, |instance| LinearHittable {
+                geometry_type: 5,
+                scene_index: instance as u32,
+            }),
+        material_index: hit.material_index,
+        position: hit.p,
+        normal: hit.normal,
+        distance: hit.t * ray.direction.magnitude(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::scenes;
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        // Sphere of radius 0.5 at the origin
+        let scene = scenes::simple_scene();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 2.0), Vector3::new(0.0, 0.0, -2.0));
+        let picked = pick(&scene, &ray).unwrap();
+        assert_eq!(picked.hittable.geometry_type, 1);
+        assert_eq!(picked.material_index, 0);
+        assert!((picked.distance - 1.5).abs() < 1.0e-5);
+        assert!((picked.position - Point3::new(0.0, 0.0, 0.5)).magnitude() < 1.0e-5);
+        assert!((picked.normal - Vector3::unit_z()).magnitude() < 1.0e-5);
+
+        let miss = Ray::new(Point3::new(1.0, 0.0, 2.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(pick(&scene, &miss), None);
+    }
+}
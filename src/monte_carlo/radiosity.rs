@@ -0,0 +1,362 @@
+use std::fmt;
+
+use super::{
+    colormap::ColormapDisplay,
+    linear_scene_bvh::LinearSceneBvh,
+    view_factors::{PatchSurface, SurfacePatch, ViewFactors},
+};
+
+/// Stefan-Boltzmann constant (W m^-2 K^-4)
+pub const STEFAN_BOLTZMANN: f32 = 5.670_374e-8;
+
+/// Grey diffuse surface properties of a patch
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SurfaceThermal {
+    /// Hemispherical emissivity (equal to the absorptivity of a grey surface)
+    pub emissivity: f32,
+    /// Surface temperature (K)
+    pub temperature: f32,
+}
+
+impl SurfaceThermal {
+    pub fn new(emissivity: f32, temperature: f32) -> Self {
+        SurfaceThermal {
+            emissivity,
+            temperature,
+        }
+    }
+
+    /// Black body emissive power at the surface temperature (W/m^2)
+    pub fn black_body_power(&self) -> f32 {
+        STEFAN_BOLTZMANN * self.temperature.powi(4)
+    }
+}
+
+/// Thermal setup of a scene: the surface properties of each patch (decided from its primitive) and the surroundings temperature
+#[derive(Copy, Clone)]
+pub struct SceneThermal {
+    /// Temperature of the black surroundings, seen through the openings of the enclosure (K)
+    pub ambient_temperature: f32,
+    pub surface: fn(&SurfacePatch) -> SurfaceThermal,
+}
+
+/// Net radiative exchange between the patches of a grey diffuse enclosure
+pub struct RadiativeExchange {
+    pub names: Vec<String>,
+    /// Area of each patch (m^2)
+    pub areas: Vec<f32>,
+    pub thermals: Vec<SurfaceThermal>,
+    /// Radiosity, the radiation leaving each patch (W/m^2)
+    pub radiosity: Vec<f32>,
+    /// Net radiative heat flux leaving each patch (W/m^2, positive when the patch is losing heat)
+    pub net_flux: Vec<f32>,
+    /// Net radiative heat transfer rate leaving each patch (W)
+    pub net_power: Vec<f32>,
+    /// Net radiative heat transfer rate received by the surroundings through the openings (W)
+    pub surroundings_power: f32,
+    /// Net radiative heat transfer rate received by the untagged surfaces (treated as black at the ambient temperature) (W)
+    pub untagged_power: f32,
+}
+
+impl RadiativeExchange {
+    /// Colour mapping of the net flux (symmetric coolwarm range, so heat sources are red and sinks are blue)
+    pub fn flux_display(&self) -> ColormapDisplay {
+        let max = self
+            .net_flux
+            .iter()
+            .fold(f32::EPSILON, |max, q| max.max(q.abs()));
+        ColormapDisplay {
+            map: 3,
+            min: -max,
+            max,
+            contours: 0,
+        }
+    }
+
+    /// Linear display colour of each primitive surface in the scene (indexed by `LinearSceneBvh::patch_color_index`, w: 1 where a patch is defined)
+    pub fn patch_colors(&self, scene: &LinearSceneBvh, patches: &[SurfacePatch]) -> Vec<[f32; 4]> {
+        let display = self.flux_display();
+        let mut colors = vec![[0.0; 4]; scene.patch_color_count()];
+        for (patch, q) in patches.iter().zip(&self.net_flux) {
+            let c = display.linear_color(*q);
+            let color = [c.x, c.y, c.z, 1.0];
+            match &patch.surface {
+                PatchSurface::CuboidFace { cuboid_index, face } => {
+                    colors[scene.patch_color_index(2, *cuboid_index, *face)] = color;
+                }
+                PatchSurface::Sphere { sphere_index } => {
+                    colors[scene.patch_color_index(1, *sphere_index, 0)] = color;
+                }
+                PatchSurface::Triangles { triangle_indices } => {
+                    for triangle_index in triangle_indices {
+                        colors[scene.patch_color_index(4, *triangle_index, 0)] = color;
+                    }
+                }
+            }
+        }
+        colors
+    }
+}
+
+impl fmt::Display for RadiativeExchange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Net radiative exchange (positive: heat leaving the surface)"
+        )?;
+        writeln!(
+            f,
+            "{:>3}  {:<24} {:>10} {:>6} {:>8} {:>12} {:>12} {:>12}",
+            "", "patch", "area m2", "eps", "T K", "J W/m2", "q W/m2", "Q W"
+        )?;
+        for i in 0..self.names.len() {
+            writeln!(
+                f,
+                "{:>3}: {:<24} {:>10.6} {:>6.3} {:>8.1} {:>12.3} {:>12.3} {:>12.6}",
+                i,
+                self.names[i],
+                self.areas[i],
+                self.thermals[i].emissivity,
+                self.thermals[i].temperature,
+                self.radiosity[i],
+                self.net_flux[i],
+                self.net_power[i]
+            )?;
+        }
+        let total: f32 = self.net_power.iter().sum();
+        writeln!(f, "Total leaving the surfaces: {:.6} W", total)?;
+        if self.untagged_power != 0.0 {
+            writeln!(
+                f,
+                "Received by the untagged surfaces (treated as black at the ambient temperature): {:.6} W",
+                self.untagged_power
+            )?;
+        }
+        writeln!(
+            f,
+            "Received by the surroundings: {:.6} W (imbalance {:.6} W from view factor noise)",
+            self.surroundings_power,
+            total - self.surroundings_power - self.untagged_power
+        )
+    }
+}
+
+/// Net radiation method for grey diffuse surfaces with known temperatures, using Monte Carlo view factors
+/// Solves J_i = e_i E_i + (1 - e_i) G_i with the irradiation G_i = sum_j F_ij J_j + B_i J_i + (1 - sum_j F_ij - B_i) E_ambient,
+/// where the blocked fraction B_i (in contact with another primitive) is treated as seeing the patch itself (no net exchange),
+/// and the untagged fraction (hitting a surface that isn't a patch) is treated as black at the ambient temperature, like the surroundings
+pub struct RadiositySolver<'a> {
+    view_factors: &'a ViewFactors,
+    thermals: Vec<SurfaceThermal>,
+    ambient_temperature: f32,
+}
+
+impl<'a> RadiositySolver<'a> {
+    pub fn new(
+        view_factors: &'a ViewFactors,
+        thermals: Vec<SurfaceThermal>,
+        ambient_temperature: f32,
+    ) -> Self {
+        assert_eq!(
+            view_factors.matrix.len(),
+            thermals.len(),
+            "Expect surface properties for each patch"
+        );
+        RadiositySolver {
+            view_factors,
+            thermals,
+            ambient_temperature,
+        }
+    }
+
+    /// Setup from the patches and the thermal setup of the scene
+    pub fn from_scene(
+        view_factors: &'a ViewFactors,
+        patches: &[SurfacePatch],
+        scene_thermal: &SceneThermal,
+    ) -> Self {
+        Self::new(
+            view_factors,
+            patches.iter().map(scene_thermal.surface).collect(),
+            scene_thermal.ambient_temperature,
+        )
+    }
+
+    pub fn solve(&self) -> RadiativeExchange {
+        let f = &self.view_factors.matrix;
+        let n = f.len();
+        let ambient_power = STEFAN_BOLTZMANN * self.ambient_temperature.powi(4);
+        // Everything that isn't a patch or blocked, the surroundings and the untagged surfaces
+        let escape: Vec<f32> = self
+            .view_factors
+            .row_sums()
+            .iter()
+            .zip(&self.view_factors.blocked)
+            .map(|(s, b)| (1.0 - s - b).max(0.0))
+            .collect();
+        let blocked = &self.view_factors.blocked;
+
+        // Linear system for the radiosities: J_i - (1 - e_i) (sum_j F_ij J_j + B_i J_i) = e_i E_i + (1 - e_i) (1 - sum_j F_ij - B_i) E_ambient
+        let mut a = vec![vec![0.0f64; n]; n];
+        let mut b = vec![0.0f64; n];
+        for i in 0..n {
+            let thermal = &self.thermals[i];
+            let reflectivity = (1.0 - thermal.emissivity) as f64;
+            for j in 0..n {
+                a[i][j] = -reflectivity * f[i][j] as f64;
+            }
+            a[i][i] += 1.0 - reflectivity * blocked[i] as f64;
+            b[i] = (thermal.emissivity * thermal.black_body_power()) as f64
+                + reflectivity * (escape[i] * ambient_power) as f64;
+        }
+        let radiosity: Vec<f32> = solve_linear_system(a, b)
+            .into_iter()
+            .map(|j| j as f32)
+            .collect();
+
+        // Net flux is the radiosity minus the irradiation
+        let net_flux: Vec<f32> = (0..n)
+            .map(|i| {
+                let irradiation: f32 = (0..n).map(|j| f[i][j] * radiosity[j]).sum::<f32>()
+                    + blocked[i] * radiosity[i]
+                    + escape[i] * ambient_power;
+                radiosity[i] - irradiation
+            })
+            .collect();
+        let net_power: Vec<f32> = net_flux
+            .iter()
+            .zip(&self.view_factors.areas)
+            .map(|(q, a)| q * a)
+            .collect();
+
+        // Exchange with the surroundings (using reciprocity for the radiation arriving from the surroundings)
+        let untagged = &self.view_factors.untagged;
+        let surroundings_power = (0..n)
+            .map(|i| {
+                self.view_factors.areas[i]
+                    * (escape[i] - untagged[i]).max(0.0)
+                    * (radiosity[i] - ambient_power)
+            })
+            .sum();
+        let untagged_power = (0..n)
+            .map(|i| self.view_factors.areas[i] * untagged[i] * (radiosity[i] - ambient_power))
+            .sum();
+
+        RadiativeExchange {
+            names: self.view_factors.names.clone(),
+            areas: self.view_factors.areas.clone(),
+            thermals: self.thermals.clone(),
+            radiosity,
+            net_flux,
+            net_power,
+            surroundings_power,
+            untagged_power,
+        }
+    }
+}
+
+/// Gaussian elimination with partial pivoting
+fn solve_linear_system(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    for k in 0..n {
+        let pivot = (k..n)
+            .max_by(|&i, &j| a[i][k].abs().total_cmp(&a[j][k].abs()))
+            .unwrap();
+        a.swap(k, pivot);
+        b.swap(k, pivot);
+        let (rows, below) = a.split_at_mut(k + 1);
+        let pivot_row = &rows[k];
+        for (i, row) in below.iter_mut().enumerate() {
+            let factor = row[k] / pivot_row[k];
+            for (x, p) in row.iter_mut().zip(pivot_row).skip(k) {
+                *x -= factor * p;
+            }
+            b[k + 1 + i] -= factor * b[k];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for k in (0..n).rev() {
+        let sum: f64 = ((k + 1)..n).map(|j| a[k][j] * x[j]).sum();
+        x[k] = (b[k] - sum) / a[k][k];
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monte_carlo::{scenes, view_factors::ViewFactorSolver};
+
+    #[test]
+    fn test_parallel_plates() {
+        // Infinite parallel plates (view factors of 1 between them), analytic q = sigma (T1^4 - T2^4) / (1/e1 + 1/e2 - 1)
+        let view_factors = ViewFactors {
+            names: vec!["hot".to_string(), "cold".to_string()],
+            areas: vec![2.0, 2.0],
+            matrix: vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            blocked: vec![0.0, 0.0],
+            untagged: vec![0.0, 0.0],
+            rays_per_patch: 0,
+        };
+        let thermals = vec![
+            SurfaceThermal::new(0.8, 600.0),
+            SurfaceThermal::new(0.3, 300.0),
+        ];
+        let expected = STEFAN_BOLTZMANN * (600.0f32.powi(4) - 300.0f32.powi(4))
+            / (1.0 / 0.8 + 1.0 / 0.3 - 1.0);
+
+        let exchange = RadiositySolver::new(&view_factors, thermals, 0.0).solve();
+        assert!((exchange.net_flux[0] - expected).abs() / expected < 1.0e-4);
+        assert!((exchange.net_flux[1] + expected).abs() / expected < 1.0e-4);
+        assert!((exchange.net_power[0] - 2.0 * expected).abs() / expected < 1.0e-3);
+        assert!(exchange.surroundings_power.abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn test_untagged_surfaces() {
+        // Plate only seeing an untagged surface, everything it emits is received by that surface rather than the surroundings
+        let view_factors = ViewFactors {
+            names: vec!["hot".to_string()],
+            areas: vec![2.0],
+            matrix: vec![vec![0.0]],
+            blocked: vec![0.0],
+            untagged: vec![1.0],
+            rays_per_patch: 0,
+        };
+        let exchange =
+            RadiositySolver::new(&view_factors, vec![SurfaceThermal::new(1.0, 600.0)], 0.0).solve();
+        let expected = 2.0 * STEFAN_BOLTZMANN * 600.0f32.powi(4);
+        assert!((exchange.net_power[0] - expected).abs() / expected < 1.0e-4);
+        assert!((exchange.untagged_power - expected).abs() / expected < 1.0e-4);
+        assert!(exchange.surroundings_power.abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn test_cornell_box() {
+        let scene = scenes::cornell_box();
+        let patches = SurfacePatch::scene_patches(&scene);
+        let view_factors = ViewFactorSolver::new(&scene, &patches).solve(10000);
+
+        // Isothermal enclosure (with the surroundings at the same temperature) has no net exchange, whatever the view factors
+        let isothermal = RadiositySolver::new(
+            &view_factors,
+            vec![SurfaceThermal::new(0.7, 400.0); patches.len()],
+            400.0,
+        )
+        .solve();
+        assert!(isothermal.net_flux.iter().all(|q| q.abs() < 1.0e-2));
+
+        // Hot light, the light loses heat and the energy balance closes up to the view factor noise
+        let exchange =
+            RadiositySolver::from_scene(&view_factors, &patches, &scenes::cornell_box_thermal())
+                .solve();
+        let light = patches
+            .iter()
+            .position(|p| p.name == "cuboid 6 -y")
+            .unwrap();
+        assert!(exchange.net_power[light] > 0.0);
+        let total: f32 = exchange.net_power.iter().sum();
+        assert!((total - exchange.surroundings_power).abs() < 0.05 * exchange.net_power[light]);
+    }
+}
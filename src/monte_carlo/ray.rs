@@ -0,0 +1,19 @@
+use cgmath::{Point3, Vector3};
+
+/// Ray for the CPU scene queries (CPU version of `Ray` in the shader)
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Ray { origin, direction }
+    }
+
+    /// Point along the ray at distance t (in units of the direction length)
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+}
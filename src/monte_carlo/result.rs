@@ -1,19 +1,145 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
 use wgpu::util::DeviceExt;
 
-use super::window;
+use super::{
+    colormap::{self, ColormapDisplay},
+    tone_mapping::ToneMapping,
+    window,
+    wos::WosStats,
+};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct ResultUniforms {
     pub pass_index: u32,
+    /// 0: Sampling, 1: Paused (the result is only displayed)
+    pub paused: u32,
+}
+
+/// Per pixel noise of the accumulated result, from the luminance moments
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct NoiseStats {
+    /// Mean number of samples per pixel
+    pub mean_samples: f32,
+    /// Mean relative standard error of the pixel luminance
+    pub mean_relative_error: f32,
+    /// Fraction of the pixels below the target relative error
+    pub converged_fraction: f32,
+}
+
+impl NoiseStats {
+    /// Stats of the moments texture pixels (x: mean luminance, y: mean squared luminance, z: samples)
+    pub fn from_moments(moments: &[[f32; 4]], target_error: f32) -> Self {
+        if moments.is_empty() {
+            return NoiseStats::default();
+        }
+        let count = moments.len() as f32;
+        let mut stats = NoiseStats::default();
+        for m in moments {
+            let error = relative_error(m);
+            stats.mean_samples += m[2] / count;
+            stats.mean_relative_error += error / count;
+            if error < target_error {
+                stats.converged_fraction += 1.0 / count;
+            }
+        }
+        stats
+    }
+}
+
+impl fmt::Display for NoiseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} spp, relative error {:.4}, {:.1}% converged",
+            self.mean_samples,
+            self.mean_relative_error,
+            100.0 * self.converged_fraction
+        )
+    }
+}
+
+/// Relative standard error of the pixel mean luminance (same as the shader), infinite before any samples
+fn relative_error(moments: &[f32; 4]) -> f32 {
+    if moments[2] < 1.0 {
+        return f32::INFINITY;
+    }
+    let variance = (moments[1] - moments[0] * moments[0]).max(0.0);
+    (variance / moments[2]).sqrt() / moments[0].max(0.01)
+}
+
+/// Copy of a result texture being mapped for reading, polled each frame instead of waiting on the device
+struct TextureReadback {
+    buffer: wgpu::Buffer,
+    mapping: Pin<Box<dyn Future<Output = std::result::Result<(), wgpu::BufferAsyncError>> + Send>>,
+    padded_bytes_per_row: u32,
+    size: wgpu::Extent3d,
+    /// Pass index when the copy was made
+    pass_index: u32,
+}
+
+impl TextureReadback {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        size: wgpu::Extent3d,
+        pass_index: u32,
+    ) -> Self {
+        let (buffer, padded_bytes_per_row) =
+            Result::copy_texture_to_buffer(device, queue, texture, size);
+        let mapping = Box::pin(buffer.slice(..).map_async(wgpu::MapMode::Read));
+        TextureReadback {
+            buffer,
+            mapping,
+            padded_bytes_per_row,
+            size,
+            pass_index,
+        }
+    }
+
+    /// Pixels of the copy once it has been mapped (polls the device without waiting)
+    fn poll(&mut self, device: &wgpu::Device) -> Option<Vec<[f32; 4]>> {
+        device.poll(wgpu::Maintain::Poll);
+        match self
+            .mapping
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Pending => None,
+            Poll::Ready(mapped) => {
+                mapped.expect("Unable to map texture buffer");
+                Some(Result::mapped_pixels(
+                    &self.buffer,
+                    self.padded_bytes_per_row,
+                    self.size,
+                ))
+            }
+        }
+    }
 }
 
 pub struct Result {
     texture: wgpu::Texture,
+    section_texture: wgpu::Texture,
+    moments_texture: wgpu::Texture,
+    /// Size of the result textures (the render resolution)
+    size: wgpu::Extent3d,
     uniforms: ResultUniforms,
     uniforms_buffer: wgpu::Buffer,
+    wos_stats_buffer: wgpu::Buffer,
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    /// Read back of the moments texture for the noise (with the adaptive sampling target error)
+    noise_readback: Option<(TextureReadback, f32)>,
+    /// Read back of the section texture for the colour map range
+    section_range_readback: Option<TextureReadback>,
 }
 
 impl Result {
@@ -25,26 +151,14 @@ impl Result {
             depth_or_array_layers: 1,
         };
 
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::COPY_DST
-                | wgpu::TextureUsages::COPY_SRC
-                | wgpu::TextureUsages::STORAGE_BINDING
-                | wgpu::TextureUsages::TEXTURE_BINDING,
-            label: None,
-        });
-
-        let initial_data: Vec<u8> =
-            vec![0; texture_size.width as usize * texture_size.height as usize * 4 * 4];
-
-        Self::update_texture(device, queue, &texture, &initial_data[..], texture_size);
+        let (texture, section_texture, moments_texture) =
+            Self::create_textures(device, queue, texture_size);
 
         // Initialize the uniforms buffer (to keep track of things like pass index)
-        let uniforms = ResultUniforms { pass_index: 0 };
+        let uniforms = ResultUniforms {
+            pass_index: 0,
+            paused: 0,
+        };
 
         let uniforms_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -52,12 +166,21 @@ impl Result {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        // Initialize the wos stats buffer (atomically counted in the shader)
+        let wos_stats_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[WosStats::default()]),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+        });
+
         // Create a combined bind group layout and bind group for the result data.
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::StorageTexture {
                         access: wgpu::StorageTextureAccess::ReadWrite,
                         format: wgpu::TextureFormat::Rgba32Float,
@@ -68,41 +191,67 @@ impl Result {
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
                     count: None,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
                 },
-            ],
-            label: None,
-        });
-
-        // Generate texture view for the binding resource.
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    count: None,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: uniforms_buffer.as_entire_binding(),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadWrite,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadWrite,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
                 },
             ],
             label: None,
         });
 
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniforms_buffer,
+            &wos_stats_buffer,
+            [&texture, &section_texture, &moments_texture],
+        );
+
         Result {
             texture,
+            section_texture,
+            moments_texture,
+            size: texture_size,
             bind_group_layout,
             bind_group,
             uniforms,
             uniforms_buffer,
+            wos_stats_buffer,
+            noise_readback: None,
+            section_range_readback: None,
         }
     }
 
@@ -114,31 +263,190 @@ impl Result {
         &self.bind_group
     }
 
+    /// Render resolution of the result
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(self.size.width, self.size.height)
+    }
+
+    pub fn pass_index(&self) -> u32 {
+        self.uniforms.pass_index
+    }
+
     pub fn increment_pass_index(&mut self, queue: &wgpu::Queue) {
         self.set_pass_index(queue, self.uniforms.pass_index + 1);
     }
 
-    pub fn reset_texture(
-        &mut self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        size: winit::dpi::PhysicalSize<u32>,
-    ) {
-        let texture_size = wgpu::Extent3d {
-            width: size.width,
-            height: size.height,
-            depth_or_array_layers: 1,
-        };
+    pub fn reset_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let texture_size = self.size;
 
         let inital_data: Vec<u8> =
             vec![0; texture_size.width as usize * texture_size.height as usize * 4 * 4];
 
         self.set_pass_index(queue, 0);
+        self.noise_readback = None;
+        self.section_range_readback = None;
+        queue.write_buffer(
+            &self.wos_stats_buffer,
+            0,
+            bytemuck::cast_slice(&[WosStats::default()]),
+        );
         Self::update_texture(device, queue, &self.texture, &inital_data[..], texture_size);
+        Self::update_texture(
+            device,
+            queue,
+            &self.section_texture,
+            &inital_data[..],
+            texture_size,
+        );
+        Self::update_texture(
+            device,
+            queue,
+            &self.moments_texture,
+            &inital_data[..],
+            texture_size,
+        );
+    }
+
+    /// Reallocate the result textures (and their bind group) for a new window size, restarting the accumulation
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, window: window::Window) {
+        let texture_size = wgpu::Extent3d {
+            width: window.width_pixels,
+            height: window.height_pixels,
+            depth_or_array_layers: 1,
+        };
+        let (texture, section_texture, moments_texture) =
+            Self::create_textures(device, queue, texture_size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniforms_buffer,
+            &self.wos_stats_buffer,
+            [&texture, &section_texture, &moments_texture],
+        );
+        self.texture = texture;
+        self.section_texture = section_texture;
+        self.moments_texture = moments_texture;
+        self.size = texture_size;
+
+        self.set_pass_index(queue, 0);
+        self.noise_readback = None;
+        self.section_range_readback = None;
+        queue.write_buffer(
+            &self.wos_stats_buffer,
+            0,
+            bytemuck::cast_slice(&[WosStats::default()]),
+        );
+    }
+
+    /// Per pixel noise of the accumulated result (relative to the adaptive sampling target error)
+    pub fn read_noise_stats(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target_error: f32,
+    ) -> NoiseStats {
+        NoiseStats::from_moments(
+            &Self::read_texture(device, queue, &self.moments_texture, self.size),
+            target_error,
+        )
+    }
+
+    /// Start reading back the noise of the accumulated result without blocking (ignored while a read back is in flight),
+    /// the stats are returned by `poll_noise_stats` once the copy has been mapped
+    pub fn request_noise_stats(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target_error: f32,
+    ) {
+        if self.noise_readback.is_some() {
+            return;
+        }
+        let readback = TextureReadback::new(
+            device,
+            queue,
+            &self.moments_texture,
+            self.size,
+            self.pass_index(),
+        );
+        self.noise_readback = Some((readback, target_error));
+    }
+
+    /// Pass index and noise of the requested read back once it is ready (polls the device without waiting)
+    pub fn poll_noise_stats(&mut self, device: &wgpu::Device) -> Option<(u32, NoiseStats)> {
+        let (readback, target_error) = self.noise_readback.as_mut()?;
+        let moments = readback.poll(device)?;
+        let stats = NoiseStats::from_moments(&moments, *target_error);
+        let pass_index = readback.pass_index;
+        self.noise_readback = None;
+        Some((pass_index, stats))
+    }
+
+    /// Start reading back the accumulated scalar wos result on the sections without blocking (ignored while a read back is in flight),
+    /// the range is returned by `poll_section_range` once the copy has been mapped
+    pub fn request_section_range(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.section_range_readback.is_some() {
+            return;
+        }
+        self.section_range_readback = Some(TextureReadback::new(
+            device,
+            queue,
+            &self.section_texture,
+            self.size,
+            self.pass_index(),
+        ));
+    }
+
+    /// Range of the scalar wos result over the pixels covered by colour mapped sections, once the requested read back is ready
+    /// (None while waiting, or if no pixels are covered)
+    pub fn poll_section_range(&mut self, device: &wgpu::Device) -> Option<(f32, f32)> {
+        let pixels = self.section_range_readback.as_mut()?.poll(device)?;
+        self.section_range_readback = None;
+        section_range(&pixels)
+    }
+
+    /// Read back the walk on spheres termination statistics accumulated since the last reset
+    pub fn read_wos_stats(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> WosStats {
+        let size = std::mem::size_of::<WosStats>() as wgpu::BufferAddress;
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.wos_stats_buffer, 0, &staging_buffer, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // Block until the copy is complete and the buffer is mapped
+        let buffer_slice = staging_buffer.slice(..);
+        let mapping = buffer_slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).expect("Unable to map wos stats buffer");
+
+        let stats = *bytemuck::from_bytes::<WosStats>(&buffer_slice.get_mapped_range());
+        staging_buffer.unmap();
+        stats
+    }
+
+    pub fn paused(&self) -> bool {
+        self.uniforms.paused == 1
+    }
+
+    /// Pause (or resume) the accumulation, a paused result is still displayed
+    pub fn set_paused(&mut self, queue: &wgpu::Queue, paused: bool) {
+        self.uniforms.paused = paused as u32;
+        self.write_uniforms(queue);
     }
 
     fn set_pass_index(&mut self, queue: &wgpu::Queue, index: u32) {
         self.uniforms.pass_index = index;
+        self.write_uniforms(queue);
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
         queue.write_buffer(
             &self.uniforms_buffer,
             0,
@@ -172,7 +480,7 @@ impl Result {
                 },
             },
             wgpu::ImageCopyTexture {
-                texture: &texture,
+                texture,
                 mip_level: 0,
                 aspect: wgpu::TextureAspect::All,
                 origin: wgpu::Origin3d::ZERO,
@@ -183,53 +491,252 @@ impl Result {
         queue.submit(std::iter::once(encoder.finish()));
     }
 
+    /// Save the displayed result (with the colour mapped sections and legend) as a png in the working directory, with the legend in a text file next to it
     pub fn write_texture_to_working_dir(
         &self,
         device: &wgpu::Device,
-        // queue: &wgpu::Queue,
-        size: winit::dpi::PhysicalSize<u32>,
+        queue: &wgpu::Queue,
+        display: &ColormapDisplay,
+        tone_mapping: &ToneMapping,
+    ) -> String {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("monte_carlo_{}.png", timestamp);
+        self.write_texture(device, queue, display, tone_mapping, &path);
+        path
+    }
+
+    /// Save the result as a png (and the colour map legend next to it, when a colour map is shown)
+    pub fn write_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        display: &ColormapDisplay,
+        tone_mapping: &ToneMapping,
+        path: &str,
     ) {
-        // Copy GPU texture to Buffer
+        let size = self.size;
+        let color = Self::read_texture(device, queue, &self.texture, size);
+        let section = Self::read_texture(device, queue, &self.section_texture, size);
+        let image = colormap::composite_image(
+            size.width,
+            size.height,
+            &color,
+            &section,
+            display,
+            tone_mapping,
+        );
 
-        // Using 32bit float texture in shader
-        let buffer_size = (size.width * size.height * 32) as u64;
-        let texture_size = wgpu::Extent3d {
-            width: size.width,
-            height: size.height,
-            depth_or_array_layers: 1,
-        };
+        image.save(path).expect("Unable to save image");
+        if display.map != 0 {
+            std::fs::write(
+                std::path::Path::new(path).with_extension("legend.txt"),
+                display.legend(),
+            )
+            .expect("Unable to save legend");
+        }
+    }
+
+    /// Result textures (colour, section and moments) zeroed
+    fn create_textures(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: wgpu::Extent3d,
+    ) -> (wgpu::Texture, wgpu::Texture, wgpu::Texture) {
+        let texture = Self::create_texture(device, size);
+
+        // Initialize the section texture (accumulated scalar wos result and coverage of the colour mapped sections)
+        let section_texture = Self::create_texture(device, size);
+
+        // Initialize the moments texture (accumulated luminance moments and sample count of each pixel for the variance)
+        let moments_texture = Self::create_texture(device, size);
+
+        let initial_data: Vec<u8> = vec![0; size.width as usize * size.height as usize * 4 * 4];
+
+        Self::update_texture(device, queue, &texture, &initial_data[..], size);
+        Self::update_texture(device, queue, &section_texture, &initial_data[..], size);
+        Self::update_texture(device, queue, &moments_texture, &initial_data[..], size);
+
+        (texture, section_texture, moments_texture)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        uniforms_buffer: &wgpu::Buffer,
+        wos_stats_buffer: &wgpu::Buffer,
+        [texture, section_texture, moments_texture]: [&wgpu::Texture; 3],
+    ) -> wgpu::BindGroup {
+        // Generate texture view for the binding resource.
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let section_texture_view =
+            section_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let moments_texture_view =
+            moments_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniforms_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wos_stats_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&section_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&moments_texture_view),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    fn create_texture(device: &wgpu::Device, size: wgpu::Extent3d) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: None,
+        })
+    }
+
+    /// Read back a 32 bit float rgba texture (rows start at the bottom of the screen)
+    fn read_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        size: wgpu::Extent3d,
+    ) -> Vec<[f32; 4]> {
+        let (output_buffer, padded_bytes_per_row) =
+            Self::copy_texture_to_buffer(device, queue, texture, size);
+
+        // Block until the copy is complete and the buffer is mapped
+        let mapping = output_buffer.slice(..).map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).expect("Unable to map texture buffer");
+        Self::mapped_pixels(&output_buffer, padded_bytes_per_row, size)
+    }
+
+    /// Copy a texture (of 4 x f32 pixels) to a new mappable buffer, returns the buffer and its (aligned) bytes per row
+    fn copy_texture_to_buffer(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        size: wgpu::Extent3d,
+    ) -> (wgpu::Buffer, u32) {
+        // Rows of the copy need to be aligned
+        let unpadded_bytes_per_row = size.width * 16;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
 
         let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            mapped_at_creation: true,
-            size: buffer_size,
+            mapped_at_creation: false,
+            size: (padded_bytes_per_row * size.height) as u64,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
         });
 
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
         encoder.copy_texture_to_buffer(
-            self.texture.as_image_copy(),
+            texture.as_image_copy(),
             wgpu::ImageCopyBuffer {
                 buffer: &output_buffer,
                 layout: wgpu::ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(size.width * 16),
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
                     rows_per_image: std::num::NonZeroU32::new(size.height),
                 },
             },
-            texture_size,
+            size,
         );
+        queue.submit(std::iter::once(encoder.finish()));
+        (output_buffer, padded_bytes_per_row)
+    }
+
+    /// Pixels of a mapped texture copy (without the row padding), the buffer is unmapped after
+    fn mapped_pixels(
+        output_buffer: &wgpu::Buffer,
+        padded_bytes_per_row: u32,
+        size: wgpu::Extent3d,
+    ) -> Vec<[f32; 4]> {
+        let unpadded_bytes_per_row = size.width * 16;
+        let pixels = output_buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| {
+                bytemuck::cast_slice::<u8, [f32; 4]>(&row[..unpadded_bytes_per_row as usize])
+                    .to_vec()
+            })
+            .collect();
+        output_buffer.unmap();
+        pixels
+    }
+}
+
+/// Range of the accumulated scalar wos result (stored multiplied by the coverage, with the coverage) over the pixels covered by colour mapped sections (if any)
+fn section_range(pixels: &[[f32; 4]]) -> Option<(f32, f32)> {
+    pixels
+        .iter()
+        .filter(|p| p[1] > 0.5)
+        .map(|p| p[0] / p[1])
+        .fold(None, |range, v| match range {
+            None => Some((v, v)),
+            Some((min, max)) => Some((v.min(min), v.max(max))),
+        })
+}
 
-        // Read texture to CPU
-        // let = output_buffer.slice(..).map_async(wgpu::MapMode::Read);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_stats() {
+        // Constant pixel has no variance, a pixel alternating 0 and 2 (100 samples) has a relative error of 1 / 10
+        let moments = [
+            [1.0, 1.0, 16.0, 1.0],
+            [1.0, 2.0, 100.0, 1.0],
+            [0.0, 0.0, 0.0, 0.0],
+        ];
+        assert_eq!(relative_error(&moments[0]), 0.0);
+        assert!((relative_error(&moments[1]) - 0.1).abs() < 1.0e-6);
+        assert_eq!(relative_error(&moments[2]), f32::INFINITY);
+
+        let stats = NoiseStats::from_moments(&moments, 0.05);
+        assert!((stats.mean_samples - 116.0 / 3.0).abs() < 1.0e-4);
+        assert!((stats.converged_fraction - 1.0 / 3.0).abs() < 1.0e-6);
+    }
 
-        // Create bmp
-        // let mut img = Image::new(size.width, size.height);
-        // for (x, y) in img.coordinates() {
-        // img.set_pixel(x, y, output_buffer[0])
-        // }
-        println!("WIP. MAP ASYNC todo.")
+    #[test]
+    fn test_section_range() {
+        // Mean of the accumulated samples over the covered pixels only
+        let pixels = [
+            [2.0, 4.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0],
+            [3.0, 2.0, 0.0, 0.0],
+            [-1.0, 1.0, 0.0, 0.0],
+        ];
+        assert_eq!(section_range(&pixels), Some((-1.0, 1.5)));
+        assert_eq!(section_range(&pixels[1..2]), None);
     }
 }
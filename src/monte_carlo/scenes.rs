@@ -2,60 +2,186 @@ use cgmath::{prelude::*, Deg, Matrix4, Vector3};
 use obj::Obj;
 
 use super::{
-    constant_medium::ConstantMedium, construction_scene, cuboid::Cuboid,
-    hittable_primitive::HittablePrimitive, linear_scene_bvh::LinearSceneBvh, material::Material,
-    mesh::Mesh, sphere::Sphere,
+    conductivity_regions::ConductivityRegions,
+    constant_medium::ConstantMedium,
+    construction_scene,
+    cuboid::Cuboid,
+    hittable_primitive::HittablePrimitive,
+    interactive_section::{InteractiveSection, InteractiveSections, SectionKind},
+    linear_hittable::LinearHittable,
+    linear_scene_bvh::LinearSceneBvh,
+    material::Material,
+    mesh::Mesh,
+    radiosity::{SceneThermal, SurfaceThermal},
+    sphere::Sphere,
+    view_factors::{PatchSurface, SurfacePatch},
+    wos_transient::TransientProblem,
 };
 
+/// Scene with its interactive sections (the xy and yz planes and the x line are its wos albedo blend cuboids)
 #[allow(dead_code)]
-pub fn test_wos_bunny_mesh_scene() -> LinearSceneBvh {
+pub fn test_wos_bunny_mesh_scene() -> (LinearSceneBvh, InteractiveSections) {
     let obj: Obj = Obj::load("src/monte_carlo/resources/bunny.obj").expect("Unable to load obj");
 
-    construction_scene::build_from_hittable_primitives(
-        &vec![
+    let xy_plane = HittablePrimitive::Cuboid(Cuboid::new(
+        Matrix4::identity()
+            * Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0))
+            * Matrix4::from_nonuniform_scale(5.0, 5.0, 0.001),
+        0,
+    ));
+    let yz_plane = HittablePrimitive::Cuboid(Cuboid::new(
+        Matrix4::identity()
+            * Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0))
+            * Matrix4::from_nonuniform_scale(0.001, 5.0, 5.0),
+        0,
+    ));
+    let x_line = HittablePrimitive::Cuboid(Cuboid::new(
+        Matrix4::identity()
+            * Matrix4::from_translation(Vector3::new(0.0, 0.1, 0.0))
+            * Matrix4::from_nonuniform_scale(5.0, 0.002, 0.002),
+        0,
+    ));
+
+    let (scene, hittables) = construction_scene::build_with_hittables(
+        &[
             Material::new(5, 0.0, 0.0, Vector3::<f32>::new(0.0, 0.0, 0.0)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.8, 0.8, 0.3)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.9, 0.2, 0.2)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.2, 0.9, 0.2)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.2, 0.2, 0.9)),
         ],
-        &vec![
-            HittablePrimitive::Cuboid(Cuboid::new(
-                Matrix4::identity()
-                    * Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0))
-                    * Matrix4::from_nonuniform_scale(5.0, 5.0, 0.001),
-                0,
-            )),
+        &[
+            xy_plane,
             HittablePrimitive::Cuboid(Cuboid::new(
                 Matrix4::identity()
                     * Matrix4::from_translation(Vector3::new(0.0, -0.05, 0.0))
                     * Matrix4::from_nonuniform_scale(0.2, 0.005, 0.1),
                 1,
             )),
+            yz_plane,
+            x_line,
             HittablePrimitive::Mesh(Mesh::new(obj, 2)),
             HittablePrimitive::Sphere(Sphere::new(Vector3::<f32>::new(0.2, 0.1, 0.0), 0.05, 3)),
             HittablePrimitive::Sphere(Sphere::new(Vector3::<f32>::new(-0.2, 0.1, 0.0), 0.05, 4)),
         ],
+    );
+
+    let sections = test_wos_bunny_mesh_sections(hittables[0], hittables[2], hittables[3]);
+    (scene, sections)
+}
+
+/// Interactive sections of `test_wos_bunny_mesh_scene`, referencing its wos albedo blend cuboids
+fn test_wos_bunny_mesh_sections(
+    xy_plane: LinearHittable,
+    yz_plane: LinearHittable,
+    x_line: LinearHittable,
+) -> InteractiveSections {
+    InteractiveSections::new(
+        vec![
+            InteractiveSection::new("xy plane", SectionKind::Plane, xy_plane, true),
+            InteractiveSection::new("yz plane", SectionKind::Plane, yz_plane, false),
+            InteractiveSection::new("x line", SectionKind::Line, x_line, false),
+        ],
+        0.0005,
+        0.25,
+        0.005,
+    )
+}
+
+/// Thermal setup of `test_wos_bunny_mesh_scene`, a warm bunny on a cool plinth in a room temperature surroundings
+#[allow(dead_code)]
+pub fn test_wos_bunny_mesh_thermal() -> SceneThermal {
+    SceneThermal {
+        ambient_temperature: 293.0,
+        surface: |patch: &SurfacePatch| match patch.surface {
+            PatchSurface::Triangles { .. } => SurfaceThermal::new(0.9, 310.0),
+            PatchSurface::CuboidFace {
+                cuboid_index: 1, ..
+            } => SurfaceThermal::new(0.3, 283.0),
+            _ => SurfaceThermal::new(0.8, 293.0),
+        },
+    }
+}
+
+/// Transient conduction setup of `test_wos_bunny_mesh_scene`, the air starts at 0 and the boundaries (mean albedo as the
+/// temperature) are switched on over the first 0.1 s, with the far field held at 0
+#[allow(dead_code)]
+pub fn test_wos_bunny_mesh_transient() -> TransientProblem {
+    TransientProblem {
+        diffusivity: 0.01,
+        initial: |_| 0.0,
+        boundary: |material, _, time| {
+            material.map_or(0.0, |m| {
+                (m.albedo[0] + m.albedo[1] + m.albedo[2]) / 3.0 * (time / 0.1).min(1.0)
+            })
+        },
+    }
+}
+
+/// Conductivity regions of `test_wos_bunny_mesh_scene`, a conductive core (ten times the surrounding medium) above the bunny
+#[allow(dead_code)]
+pub fn test_wos_bunny_mesh_regions() -> ConductivityRegions {
+    ConductivityRegions::new(
+        construction_scene::build_from_spheres(
+            &[Material::new(
+                0,
+                0.0,
+                0.0,
+                Vector3::<f32>::new(1.0, 1.0, 1.0),
+            )],
+            &[Sphere::new(Vector3::<f32>::new(0.0, 0.25, 0.0), 0.05, 0)],
+        ),
+        vec![10.0],
+        1.0,
+        0.002,
     )
 }
 
 #[allow(dead_code)]
 pub fn simple_scene() -> LinearSceneBvh {
     construction_scene::build_from_spheres(
-        &vec![Material::new(
+        &[Material::new(
             0,
             0.0,
             0.0,
             Vector3::<f32>::new(1.0, 0.0, 0.0),
         )],
-        &vec![Sphere::new(Vector3::<f32>::new(0.0, 0.0, 0.0), 0.5, 0)],
+        &[Sphere::new(Vector3::<f32>::new(0.0, 0.0, 0.0), 0.5, 0)],
+    )
+}
+
+/// Ten bunnies sharing one mesh (and its bvh), the first in place and the rest in a ring around it
+#[allow(dead_code)]
+pub fn bunny_instances_scene() -> LinearSceneBvh {
+    let obj: Obj = Obj::load("src/monte_carlo/resources/bunny.obj").expect("Unable to load obj");
+    let mut primitives = vec![
+        HittablePrimitive::Cuboid(Cuboid::new(
+            Matrix4::from_translation(Vector3::new(0.0, -0.05, 0.0))
+                * Matrix4::from_nonuniform_scale(0.6, 0.005, 0.6),
+            0,
+        )),
+        HittablePrimitive::Mesh(Mesh::new(obj, 1)),
+    ];
+    primitives.extend((0..9).map(|i| {
+        HittablePrimitive::MeshInstance(
+            0,
+            Matrix4::from_angle_y(Deg(40.0 * i as f32))
+                * Matrix4::from_translation(Vector3::new(0.35, 0.0, 0.0)),
+        )
+    }));
+    construction_scene::build_from_hittable_primitives(
+        &[
+            Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.8, 0.8, 0.8)),
+            Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.9, 0.2, 0.2)),
+        ],
+        &primitives,
     )
 }
 
 #[allow(dead_code)]
 pub fn test_scene_wos() -> LinearSceneBvh {
     construction_scene::build_from_hittable_primitives(
-        &vec![
+        &[
             Material::new(5, 0.0, 0.0, Vector3::<f32>::new(0.0, 0.0, 0.0)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.7, 0.6, 0.7)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.8, 0.0, 0.0)),
@@ -63,7 +189,7 @@ pub fn test_scene_wos() -> LinearSceneBvh {
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.0, 0.0, 0.8)),
             Material::new(2, 0.0, 1.5, Vector3::<f32>::new(1.0, 1.0, 1.0)),
         ],
-        &vec![
+        &[
             HittablePrimitive::Cuboid(Cuboid::new(
                 Matrix4::identity()
                     * Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0))
@@ -95,7 +221,7 @@ pub fn test_scene_wos() -> LinearSceneBvh {
 #[allow(dead_code)]
 pub fn cornell_box() -> LinearSceneBvh {
     construction_scene::build_from_hittable_primitives(
-        &vec![
+        &[
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(1.0, 1.0, 1.0)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(1.0, 0.0, 0.0)),
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(0.0, 1.0, 0.0)),
@@ -107,7 +233,7 @@ pub fn cornell_box() -> LinearSceneBvh {
             Material::new(0, 0.0, 0.0, Vector3::<f32>::new(1.0, 1.0, 0.0)),
             Material::new(5, 0.0, 0.0, Vector3::<f32>::new(1.0, 1.0, 1.0)),
         ],
-        &vec![
+        &[
             HittablePrimitive::Cuboid(Cuboid::new(
                 Matrix4::identity()
                     * Matrix4::from_translation(Vector3::new(0.0, 0.2, 0.0))
@@ -183,3 +309,17 @@ pub fn cornell_box() -> LinearSceneBvh {
         ],
     )
 }
+
+/// Thermal setup of `cornell_box`, a hot light panel in a room temperature box (open at the front to the surroundings)
+#[allow(dead_code)]
+pub fn cornell_box_thermal() -> SceneThermal {
+    SceneThermal {
+        ambient_temperature: 300.0,
+        surface: |patch: &SurfacePatch| match patch.surface {
+            PatchSurface::CuboidFace {
+                cuboid_index: 6, ..
+            } => SurfaceThermal::new(0.9, 600.0),
+            _ => SurfaceThermal::new(0.8, 300.0),
+        },
+    }
+}
@@ -1,6 +1,6 @@
-use cgmath::{Point3, Vector3};
+use cgmath::{InnerSpace, Point3, Vector3};
 
-use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh};
+use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh, ray::Ray, util};
 
 ///
 /// POD Sphere ready to ship to GPU
@@ -18,11 +18,7 @@ pub struct Sphere {
 }
 
 impl Sphere {
-    pub fn new(
-        center: Vector3<f32>,
-        radius: f32,
-        material_index: u32,
-    ) -> Self {
+    pub fn new(center: Vector3<f32>, radius: f32, material_index: u32) -> Self {
         Sphere {
             center: center.into(),
             radius,
@@ -58,4 +54,46 @@ impl Sphere {
             ),
         )
     }
+
+    /// Signed distance from a point to the surface of the sphere (negative inside)
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        (point - Point3::from(self.center)).magnitude() - self.radius
+    }
+
+    /// Closest ray hit distance within the range and the outward normal (CPU version of `sphere_hit` in the shader)
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vector3<f32>)> {
+        let center = Point3::from(self.center);
+        let oc = ray.origin - center;
+        let a = ray.direction.magnitude2();
+        let half_b = oc.dot(ray.direction);
+        let c = oc.magnitude2() - self.radius * self.radius;
+
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrtd = discriminant.sqrt();
+
+        // Find the nearest root that lies in acceptable range
+        let mut root = (-half_b - sqrtd) / a;
+        if root < t_min || root > t_max {
+            root = (-half_b + sqrtd) / a;
+            if root < t_min || root > t_max {
+                return None;
+            }
+        }
+
+        Some((root, (ray.at(root) - center) / self.radius))
+    }
+
+    /// Surface area of the sphere
+    pub fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
+
+    /// Uniformly distributed random point on the surface and its outward normal
+    pub fn random_surface_point(&self) -> (Point3<f32>, Vector3<f32>) {
+        let normal = util::random_unit_vector();
+        (Point3::from(self.center) + normal * self.radius, normal)
+    }
 }
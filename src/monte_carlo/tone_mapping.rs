@@ -0,0 +1,132 @@
+use cgmath::Vector3;
+
+use super::colormap::{colormap, srgb_to_linear};
+
+/// Number of tone map operators (cycled with the 'O' key)
+pub const TONE_MAP_OPERATOR_COUNT: u32 = 4;
+
+/// Middle grey luminance the false colour range is centred on
+const MIDDLE_GREY: f32 = 0.18;
+
+/// Stops either side of middle grey covered by the false colour map
+const FALSE_COLOR_STOPS: f32 = 6.0;
+
+/// Linear white point of the Hable filmic curve
+const HABLE_WHITE: f32 = 11.2;
+
+pub fn tone_map_operator_name(operator: u32) -> &'static str {
+    match operator {
+        0 => "none (clamp)",
+        1 => "reinhard",
+        2 => "aces",
+        3 => "filmic",
+        _ => "unknown",
+    }
+}
+
+/// Display transform of the linear HDR accumulated radiance (CPU version of `tone_map` in the shader)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneMapping {
+    /// 0: None (clamp), 1: Reinhard, 2: ACES (fitted), 3: Filmic (Hable)
+    pub operator: u32,
+    /// Exposure in stops (radiance is scaled by 2^exposure)
+    pub exposure: f32,
+    /// Show the exposed luminance with a false colour map instead (inferno over middle grey +- 6 stops)
+    pub false_color: bool,
+}
+
+impl ToneMapping {
+    /// Display linear colour in [0, 1] of a linear HDR radiance
+    pub fn apply(&self, radiance: Vector3<f32>) -> Vector3<f32> {
+        let c = radiance * self.exposure.exp2();
+        if self.false_color {
+            let luminance = c.x * 0.2126 + c.y * 0.7152 + c.z * 0.0722;
+            return false_color(luminance);
+        }
+        let mapped = match self.operator {
+            1 => c.map(|v| v / (1.0 + v)),
+            2 => c.map(aces),
+            3 => c.map(|v| hable(v) / hable(HABLE_WHITE)),
+            _ => c,
+        };
+        mapped.map(|v| v.clamp(0.0, 1.0))
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "tone map: {}, exposure: {:+.1} stops{}",
+            tone_map_operator_name(self.operator),
+            self.exposure,
+            if self.false_color {
+                ", false colour luminance"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// Linear colour of the false colour map at a luminance
+fn false_color(luminance: f32) -> Vector3<f32> {
+    let stops = (luminance.max(1.0e-8) / MIDDLE_GREY).log2();
+    let t = (stops + FALSE_COLOR_STOPS) / (2.0 * FALSE_COLOR_STOPS);
+    colormap(2, t.clamp(0.0, 1.0)).map(srgb_to_linear)
+}
+
+/// Narkowicz fit of the ACES filmic curve
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+/// Hable (Uncharted 2) filmic curve, normalised by its value at the white point
+fn hable(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_map_operators() {
+        let grey = |v: f32| Vector3::new(v, v, v);
+        for operator in 0..TONE_MAP_OPERATOR_COUNT {
+            let tone_mapping = ToneMapping {
+                operator,
+                exposure: 0.0,
+                false_color: false,
+            };
+            // Monotonic, black stays black and bright radiance is in range
+            let values: Vec<f32> = [0.0, 0.1, 0.5, 1.0, 4.0, 100.0]
+                .iter()
+                .map(|&v| tone_mapping.apply(grey(v)).x)
+                .collect();
+            assert!(values[0].abs() < 1.0e-3, "{}", operator);
+            assert!(values.windows(2).all(|w| w[1] >= w[0]), "{}", operator);
+            assert!(values[5] <= 1.0 && values[5] > 0.95, "{}", operator);
+        }
+
+        // No operator and no exposure keeps the displayable range unchanged, and each stop doubles it
+        let none = ToneMapping {
+            operator: 0,
+            exposure: 0.0,
+            false_color: false,
+        };
+        assert_eq!(none.apply(grey(0.25)), grey(0.25));
+        let brighter = ToneMapping {
+            exposure: 1.0,
+            ..none
+        };
+        assert_eq!(brighter.apply(grey(0.25)), grey(0.5));
+
+        // False colour is centred on middle grey
+        let false_color = ToneMapping {
+            false_color: true,
+            ..none
+        };
+        let mid = false_color.apply(grey(MIDDLE_GREY));
+        let expected = colormap(2, 0.5).map(srgb_to_linear);
+        assert!((mid - expected).x.abs() < 1.0e-4);
+    }
+}
@@ -1,6 +1,8 @@
-use cgmath::Point3;
+use std::collections::HashMap;
 
-use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh};
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh, ray::Ray, util};
 
 /// POD plain triangle vertex ready to ship to GPU
 #[repr(C)]
@@ -8,17 +10,26 @@ use super::{aabb::Aabb, linear_scene_bvh::LinearSceneBvh};
 pub struct TriangleVertex {
     pub position: [f32; 3],
     _pad1: u32,
+    /// Angle weighted pseudonormal of the vertex (used for the sign of the mesh signed distance)
+    pub normal: [f32; 3],
+    _pad2: u32,
 }
 
 impl TriangleVertex {
     pub fn new(position: [f32; 3]) -> Self {
-        TriangleVertex { position, _pad1: 0 }
-        // TriangleVertex { position }
+        TriangleVertex {
+            position,
+            _pad1: 0,
+            normal: [0.0; 3],
+            _pad2: 0,
+        }
     }
     pub fn empty() -> Self {
         TriangleVertex {
             position: [0.0, 0.0, 0.0],
             _pad1: 0,
+            normal: [0.0; 3],
+            _pad2: 0,
         }
     }
 }
@@ -31,6 +42,15 @@ pub struct Triangle {
     pub indices: [u32; 3],
     /// Index of the material in the linear scene bvh
     pub material_index: u32,
+    /// Pseudonormal of the edge from vertex 0 to 1
+    pub edge_normal_0: [f32; 3],
+    _pad1: f32,
+    /// Pseudonormal of the edge from vertex 1 to 2
+    pub edge_normal_1: [f32; 3],
+    _pad2: f32,
+    /// Pseudonormal of the edge from vertex 2 to 0
+    pub edge_normal_2: [f32; 3],
+    _pad3: f32,
 }
 
 impl Triangle {
@@ -38,12 +58,18 @@ impl Triangle {
         Triangle {
             indices,
             material_index,
+            edge_normal_0: [0.0; 3],
+            _pad1: 0.0,
+            edge_normal_1: [0.0; 3],
+            _pad2: 0.0,
+            edge_normal_2: [0.0; 3],
+            _pad3: 0.0,
         }
     }
     pub fn empty() -> Self {
         Triangle {
-            indices: [0, 0, 0],
             material_index: LinearSceneBvh::null_index_ptr(),
+            ..Triangle::new(0, [0, 0, 0])
         }
     }
     /// Returns the bounding box of the constant medium
@@ -66,4 +92,183 @@ impl Triangle {
             ),
         )
     }
+
+    /// Returns the three vertex positions of the triangle
+    pub fn vertices(&self, scene: &LinearSceneBvh) -> [Point3<f32>; 3] {
+        [
+            scene.tri_verts[self.indices[0] as usize].position.into(),
+            scene.tri_verts[self.indices[1] as usize].position.into(),
+            scene.tri_verts[self.indices[2] as usize].position.into(),
+        ]
+    }
+
+    /// Ray hit distance within the range and the unit normal (CPU version of `triangle_hit` in the shader)
+    /// Attribution: https://iquilezles.org/articles/intersectors/ (triIntersect)
+    pub fn hit(
+        &self,
+        scene: &LinearSceneBvh,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<(f32, Vector3<f32>)> {
+        let [v0, v1, v2] = self.vertices(scene);
+        let v1v0 = v1 - v0;
+        let v2v0 = v2 - v0;
+        let rov0 = ray.origin - v0;
+        let n = v1v0.cross(v2v0);
+        let q = rov0.cross(ray.direction);
+        let d = 1.0 / ray.direction.dot(n);
+        let u = d * (-q).dot(v2v0);
+        let v = d * q.dot(v1v0);
+        let t = d * (-n).dot(rov0);
+
+        // Within range and within triangle (also rejects parallel rays where d is not finite)
+        if !(t >= t_min && t <= t_max) || u < 0.0 || v < 0.0 || (u + v) > 1.0 {
+            return None;
+        }
+        Some((t, n.normalize()))
+    }
+
+    /// Signed distance from a point to the triangle (CPU version of `triangle_sd` in the shader)
+    /// The sign is taken from the angle weighted pseudonormal of the closest feature (face, edge or vertex), so the
+    /// closest triangle of a closed mesh gives the correct inside (negative) / outside (positive) sign.
+    /// Attribution: Real-Time Collision Detection (Ericson) closest point on triangle & Baerentzen and Aanaes 2005.
+    pub fn signed_distance(&self, scene: &LinearSceneBvh, point: Point3<f32>) -> f32 {
+        let [a, b, c] = self.vertices(scene);
+        let vertex_normal =
+            |i: usize| -> Vector3<f32> { scene.tri_verts[self.indices[i] as usize].normal.into() };
+
+        let ab = b - a;
+        let ac = c - a;
+        let (closest, pseudonormal) = 'closest: {
+            // Vertex region a
+            let ap = point - a;
+            let d1 = ab.dot(ap);
+            let d2 = ac.dot(ap);
+            if d1 <= 0.0 && d2 <= 0.0 {
+                break 'closest (a, vertex_normal(0));
+            }
+            // Vertex region b
+            let bp = point - b;
+            let d3 = ab.dot(bp);
+            let d4 = ac.dot(bp);
+            if d3 >= 0.0 && d4 <= d3 {
+                break 'closest (b, vertex_normal(1));
+            }
+            // Edge region ab
+            let vc = d1 * d4 - d3 * d2;
+            if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+                let v = d1 / (d1 - d3);
+                break 'closest (a + ab * v, self.edge_normal_0.into());
+            }
+            // Vertex region c
+            let cp = point - c;
+            let d5 = ab.dot(cp);
+            let d6 = ac.dot(cp);
+            if d6 >= 0.0 && d5 <= d6 {
+                break 'closest (c, vertex_normal(2));
+            }
+            // Edge region ca
+            let vb = d5 * d2 - d1 * d6;
+            if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+                let w = d2 / (d2 - d6);
+                break 'closest (a + ac * w, self.edge_normal_2.into());
+            }
+            // Edge region bc
+            let va = d3 * d6 - d5 * d4;
+            if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+                let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+                break 'closest (b + (c - b) * w, self.edge_normal_1.into());
+            }
+            // Face region (degenerate triangles fall back to the first vertex)
+            let denom = va + vb + vc;
+            if denom == 0.0 {
+                break 'closest (a, vertex_normal(0));
+            }
+            let v = vb / denom;
+            let w = vc / denom;
+            (a + ab * v + ac * w, ab.cross(ac))
+        };
+
+        let offset = point - closest;
+        if offset.dot(pseudonormal) < 0.0 {
+            -offset.magnitude()
+        } else {
+            offset.magnitude()
+        }
+    }
+}
+
+/// Area of a triangle from its vertices
+pub fn triangle_area([a, b, c]: [Point3<f32>; 3]) -> f32 {
+    0.5 * (b - a).cross(c - a).magnitude()
+}
+
+/// Uniformly distributed random point on a triangle from its vertices and its unit normal
+pub fn random_triangle_point([a, b, c]: [Point3<f32>; 3]) -> (Point3<f32>, Vector3<f32>) {
+    let (mut u, mut v) = (util::random_float(), util::random_float());
+    if u + v > 1.0 {
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+    (
+        a + (b - a) * u + (c - a) * v,
+        (b - a).cross(c - a).normalize(),
+    )
+}
+
+/// Compute the angle weighted vertex pseudonormals and the edge pseudonormals of a (closed, outward facing) triangle mesh.
+/// Indices of the triangles are local to the supplied vertices.
+pub fn compute_pseudonormals(tri_verts: &mut [TriangleVertex], tris: &mut [Triangle]) {
+    let mut vertex_normals = vec![Vector3::new(0.0, 0.0, 0.0); tri_verts.len()];
+    let mut edge_normals: HashMap<(u32, u32), Vector3<f32>> = HashMap::new();
+
+    for tri in tris.iter() {
+        let positions: Vec<Point3<f32>> = tri
+            .indices
+            .iter()
+            .map(|i| tri_verts[*i as usize].position.into())
+            .collect();
+        let face_normal = (positions[1] - positions[0]).cross(positions[2] - positions[0]);
+        if face_normal.magnitude2() == 0.0 {
+            continue;
+        }
+        let face_normal = face_normal.normalize();
+
+        for i in 0..3 {
+            // Vertex normals are weighted by the incident angle of the face
+            let to_next = positions[(i + 1) % 3] - positions[i];
+            let to_prev = positions[(i + 2) % 3] - positions[i];
+            vertex_normals[tri.indices[i] as usize] += face_normal * to_next.angle(to_prev).0;
+
+            // Edge normals are the sum of the adjacent face normals (keyed independent of winding)
+            let (i_0, i_1) = (tri.indices[i], tri.indices[(i + 1) % 3]);
+            *edge_normals
+                .entry((i_0.min(i_1), i_0.max(i_1)))
+                .or_insert_with(|| Vector3::new(0.0, 0.0, 0.0)) += face_normal;
+        }
+    }
+
+    for (vert, normal) in tri_verts.iter_mut().zip(vertex_normals) {
+        vert.normal = normalize_or_zero(normal).into();
+    }
+    for tri in tris.iter_mut() {
+        let edge_normal = |i: usize| -> [f32; 3] {
+            let (i_0, i_1) = (tri.indices[i], tri.indices[(i + 1) % 3]);
+            edge_normals
+                .get(&(i_0.min(i_1), i_0.max(i_1)))
+                .map_or([0.0; 3], |n| normalize_or_zero(*n).into())
+        };
+        tri.edge_normal_0 = edge_normal(0);
+        tri.edge_normal_1 = edge_normal(1);
+        tri.edge_normal_2 = edge_normal(2);
+    }
+}
+
+fn normalize_or_zero(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
 }
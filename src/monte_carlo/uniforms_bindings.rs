@@ -5,8 +5,8 @@ use super::{constants, window};
 pub struct UniformsBindings {
     bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
-    // constants_buffer: wgpu::Buffer,
-    // window_buffer: wgpu::Buffer,
+    constants_buffer: wgpu::Buffer,
+    window_buffer: wgpu::Buffer,
 }
 
 impl UniformsBindings {
@@ -27,12 +27,12 @@ impl UniformsBindings {
         let constants_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: bytemuck::cast_slice(constants_slice),
-            usage: wgpu::BufferUsages::UNIFORM,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
         let window_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: bytemuck::cast_slice(window_slice),
-            usage: wgpu::BufferUsages::UNIFORM,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -41,7 +41,7 @@ impl UniformsBindings {
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     count: None,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -51,7 +51,7 @@ impl UniformsBindings {
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
                     count: None,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -78,8 +78,8 @@ impl UniformsBindings {
         Self {
             bind_group_layout,
             bind_group,
-            // constants_buffer,
-            // window_buffer,
+            constants_buffer,
+            window_buffer,
         }
     }
 
@@ -91,19 +91,19 @@ impl UniformsBindings {
         &self.bind_group
     }
 
-    // pub fn update_constants_buffer(
-    //     &mut self,
-    //     queue: &wgpu::Queue,
-    //     constants_slice: &[constants::Constants],
-    // ) {
-    //     queue.write_buffer(
-    //         &self.constants_buffer,
-    //         0,
-    //         bytemuck::cast_slice(constants_slice),
-    //     );
-    // }
+    pub fn update_constants_buffer(
+        &mut self,
+        queue: &wgpu::Queue,
+        constants_slice: &[constants::Constants],
+    ) {
+        queue.write_buffer(
+            &self.constants_buffer,
+            0,
+            bytemuck::cast_slice(constants_slice),
+        );
+    }
 
-    // pub fn update_window_buffer(&mut self, queue: &wgpu::Queue, window_slice: &[window::Window]) {
-    //     queue.write_buffer(&self.window_buffer, 0, bytemuck::cast_slice(window_slice));
-    // }
+    pub fn update_window_buffer(&mut self, queue: &wgpu::Queue, window_slice: &[window::Window]) {
+        queue.write_buffer(&self.window_buffer, 0, bytemuck::cast_slice(window_slice));
+    }
 }
@@ -1,3 +1,4 @@
+use cgmath::{InnerSpace, Vector3};
 use rand::prelude::*;
 
 pub fn degrees_to_radians(degrees: f32) -> f32 {
@@ -19,6 +20,62 @@ pub fn random_int(min: u32, max: u32) -> u32 {
     (random_float() * ((max - min + 1) as f32)) as u32 + min
 }
 
+///
+/// Random float from the standard normal distribution (Box-Muller transform)
+///
+pub fn random_normal() -> f32 {
+    let r_1 = 1.0 - random_float();
+    let r_2 = random_float();
+    (-2.0 * r_1.ln()).sqrt() * (2.0 * std::f32::consts::PI * r_2).cos()
+}
+
+///
+/// Random point inside the unit sphere (rejection sampled)
+///
+pub fn random_in_unit_sphere() -> Vector3<f32> {
+    loop {
+        let p = Vector3::new(
+            2.0 * random_float() - 1.0,
+            2.0 * random_float() - 1.0,
+            2.0 * random_float() - 1.0,
+        );
+        if p.magnitude2() < 1.0 {
+            return p;
+        }
+    }
+}
+
+///
+/// Random direction uniformly distributed on the unit sphere
+///
+pub fn random_unit_vector() -> Vector3<f32> {
+    random_in_unit_sphere().normalize()
+}
+
+///
+/// Random direction in the hemisphere around the unit normal, distributed by the cosine of the angle to the normal
+///
+pub fn random_cosine_direction(normal: Vector3<f32>) -> Vector3<f32> {
+    let r_1 = random_float();
+    let r_2 = random_float();
+    let phi = 2.0 * std::f32::consts::PI * r_1;
+    let local = Vector3::new(
+        phi.cos() * r_2.sqrt(),
+        phi.sin() * r_2.sqrt(),
+        (1.0 - r_2).sqrt(),
+    );
+
+    // Orthonormal basis around the normal
+    let a = if normal.x.abs() > 0.9 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let v = normal.cross(a).normalize();
+    let u = normal.cross(v);
+    u * local.x + v * local.y + normal * local.z
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
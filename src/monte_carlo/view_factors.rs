@@ -0,0 +1,452 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{
+    hit_record::HitRecord,
+    linear_scene_bvh::LinearSceneBvh,
+    ray::Ray,
+    triangle::{random_triangle_point, triangle_area},
+    util,
+};
+
+/// Offset of the ray origins from the emitting surface (to avoid hitting the surface itself)
+const SURFACE_OFFSET: f32 = 1.0e-4;
+
+/// Maximum number of wos albedo blend sections a ray passes through before it is discarded
+const MAX_SECTION_CROSSINGS: u32 = 16;
+
+/// Where a ray from a patch ends up
+enum RayEnd {
+    Patch(usize),
+    /// Escaped the scene
+    Escaped,
+    /// Started inside another primitive
+    Blocked,
+    /// Hit an opaque surface that isn't one of the patches
+    Untagged,
+}
+
+/// Geometry of a surface patch
+#[derive(Clone, Debug)]
+pub enum PatchSurface {
+    /// Face of a cuboid. 0: +x, 1: -x, 2: +y, 3: -y, 4: +z, 5: -z
+    CuboidFace { cuboid_index: u32, face: u32 },
+    /// Whole surface of a sphere
+    Sphere { sphere_index: u32 },
+    /// Group of mesh triangles (instance triangles, see `LinearSceneBvh::instance_triangle`)
+    Triangles { triangle_indices: Vec<u32> },
+}
+
+/// Named (tagged) surface patch taking part in the radiative exchange
+#[derive(Clone, Debug)]
+pub struct SurfacePatch {
+    pub name: String,
+    pub surface: PatchSurface,
+}
+
+impl SurfacePatch {
+    pub fn new(name: &str, surface: PatchSurface) -> Self {
+        SurfacePatch {
+            name: name.to_string(),
+            surface,
+        }
+    }
+
+    /// The six faces of a cuboid as separate patches
+    pub fn cuboid_faces(name: &str, cuboid_index: u32) -> Vec<SurfacePatch> {
+        ["+x", "-x", "+y", "-y", "+z", "-z"]
+            .iter()
+            .enumerate()
+            .map(|(face, axis)| {
+                SurfacePatch::new(
+                    &format!("{} {}", name, axis),
+                    PatchSurface::CuboidFace {
+                        cuboid_index,
+                        face: face as u32,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// All the mesh triangles (of every mesh instance) using a material as a single patch
+    pub fn triangles_with_material(
+        name: &str,
+        scene: &LinearSceneBvh,
+        material_index: u32,
+    ) -> SurfacePatch {
+        SurfacePatch::new(
+            name,
+            PatchSurface::Triangles {
+                triangle_indices: (0..scene.instance_triangle_count() as u32)
+                    .filter(|&i| {
+                        scene.tris[scene.instance_triangle(i).1].material_index == material_index
+                    })
+                    .collect(),
+            },
+        )
+    }
+
+    /// Default tagging of the scene surfaces: the faces of each cuboid, each sphere and the mesh triangles grouped by material
+    /// (only primitives that are scene hittables are included, so constant medium boundaries are skipped, and primitives
+    /// using the wos albedo blend material are sections, not surfaces, and are skipped)
+    pub fn scene_patches(scene: &LinearSceneBvh) -> Vec<SurfacePatch> {
+        let hittables: HashSet<(u32, u32)> = scene
+            .hittables
+            .iter()
+            .map(|h| (h.geometry_type, h.scene_index))
+            .collect();
+        let is_surface = |geometry_type: u32, scene_index: usize, material_index: u32| {
+            hittables.contains(&(geometry_type, scene_index as u32))
+                && scene.materials[material_index as usize].material_type != 5
+        };
+
+        let mut patches = vec![];
+        for (i, cuboid) in scene.cuboids.iter().enumerate() {
+            if is_surface(2, i, cuboid.material_index) {
+                patches.extend(SurfacePatch::cuboid_faces(
+                    &format!("cuboid {}", i),
+                    i as u32,
+                ));
+            }
+        }
+        for (i, sphere) in scene.spheres.iter().enumerate() {
+            if is_surface(1, i, sphere.material_index) {
+                patches.push(SurfacePatch::new(
+                    &format!("sphere {}", i),
+                    PatchSurface::Sphere {
+                        sphere_index: i as u32,
+                    },
+                ));
+            }
+        }
+        let mut mesh_materials: Vec<u32> = (0..scene.instance_triangle_count() as u32)
+            .map(|i| scene.instance_triangle(i))
+            .map(|(instance, tri)| (instance, scene.tris[tri].material_index))
+            .filter(|&(instance, material_index)| is_surface(5, instance, material_index))
+            .map(|(_, material_index)| material_index)
+            .collect();
+        mesh_materials.sort_unstable();
+        mesh_materials.dedup();
+        for material_index in mesh_materials {
+            patches.push(SurfacePatch::triangles_with_material(
+                &format!("mesh material {}", material_index),
+                scene,
+                material_index,
+            ));
+        }
+        patches
+    }
+}
+
+/// View factor matrix between surface patches, F[i][j] is the fraction of the diffuse radiation leaving patch i that arrives at patch j
+pub struct ViewFactors {
+    pub names: Vec<String>,
+    /// Area of each patch
+    pub areas: Vec<f32>,
+    pub matrix: Vec<Vec<f32>>,
+    /// Fraction of the rays from each patch starting inside another primitive (where surfaces are in contact)
+    pub blocked: Vec<f32>,
+    /// Fraction of the rays from each patch hitting an opaque surface that isn't a patch
+    pub untagged: Vec<f32>,
+    pub rays_per_patch: u32,
+}
+
+impl ViewFactors {
+    /// Sum of each row of the matrix (1 for a closed enclosure, less when rays escape, are blocked or hit untagged surfaces)
+    pub fn row_sums(&self) -> Vec<f32> {
+        self.matrix.iter().map(|row| row.iter().sum()).collect()
+    }
+
+    /// Largest reciprocity error |A_i F_ij - A_j F_ji| / max(A_i F_ij, A_j F_ji) over the pairs of patches with a view factor
+    /// of at least `min_view_factor` (smaller view factors are dominated by sampling noise)
+    /// Returns the pair of patches and the relative error
+    pub fn max_reciprocity_error(&self, min_view_factor: f32) -> Option<(usize, usize, f32)> {
+        let n = self.matrix.len();
+        let mut max_error: Option<(usize, usize, f32)> = None;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                if self.matrix[i][j].max(self.matrix[j][i]) < min_view_factor {
+                    continue;
+                }
+                let a_f_ij = self.areas[i] * self.matrix[i][j];
+                let a_f_ji = self.areas[j] * self.matrix[j][i];
+                let error = (a_f_ij - a_f_ji).abs() / a_f_ij.max(a_f_ji);
+                if max_error.is_none_or(|(_, _, e)| error > e) {
+                    max_error = Some((i, j, error));
+                }
+            }
+        }
+        max_error
+    }
+}
+
+impl fmt::Display for ViewFactors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "View factors ({} rays per patch, row i: from patch i, column j: to patch j)",
+            self.rays_per_patch
+        )?;
+        for (i, name) in self.names.iter().enumerate() {
+            writeln!(f, "{:>3}: {} (area {:.6})", i, name, self.areas[i])?;
+        }
+        write!(f, "     ")?;
+        for j in 0..self.names.len() {
+            write!(f, " {:>7}", j)?;
+        }
+        writeln!(f, "      sum  blocked  untagged")?;
+        for (i, (row, sum)) in self.matrix.iter().zip(self.row_sums()).enumerate() {
+            write!(f, "{:>3}: ", i)?;
+            for value in row {
+                write!(f, " {:>7.4}", value)?;
+            }
+            writeln!(
+                f,
+                "  {:>7.4}  {:>7.4}  {:>8.4}",
+                sum, self.blocked[i], self.untagged[i]
+            )?;
+        }
+        if self.untagged.iter().any(|&u| u > 0.0) {
+            writeln!(
+                f,
+                "Warning: rays hit untagged surfaces, which are not part of the enclosure (tag them as patches to include them)"
+            )?;
+        }
+        match self.max_reciprocity_error(0.01) {
+            Some((i, j, error)) => writeln!(
+                f,
+                "Max reciprocity error (view factors >= 0.01): {:.2}% between {} and {}",
+                error * 100.0,
+                i,
+                j
+            ),
+            None => writeln!(f, "No view factors >= 0.01 to check reciprocity"),
+        }
+    }
+}
+
+/// Monte Carlo view factor solver, shooting cosine distributed rays from each patch through the scene bvh (CPU)
+pub struct ViewFactorSolver<'a> {
+    scene: &'a LinearSceneBvh,
+    patches: &'a [SurfacePatch],
+    areas: Vec<f32>,
+    /// Cumulative area of the triangles of each patch (empty for other surfaces), for area weighted sampling
+    triangle_cdfs: Vec<Vec<f32>>,
+    /// Patch index of each (geometry type, scene index, cuboid face)
+    patch_lookup: HashMap<(u32, u32, u32), usize>,
+}
+
+impl<'a> ViewFactorSolver<'a> {
+    pub fn new(scene: &'a LinearSceneBvh, patches: &'a [SurfacePatch]) -> Self {
+        let mut areas = vec![];
+        let mut triangle_cdfs = vec![];
+        let mut patch_lookup = HashMap::new();
+
+        for (i, patch) in patches.iter().enumerate() {
+            let mut cdf = vec![];
+            let area = match &patch.surface {
+                PatchSurface::CuboidFace { cuboid_index, face } => {
+                    patch_lookup.insert((2, *cuboid_index, *face), i);
+                    scene.cuboids[*cuboid_index as usize].face_area(*face)
+                }
+                PatchSurface::Sphere { sphere_index } => {
+                    patch_lookup.insert((1, *sphere_index, 0), i);
+                    scene.spheres[*sphere_index as usize].area()
+                }
+                PatchSurface::Triangles { triangle_indices } => {
+                    let mut total = 0.0;
+                    for triangle_index in triangle_indices {
+                        patch_lookup.insert((4, *triangle_index, 0), i);
+                        total += triangle_area(scene.instance_triangle_vertices(*triangle_index));
+                        cdf.push(total);
+                    }
+                    total
+                }
+            };
+            areas.push(area);
+            triangle_cdfs.push(cdf);
+        }
+
+        ViewFactorSolver {
+            scene,
+            patches,
+            areas,
+            triangle_cdfs,
+            patch_lookup,
+        }
+    }
+
+    /// Estimate the view factor matrix by shooting rays from each patch
+    pub fn solve(&self, rays_per_patch: u32) -> ViewFactors {
+        let n = self.patches.len();
+        let mut matrix = vec![vec![0.0; n]; n];
+        let mut blocked = vec![0.0; n];
+        let mut untagged = vec![0.0; n];
+
+        for (i, row) in matrix.iter_mut().enumerate() {
+            let mut hits = vec![0u32; n];
+            let mut blocked_hits = 0;
+            let mut untagged_hits = 0;
+            for _ in 0..rays_per_patch {
+                let (point, normal) = self.random_point(i);
+                let direction = util::random_cosine_direction(normal);
+                match self.trace(point + normal * SURFACE_OFFSET, direction) {
+                    RayEnd::Patch(j) => hits[j] += 1,
+                    RayEnd::Escaped => {}
+                    RayEnd::Blocked => blocked_hits += 1,
+                    RayEnd::Untagged => untagged_hits += 1,
+                }
+            }
+            for (f, h) in row.iter_mut().zip(hits) {
+                *f = h as f32 / rays_per_patch as f32;
+            }
+            blocked[i] = blocked_hits as f32 / rays_per_patch as f32;
+            untagged[i] = untagged_hits as f32 / rays_per_patch as f32;
+        }
+
+        ViewFactors {
+            names: self.patches.iter().map(|p| p.name.clone()).collect(),
+            areas: self.areas.clone(),
+            matrix,
+            blocked,
+            untagged,
+            rays_per_patch,
+        }
+    }
+
+    /// Patch receiving a ray (passing through the wos albedo blend sections)
+    fn trace(&self, origin: Point3<f32>, direction: Vector3<f32>) -> RayEnd {
+        let mut ray = Ray::new(origin, direction);
+        for _ in 0..MAX_SECTION_CROSSINGS {
+            let hit = match self.scene.scene_hits(&ray, SURFACE_OFFSET, f32::INFINITY) {
+                Some(hit) => hit,
+                None => return RayEnd::Escaped,
+            };
+            if self.scene.materials[hit.material_index as usize].material_type == 5 {
+                ray = Ray::new(hit.p, direction);
+                continue;
+            }
+            // Rays reaching the inside of a closed primitive (sphere or cuboid) started inside it, so can't be received
+            if hit.hittable.geometry_type != 4 && hit.normal.dot(direction) > 0.0 {
+                return RayEnd::Blocked;
+            }
+            return self.patch_at(&hit).map_or(RayEnd::Untagged, RayEnd::Patch);
+        }
+        RayEnd::Escaped
+    }
+
+    /// Patch containing the hit
+    fn patch_at(&self, hit: &HitRecord) -> Option<usize> {
+        let face = match hit.hittable.geometry_type {
+            2 => self.scene.cuboids[hit.hittable.get_scene_index()].face_at(hit.p),
+            _ => 0,
+        };
+        let scene_index = match hit.instance {
+            Some(instance) => self
+                .scene
+                .instance_triangle_index(instance, hit.hittable.get_scene_index()),
+            None => hit.hittable.scene_index,
+        };
+        self.patch_lookup
+            .get(&(hit.hittable.geometry_type, scene_index, face))
+            .copied()
+    }
+
+    /// Uniformly distributed random point on a patch and its outward normal
+    fn random_point(&self, patch_index: usize) -> (Point3<f32>, Vector3<f32>) {
+        match &self.patches[patch_index].surface {
+            PatchSurface::CuboidFace { cuboid_index, face } => {
+                self.scene.cuboids[*cuboid_index as usize].random_face_point(*face)
+            }
+            PatchSurface::Sphere { sphere_index } => {
+                self.scene.spheres[*sphere_index as usize].random_surface_point()
+            }
+            PatchSurface::Triangles { triangle_indices } => {
+                // Choose a triangle with probability proportional to its area
+                let cdf = &self.triangle_cdfs[patch_index];
+                let target = util::random_float() * self.areas[patch_index];
+                let k = cdf.partition_point(|&c| c < target).min(cdf.len() - 1);
+                random_triangle_point(self.scene.instance_triangle_vertices(triangle_indices[k]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Vector3};
+
+    use super::*;
+    use crate::monte_carlo::{
+        construction_scene, cuboid::Cuboid, hittable_primitive::HittablePrimitive,
+        material::Material,
+    };
+
+    fn plate(center: Vector3<f32>, scale: Vector3<f32>) -> HittablePrimitive {
+        HittablePrimitive::Cuboid(Cuboid::new(
+            Matrix4::from_translation(center)
+                * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z),
+            0,
+        ))
+    }
+
+    #[test]
+    fn test_parallel_and_perpendicular_plates() {
+        // Unit square plates: two parallel (facing each other at unit distance) and one perpendicular sharing an edge with the first
+        let t = 0.001;
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[
+                plate(Vector3::new(0.5, -t, 0.5), Vector3::new(0.5, t, 0.5)),
+                plate(Vector3::new(0.5, 1.0 + t, 0.5), Vector3::new(0.5, t, 0.5)),
+                plate(Vector3::new(-t, 0.5, 0.5), Vector3::new(t, 0.5, 0.5)),
+            ],
+        );
+        let patches = [
+            SurfacePatch::new(
+                "bottom",
+                PatchSurface::CuboidFace {
+                    cuboid_index: 0,
+                    face: 2,
+                },
+            ),
+            SurfacePatch::new(
+                "top",
+                PatchSurface::CuboidFace {
+                    cuboid_index: 1,
+                    face: 3,
+                },
+            ),
+            SurfacePatch::new(
+                "side",
+                PatchSurface::CuboidFace {
+                    cuboid_index: 2,
+                    face: 0,
+                },
+            ),
+        ];
+
+        let view_factors = ViewFactorSolver::new(&scene, &patches).solve(50000);
+        assert!((view_factors.areas[0] - 1.0).abs() < 1.0e-4);
+
+        // Analytic view factors: parallel aligned squares at unit distance 0.1998, perpendicular squares sharing an edge 0.2000
+        assert!((view_factors.matrix[0][1] - 0.1998).abs() < 0.01);
+        assert!((view_factors.matrix[1][0] - 0.1998).abs() < 0.01);
+        assert!((view_factors.matrix[0][2] - 0.2000).abs() < 0.01);
+        assert!((view_factors.matrix[2][0] - 0.2000).abs() < 0.01);
+        assert_eq!(view_factors.matrix[0][0], 0.0);
+
+        let (_, _, error) = view_factors.max_reciprocity_error(0.01).unwrap();
+        assert!(error < 0.1);
+        assert!(view_factors.untagged[0] < 0.01);
+
+        // Without the side patch, the rays reaching it are counted as hitting an untagged surface (not as escaping)
+        let view_factors = ViewFactorSolver::new(&scene, &patches[..2]).solve(50000);
+        assert!((view_factors.untagged[0] - 0.2000).abs() < 0.01);
+    }
+}
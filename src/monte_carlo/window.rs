@@ -12,4 +12,29 @@ impl Window {
             height_pixels: size.height,
         }
     }
+
+    /// Render resolution for a surface size and render scale (at least a pixel)
+    pub fn scaled(size: &winit::dpi::PhysicalSize<u32>, scale: f32) -> Self {
+        let scale_pixels = |pixels: u32| ((pixels as f32 * scale).round() as u32).max(1);
+        Window {
+            width_pixels: scale_pixels(size.width),
+            height_pixels: scale_pixels(size.height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaled() {
+        let size = winit::dpi::PhysicalSize::new(1280, 1023);
+        let half = Window::scaled(&size, 0.5);
+        assert_eq!((half.width_pixels, half.height_pixels), (640, 512));
+        let double = Window::scaled(&size, 2.0);
+        assert_eq!((double.width_pixels, double.height_pixels), (2560, 2046));
+        let tiny = Window::scaled(&winit::dpi::PhysicalSize::new(1, 1), 0.25);
+        assert_eq!((tiny.width_pixels, tiny.height_pixels), (1, 1));
+    }
 }
@@ -0,0 +1,591 @@
+use std::fmt;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix3, Point3, Vector3, Zero};
+
+use super::{conductivity_regions::ConductivityRegions, linear_scene_bvh::LinearSceneBvh, util};
+
+/// Walk on spheres configuration (CPU version of the wos fields of the constants)
+#[derive(Copy, Clone, Debug)]
+pub struct WosSettings {
+    /// Distance to surface before the walk is halted and surface sampled
+    pub tolerance: f32,
+    /// Maximum number of sphere steps in a single walk
+    pub max_steps: u32,
+    /// 0: Absolute epsilon shell (tolerance), 1: Relative epsilon shell (tolerance * distance to the boundary at the start of the walk)
+    pub epsilon_policy: u32,
+    /// 0: Exterior (outside of all primitives), 1: Interior (inside the closed primitives)
+    pub domain: u32,
+    /// Number of steps before russian roulette is applied to the walk (0: Off)
+    pub roulette_steps: u32,
+    /// Probability of a walk surviving each russian roulette step
+    pub roulette_survival: f32,
+    /// Radius of the far field boundary sphere around the origin (0: Off, i.e. at infinity)
+    pub far_field_radius: f32,
+    /// Boundary value on the far field boundary sphere (and of exterior walks that reach the maximum steps, i.e. the value at infinity)
+    pub far_field_value: Vector3<f32>,
+}
+
+impl Default for WosSettings {
+    fn default() -> Self {
+        WosSettings {
+            tolerance: 0.005,
+            max_steps: 32,
+            epsilon_policy: 0,
+            domain: 0,
+            roulette_steps: 0,
+            roulette_survival: 0.9,
+            far_field_radius: 0.0,
+            far_field_value: Vector3::zero(),
+        }
+    }
+}
+
+/// POD walk on spheres termination statistics ready to be read back from the GPU
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WosStats {
+    /// Total number of walks
+    pub walks: u32,
+    /// Walks that reached the maximum number of steps before reaching the boundary
+    pub unterminated: u32,
+    /// Walks terminated by russian roulette
+    pub roulette_terminated: u32,
+    /// Walks terminated on the far field boundary
+    pub far_field_terminated: u32,
+}
+
+impl fmt::Display for WosStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |count: u32| 100.0 * count as f32 / self.walks.max(1) as f32;
+        write!(
+            f,
+            "{} walks, {} unterminated ({:.3}%), {} roulette terminated ({:.3}%), {} far field terminated ({:.3}%)",
+            self.walks,
+            self.unterminated,
+            percent(self.unterminated),
+            self.roulette_terminated,
+            percent(self.roulette_terminated),
+            self.far_field_terminated,
+            percent(self.far_field_terminated),
+        )
+    }
+}
+
+/// Averaged result of a number of walks from a single point
+#[derive(Copy, Clone, Debug)]
+pub struct WosEstimate {
+    /// Estimate of the solution (boundary albedo blend) at the point
+    pub value: Vector3<f32>,
+    /// Estimate of the gradient of each value channel (columns are the x, y and z partial derivatives)
+    pub gradient: Option<Matrix3<f32>>,
+    /// Termination statistics of the walks
+    pub stats: WosStats,
+}
+
+///
+/// CPU walk on spheres solver for the Laplace equation (mirrors `wos` in the shader)
+/// The domain is either the space outside of the scene primitives or inside the closed primitives, with the material albedo of the closest primitive as the boundary value.
+/// Optional conductivity regions split the domain into piecewise constant conductivity media (div(k grad u) = 0), the sphere steps
+/// stop at the region interfaces and the walks cross them with the interface scheme of the regions.
+/// Optional boundary and source functions replace the material albedo boundary value and turn the problem into the Poisson equation.
+///
+pub struct WosSolver<'a> {
+    scene: &'a LinearSceneBvh,
+    settings: WosSettings,
+    regions: Option<&'a ConductivityRegions>,
+    boundary: Option<fn(Point3<f32>) -> f32>,
+    source: Option<fn(Point3<f32>) -> f32>,
+}
+
+impl<'a> WosSolver<'a> {
+    pub fn new(scene: &'a LinearSceneBvh, settings: WosSettings) -> Self {
+        WosSolver {
+            scene,
+            settings,
+            regions: None,
+            boundary: None,
+            source: None,
+        }
+    }
+
+    /// Solve over conductivity regions instead of a single homogeneous medium
+    pub fn with_regions(mut self, regions: &'a ConductivityRegions) -> Self {
+        self.regions = Some(regions);
+        self
+    }
+
+    /// Use a scalar function of the boundary point (in all value channels) as the boundary value of the scene primitives
+    pub fn with_boundary(mut self, boundary: fn(Point3<f32>) -> f32) -> Self {
+        self.boundary = Some(boundary);
+        self
+    }
+
+    /// Solve the Poisson equation -div(k grad u) = f with the source f (in all value channels) instead of the Laplace equation
+    pub fn with_source(mut self, source: fn(Point3<f32>) -> f32) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Value on the boundary at the point (the far field value, the boundary function or the closest material albedo)
+    fn boundary_value(
+        &self,
+        point: Point3<f32>,
+        material_index: Option<u32>,
+        far_field: bool,
+    ) -> Vector3<f32> {
+        if far_field {
+            return self.settings.far_field_value;
+        }
+        if let Some(boundary) = self.boundary {
+            let value = boundary(point);
+            return Vector3::new(value, value, value);
+        }
+        material_index.map_or(Vector3::zero(), |i| {
+            self.scene.materials[i as usize].albedo.into()
+        })
+    }
+
+    /// Single sample of the source contribution of a sphere step, the integral of the sphere's Green's function times the source.
+    /// The sample point is importance sampled from the Green's function G(r) = (1/r - 1/R) / (4 pi) (r/R is Beta(2, 2) distributed,
+    /// the median of three uniform samples), so the sample is the integral of G (R^2 / 6) times the source over the conductivity.
+    fn source_sample(&self, center: Point3<f32>, radius: f32) -> Vector3<f32> {
+        let source = match self.source {
+            Some(source) => source,
+            None => return Vector3::zero(),
+        };
+        let mut u = [
+            util::random_float(),
+            util::random_float(),
+            util::random_float(),
+        ];
+        u.sort_by(f32::total_cmp);
+        let point = center + radius * u[1] * util::random_unit_vector();
+        let conductivity = self
+            .regions
+            .map_or(1.0, |regions| regions.conductivity(point));
+        let value = radius * radius / 6.0 * source(point) / conductivity;
+        Vector3::new(value, value, value)
+    }
+
+    /// Distance to the closest region interface (infinite without regions)
+    fn interface_distance(&self, point: Point3<f32>) -> f32 {
+        self.regions
+            .map_or(f32::INFINITY, |regions| regions.interface_distance(point))
+    }
+
+    /// Distance from the point to the domain boundary (positive inside the domain, negative outside), the closest material index
+    /// and whether the far field boundary is the closest.
+    pub fn boundary_distance(&self, point: Point3<f32>) -> (f32, Option<u32>, bool) {
+        let (mut dist, material_index) = self.scene.scene_sd(point);
+        if self.settings.domain == 1 {
+            dist = -dist;
+        }
+
+        // Optional far field boundary (sphere around the origin) for exterior problems
+        if self.settings.far_field_radius > 0.0 {
+            let far_dist = self.settings.far_field_radius - point.to_vec().magnitude();
+            if far_dist < dist {
+                return (far_dist, material_index, true);
+            }
+        }
+        (dist, material_index, false)
+    }
+
+    /// Epsilon shell of a walk starting at the distance from the boundary (absolute tolerance or relative to the starting distance)
+    fn epsilon(&self, start_dist: f32) -> f32 {
+        if self.settings.epsilon_policy == 1 {
+            self.settings.tolerance * start_dist.max(0.0)
+        } else {
+            self.settings.tolerance
+        }
+    }
+
+    /// Is the point inside the domain of the walk
+    pub fn in_domain(&self, point: Point3<f32>) -> bool {
+        self.scene.is_inside(point) == (self.settings.domain == 1)
+    }
+
+    /// Walk on spheres from the point and return the boundary value where the walk halted (weighted by the russian roulette survival)
+    /// plus the source contributions of each sphere step. Walks that never reach the boundary take the far field value (the boundary at infinity)
+    /// on exterior problems, interior problems have no far field so they take the closest boundary value.
+    pub fn walk(&self, point: Point3<f32>, stats: &mut WosStats) -> Vector3<f32> {
+        let mut curr_point = point;
+        let mut closest_material_index = None;
+        let mut source = Vector3::zero();
+        let mut weight = 1.0;
+        let mut epsilon = self.settings.tolerance;
+        stats.walks += 1;
+        for i in 0..self.settings.max_steps {
+            let (dist, material_index, far_field) = self.boundary_distance(curr_point);
+            if material_index.is_some() {
+                closest_material_index = material_index;
+            }
+            if i == 0 {
+                epsilon = self.epsilon(dist);
+            }
+            if dist < epsilon {
+                if far_field {
+                    stats.far_field_terminated += 1;
+                }
+                let value = self.boundary_value(curr_point, closest_material_index, far_field);
+                return value * weight + source;
+            }
+
+            // Cross conductivity interfaces, otherwise the sphere step is limited to the current region
+            let interface_dist = self.interface_distance(curr_point);
+            if interface_dist < self.settings.tolerance {
+                curr_point = self.regions.unwrap().cross_interface(curr_point, dist);
+                continue;
+            }
+            let step = dist.min(interface_dist);
+
+            // Russian roulette
+            if self.settings.roulette_steps > 0 && i >= self.settings.roulette_steps {
+                if util::random_float() > self.settings.roulette_survival {
+                    stats.roulette_terminated += 1;
+                    return source;
+                }
+                weight /= self.settings.roulette_survival;
+            }
+
+            source += weight * self.source_sample(curr_point, step);
+            curr_point += step * util::random_unit_vector();
+        }
+        stats.unterminated += 1;
+        let value = if self.settings.domain == 0 {
+            self.settings.far_field_value
+        } else {
+            self.boundary_value(curr_point, closest_material_index, false)
+        };
+        value * weight + source
+    }
+
+    /// Single sample of the value and gradient, estimated from the first sphere step.
+    /// Uses grad u(x) = 3/R * E[u(x + R v) v] over uniform directions v, with an antithetic pair of walks from x + R v and x - R v.
+    /// The source contribution of the first sphere is included in the value but not in the gradient.
+    pub fn walk_with_gradient(
+        &self,
+        point: Point3<f32>,
+        stats: &mut WosStats,
+    ) -> (Vector3<f32>, Matrix3<f32>) {
+        let (dist, material_index, far_field) = self.boundary_distance(point);
+        // Absolute tolerance whatever the epsilon policy (a shell relative to the distance itself would never contain the point)
+        if dist < self.settings.tolerance {
+            // Already on the boundary, the gradient can't be estimated from a sphere step
+            return (
+                self.boundary_value(point, material_index, far_field),
+                Matrix3::zero(),
+            );
+        }
+
+        // The first sphere has to be within a single conductivity region
+        let interface_dist = self.interface_distance(point);
+        if interface_dist < self.settings.tolerance {
+            return (self.walk(point, stats), Matrix3::zero());
+        }
+        let dist = dist.min(interface_dist);
+
+        let dir = util::random_unit_vector();
+        let value_pos = self.walk(point + dist * dir, stats);
+        let value_neg = self.walk(point - dist * dir, stats);
+
+        let delta = (value_pos - value_neg) * (1.5 / dist);
+        (
+            (value_pos + value_neg) * 0.5 + self.source_sample(point, dist),
+            Matrix3::from_cols(delta * dir.x, delta * dir.y, delta * dir.z),
+        )
+    }
+
+    /// Average a number of walks from the point, optionally estimating the gradient too
+    pub fn solve(&self, point: Point3<f32>, walks: u32, with_gradient: bool) -> WosEstimate {
+        let mut stats = WosStats::default();
+        let mut value = Vector3::zero();
+        let mut gradient = Matrix3::zero();
+        for _ in 0..walks {
+            if with_gradient {
+                let (sample_value, sample_gradient) = self.walk_with_gradient(point, &mut stats);
+                value += sample_value;
+                gradient += sample_gradient;
+            } else {
+                value += self.walk(point, &mut stats);
+            }
+        }
+
+        let inv_walks = 1.0 / walks.max(1) as f32;
+        WosEstimate {
+            value: value * inv_walks,
+            gradient: if with_gradient {
+                Some(gradient * inv_walks)
+            } else {
+                None
+            },
+            stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monte_carlo::{
+        conductivity_regions::ConductivityRegions, construction_scene, cuboid::Cuboid,
+        hittable_primitive::HittablePrimitive, material::Material, sphere::Sphere,
+    };
+    use cgmath::Matrix4;
+
+    #[test]
+    fn test_constant_boundary() {
+        let scene = construction_scene::build_from_spheres(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(0.3, 0.6, 0.9))],
+            &[Sphere::new(Vector3::new(0.0, 0.0, 0.0), 1.0, 0)],
+        );
+        // The same value at infinity (where unterminated walks end), so the solution is constant
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                far_field_value: Vector3::new(0.3, 0.6, 0.9),
+                ..WosSettings::default()
+            },
+        );
+        let estimate = solver.solve(Point3::new(1.5, 0.0, 0.0), 100, true);
+
+        assert!((estimate.value - Vector3::new(0.3, 0.6, 0.9)).magnitude2() < 1.0e-10);
+        assert_eq!(estimate.gradient, Some(Matrix3::zero()));
+    }
+
+    #[test]
+    fn test_parallel_plates_gradient() {
+        // Two large plates at z = -1 (value 0) and z = 1 (value 1), the solution between them is u = (z + 1) / 2
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[
+                Material::new(0, 0.0, 0.0, Vector3::new(0.0, 0.0, 0.0)),
+                Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0)),
+            ],
+            &[
+                HittablePrimitive::Cuboid(Cuboid::new(
+                    Matrix4::from_translation(Vector3::new(0.0, 0.0, -1.1))
+                        * Matrix4::from_nonuniform_scale(100.0, 100.0, 0.1),
+                    0,
+                )),
+                HittablePrimitive::Cuboid(Cuboid::new(
+                    Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.1))
+                        * Matrix4::from_nonuniform_scale(100.0, 100.0, 0.1),
+                    1,
+                )),
+            ],
+        );
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                tolerance: 0.001,
+                max_steps: 128,
+                ..WosSettings::default()
+            },
+        );
+        let estimate = solver.solve(Point3::new(0.0, 0.0, 0.0), 20000, true);
+        let gradient = estimate.gradient.unwrap();
+
+        assert!((estimate.value.x - 0.5).abs() < 0.03);
+        assert!((gradient.z.x - 0.5).abs() < 0.05);
+        assert!(gradient.x.x.abs() < 0.05);
+        assert!(gradient.y.x.abs() < 0.05);
+    }
+
+    #[test]
+    fn test_conductivity_regions() {
+        // Plates at z = -1 (value 0) and z = 1 (value 1) with conductivity 4 for z < 0 and 1 above, flux continuity gives the
+        // interface value 1/5 and a piecewise linear solution u = z/5 + 1/5 (z < 0), u = 4z/5 + 1/5 (z > 0)
+        let plate = |z: f32, material_index: u32| {
+            HittablePrimitive::Cuboid(Cuboid::new(
+                Matrix4::from_translation(Vector3::new(0.0, 0.0, z))
+                    * Matrix4::from_nonuniform_scale(100.0, 100.0, 0.1),
+                material_index,
+            ))
+        };
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[
+                Material::new(0, 0.0, 0.0, Vector3::new(0.0, 0.0, 0.0)),
+                Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0)),
+            ],
+            &[plate(-1.1, 0), plate(1.1, 1)],
+        );
+        let regions = ConductivityRegions::new(
+            construction_scene::build_from_hittable_primitives(
+                &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+                &[HittablePrimitive::Cuboid(Cuboid::new(
+                    Matrix4::from_translation(Vector3::new(0.0, 0.0, -1.0))
+                        * Matrix4::from_nonuniform_scale(100.0, 100.0, 1.0),
+                    0,
+                ))],
+            ),
+            vec![4.0],
+            1.0,
+            0.01,
+        );
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                tolerance: 0.001,
+                max_steps: 8192,
+                ..WosSettings::default()
+            },
+        )
+        .with_regions(&regions);
+
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, -0.5)), 4.0);
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, 0.5)), 1.0);
+        for (z, expected) in [(-0.5, 0.1), (0.0, 0.2), (0.5, 0.6)] {
+            let estimate = solver.solve(Point3::new(0.0, 0.0, z), 5000, false);
+            assert!((estimate.value.x - expected).abs() < 0.03);
+            assert!(estimate.stats.unterminated < 50);
+        }
+    }
+
+    #[test]
+    fn test_nested_conductivity_regions() {
+        // Plates at z = -1 (value 0) and z = 1 (value 1) around a housing slab (-0.8 < z < 0.4, conductivity 4) containing a core
+        // slab (-0.4 < z < 0, conductivity 16), the solution is linear in the resistance (length / conductivity) from the lower plate
+        let slab = |z: f32, half_thickness: f32, material_index: u32| {
+            HittablePrimitive::Cuboid(Cuboid::new(
+                Matrix4::from_translation(Vector3::new(0.0, 0.0, z))
+                    * Matrix4::from_nonuniform_scale(100.0, 100.0, half_thickness),
+                material_index,
+            ))
+        };
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[
+                Material::new(0, 0.0, 0.0, Vector3::new(0.0, 0.0, 0.0)),
+                Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0)),
+            ],
+            &[slab(-1.1, 0.1, 0), slab(1.1, 0.1, 1)],
+        );
+        let regions = ConductivityRegions::new(
+            construction_scene::build_from_hittable_primitives(
+                &[
+                    Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0)),
+                    Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0)),
+                ],
+                &[slab(-0.2, 0.6, 0), slab(-0.2, 0.2, 1)],
+            ),
+            vec![4.0, 16.0],
+            1.0,
+            0.02,
+        );
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                tolerance: 0.001,
+                max_steps: 32768,
+                ..WosSettings::default()
+            },
+        )
+        .with_regions(&regions);
+
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, -0.9)), 1.0);
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, -0.6)), 4.0);
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, -0.2)), 16.0);
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, 0.2)), 4.0);
+        assert_eq!(regions.conductivity(Point3::new(0.0, 0.0, 0.7)), 1.0);
+
+        // Total resistance 0.2 + 0.1 + 0.025 + 0.1 + 0.6 = 1.025
+        for (z, resistance) in [(-0.6, 0.25), (-0.2, 0.3125), (0.2, 0.375), (0.7, 0.725)] {
+            let estimate = solver.solve(Point3::new(0.0, 0.0, z), 2000, false);
+            assert!((estimate.value.x - resistance / 1.025).abs() < 0.035);
+            assert!(estimate.stats.unterminated < 50);
+        }
+    }
+
+    #[test]
+    fn test_interior_domain() {
+        // Inside a sphere the walks only ever reach the sphere boundary, outside the domain walks halt immediately
+        let scene = construction_scene::build_from_spheres(
+            &[
+                Material::new(0, 0.0, 0.0, Vector3::new(0.2, 0.4, 0.6)),
+                Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0)),
+            ],
+            &[
+                Sphere::new(Vector3::new(0.0, 0.0, 0.0), 1.0, 0),
+                Sphere::new(Vector3::new(3.0, 0.0, 0.0), 0.5, 1),
+            ],
+        );
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                max_steps: 64,
+                domain: 1,
+                ..WosSettings::default()
+            },
+        );
+
+        assert!(solver.in_domain(Point3::new(0.5, 0.0, 0.0)));
+        assert!(!solver.in_domain(Point3::new(1.5, 0.0, 0.0)));
+        let estimate = solver.solve(Point3::new(0.5, 0.0, 0.0), 100, false);
+        assert!((estimate.value - Vector3::new(0.2, 0.4, 0.6)).magnitude2() < 1.0e-10);
+
+        // Walks cut short by the step limit take the closest boundary value (there is no far field inside), so no bias towards 0
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                max_steps: 2,
+                domain: 1,
+                ..WosSettings::default()
+            },
+        );
+        let estimate = solver.solve(Point3::new(0.5, 0.0, 0.0), 100, false);
+        assert!(estimate.stats.unterminated > 0);
+        assert!((estimate.value - Vector3::new(0.2, 0.4, 0.6)).magnitude2() < 1.0e-10);
+
+        // On the boundary with the relative epsilon policy, the gradient walk returns the boundary value (not an infinite gradient)
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                epsilon_policy: 1,
+                domain: 1,
+                ..WosSettings::default()
+            },
+        );
+        let estimate = solver.solve(Point3::new(1.0, 0.0, 0.0), 10, true);
+        assert!((estimate.value - Vector3::new(0.2, 0.4, 0.6)).magnitude2() < 1.0e-10);
+        assert_eq!(estimate.gradient, Some(Matrix3::zero()));
+    }
+
+    #[test]
+    fn test_far_field_and_roulette() {
+        // Outside a unit sphere (value 1) with the far field (value 0) at radius 4, the solution is u = (1/r - 1/4) / (1 - 1/4)
+        let scene = construction_scene::build_from_spheres(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[Sphere::new(Vector3::new(0.0, 0.0, 0.0), 1.0, 0)],
+        );
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                tolerance: 0.001,
+                max_steps: 1024,
+                epsilon_policy: 1,
+                roulette_steps: 64,
+                roulette_survival: 0.95,
+                far_field_radius: 4.0,
+                ..WosSettings::default()
+            },
+        );
+        let estimate = solver.solve(Point3::new(2.0, 0.0, 0.0), 20000, false);
+
+        assert!((estimate.value.x - 1.0 / 3.0).abs() < 0.03);
+        assert_eq!(estimate.stats.walks, 20000);
+        assert!(estimate.stats.far_field_terminated > 0);
+        assert_eq!(estimate.stats.unterminated, 0);
+
+        // Walks cut short by the step limit take the far field value, not the value of the closest boundary
+        let solver = WosSolver::new(
+            &scene,
+            WosSettings {
+                max_steps: 1,
+                far_field_value: Vector3::new(0.25, 0.25, 0.25),
+                ..WosSettings::default()
+            },
+        );
+        let estimate = solver.solve(Point3::new(2.0, 0.0, 0.0), 100, false);
+        assert_eq!(estimate.stats.unterminated, 100);
+        assert_eq!(estimate.value, Vector3::new(0.25, 0.25, 0.25));
+    }
+}
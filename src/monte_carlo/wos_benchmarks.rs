@@ -0,0 +1,229 @@
+use std::fmt;
+
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
+
+use super::{
+    construction_scene,
+    linear_scene_bvh::LinearSceneBvh,
+    material::Material,
+    sphere::Sphere,
+    wos::{WosSettings, WosSolver, WosStats},
+};
+
+/// Walk on spheres problem with a closed form solution, for checking the CPU solver (and its convergence) against
+pub struct BenchmarkProblem {
+    pub name: &'static str,
+    pub scene: LinearSceneBvh,
+    pub settings: WosSettings,
+    /// Boundary value function (None: material albedo)
+    pub boundary: Option<fn(Point3<f32>) -> f32>,
+    /// Source of the Poisson equation (None: Laplace equation)
+    pub source: Option<fn(Point3<f32>) -> f32>,
+    /// Closed form solution
+    pub exact: fn(Point3<f32>) -> f32,
+    /// Points in the domain the solution is compared at
+    pub probes: Vec<Point3<f32>>,
+}
+
+impl BenchmarkProblem {
+    /// Harmonic function u = 1 + x^2 - y^2 + xz + y/2 as the boundary value on the inside of the unit sphere
+    pub fn harmonic_sphere() -> Self {
+        BenchmarkProblem {
+            name: "harmonic function in a sphere",
+            scene: sphere_scene(1.0, Vector3::new(0.0, 0.0, 0.0)),
+            settings: WosSettings {
+                tolerance: 0.001,
+                max_steps: 256,
+                domain: 1,
+                ..WosSettings::default()
+            },
+            boundary: Some(|p| 1.0 + p.x * p.x - p.y * p.y + p.x * p.z + 0.5 * p.y),
+            source: None,
+            exact: |p| 1.0 + p.x * p.x - p.y * p.y + p.x * p.z + 0.5 * p.y,
+            probes: probe_points(0.1, 0.8),
+        }
+    }
+
+    /// Potential between concentric spheres, radius 0.5 at 1 and the far field sphere radius 2 at 0, u = (1/r - 1/2) / (2 - 1/2)
+    pub fn concentric_spheres() -> Self {
+        BenchmarkProblem {
+            name: "concentric spheres potential",
+            scene: sphere_scene(0.5, Vector3::new(1.0, 1.0, 1.0)),
+            settings: WosSettings {
+                tolerance: 0.001,
+                max_steps: 256,
+                far_field_radius: 2.0,
+                ..WosSettings::default()
+            },
+            boundary: None,
+            source: None,
+            exact: |p| (1.0 / p.to_vec().magnitude() - 0.5) / 1.5,
+            probes: probe_points(0.6, 1.8),
+        }
+    }
+
+    /// Poisson equation -laplacian(u) = 6 inside the unit sphere with u = 0 on the boundary, u = 1 - r^2
+    pub fn poisson_sphere() -> Self {
+        BenchmarkProblem {
+            name: "poisson with a uniform source in a sphere",
+            scene: sphere_scene(1.0, Vector3::new(0.0, 0.0, 0.0)),
+            settings: WosSettings {
+                tolerance: 0.001,
+                max_steps: 256,
+                domain: 1,
+                ..WosSettings::default()
+            },
+            boundary: None,
+            source: Some(|_| 6.0),
+            exact: |p| 1.0 - p.to_vec().magnitude2(),
+            probes: probe_points(0.0, 0.8),
+        }
+    }
+
+    /// All the benchmark problems
+    pub fn all() -> Vec<BenchmarkProblem> {
+        vec![
+            BenchmarkProblem::harmonic_sphere(),
+            BenchmarkProblem::concentric_spheres(),
+            BenchmarkProblem::poisson_sphere(),
+        ]
+    }
+
+    pub fn solver(&self) -> WosSolver<'_> {
+        let mut solver = WosSolver::new(&self.scene, self.settings);
+        if let Some(boundary) = self.boundary {
+            solver = solver.with_boundary(boundary);
+        }
+        if let Some(source) = self.source {
+            solver = solver.with_source(source);
+        }
+        solver
+    }
+
+    /// Root mean square error (first value channel) of estimates with a number of walks over the probe points (each repeated)
+    pub fn rmse(&self, walks: u32, repeats: u32, stats: &mut WosStats) -> f32 {
+        let solver = self.solver();
+        let mut sum = 0.0;
+        for probe in &self.probes {
+            for _ in 0..repeats {
+                let estimate = solver.solve(*probe, walks, false);
+                sum += (estimate.value.x - (self.exact)(*probe)).powi(2);
+                stats.walks += estimate.stats.walks;
+                stats.unterminated += estimate.stats.unterminated;
+                stats.roulette_terminated += estimate.stats.roulette_terminated;
+                stats.far_field_terminated += estimate.stats.far_field_terminated;
+            }
+        }
+        (sum / (self.probes.len() as u32 * repeats) as f32).sqrt()
+    }
+
+    /// Error against each walk count
+    pub fn run(&self, walk_counts: &[u32], repeats: u32) -> BenchmarkReport {
+        let mut stats = WosStats::default();
+        let rmse = walk_counts
+            .iter()
+            .map(|&walks| self.rmse(walks, repeats, &mut stats))
+            .collect();
+        BenchmarkReport {
+            name: self.name,
+            walk_counts: walk_counts.to_vec(),
+            rmse,
+            stats,
+        }
+    }
+}
+
+/// Errors of a benchmark problem against the number of walks
+pub struct BenchmarkReport {
+    pub name: &'static str,
+    pub walk_counts: Vec<u32>,
+    pub rmse: Vec<f32>,
+    pub stats: WosStats,
+}
+
+impl BenchmarkReport {
+    /// Least squares slope of log(rmse) against log(walks), -0.5 for the expected O(1/sqrt(N)) Monte Carlo convergence
+    pub fn convergence_rate(&self) -> f32 {
+        let points: Vec<(f32, f32)> = self
+            .walk_counts
+            .iter()
+            .zip(&self.rmse)
+            .map(|(&n, &e)| ((n as f32).ln(), e.ln()))
+            .collect();
+        let count = points.len() as f32;
+        let mean_x = points.iter().map(|p| p.0).sum::<f32>() / count;
+        let mean_y = points.iter().map(|p| p.1).sum::<f32>() / count;
+        let covariance: f32 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let variance: f32 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        covariance / variance
+    }
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Benchmark: {}", self.name)?;
+        for (walks, rmse) in self.walk_counts.iter().zip(&self.rmse) {
+            writeln!(f, "{:>8} walks: rmse {:.6}", walks, rmse)?;
+        }
+        writeln!(
+            f,
+            "Convergence rate: {:.3} (expected -0.5)",
+            self.convergence_rate()
+        )?;
+        writeln!(f, "Stats: {}", self.stats)
+    }
+}
+
+/// Scene of a single sphere around the origin with the albedo
+fn sphere_scene(radius: f32, albedo: Vector3<f32>) -> LinearSceneBvh {
+    construction_scene::build_from_spheres(
+        &[Material::new(0, 0.0, 0.0, albedo)],
+        &[Sphere::new(Vector3::new(0.0, 0.0, 0.0), radius, 0)],
+    )
+}
+
+/// Probe points spread over radii in the range (along different directions)
+fn probe_points(min_radius: f32, max_radius: f32) -> Vec<Point3<f32>> {
+    let directions = [
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::new(1.0, 1.0, 0.0),
+        Vector3::new(-1.0, 0.0, 1.0),
+        Vector3::new(1.0, -1.0, 1.0),
+    ];
+    directions
+        .iter()
+        .enumerate()
+        .map(|(i, d)| {
+            let t = i as f32 / (directions.len() - 1) as f32;
+            Point3::from_vec(d.normalize() * (min_radius + t * (max_radius - min_radius)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmarks_converge() {
+        for problem in BenchmarkProblem::all() {
+            let report = problem.run(&[16, 64, 256, 1024], 8);
+            let rate = report.convergence_rate();
+            assert!(
+                (rate + 0.5).abs() < 0.2,
+                "{}: convergence rate {}",
+                report.name,
+                rate
+            );
+            assert!(
+                report.rmse[3] < 0.03,
+                "{}: rmse {}",
+                report.name,
+                report.rmse[3]
+            );
+            assert_eq!(report.stats.unterminated, 0);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::f64::consts::PI;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{
+    linear_scene_bvh::LinearSceneBvh,
+    material::Material,
+    util,
+    wos::{WosSettings, WosSolver, WosStats},
+};
+
+/// Below this dimensionless time (diffusivity * time / radius^2) the walk is so unlikely to leave the sphere that the position
+/// at the end of the time is sampled from a free gaussian (rejecting points outside of the sphere)
+const GAUSSIAN_DIMENSIONLESS_TIME: f64 = 0.02;
+
+/// Number of bisection steps when inverting the exit time and radial position distributions
+const BISECTION_STEPS: u32 = 48;
+
+/// Transient heat conduction problem du/dt = diffusivity * laplacian(u) over the walk on spheres domain
+#[derive(Copy, Clone)]
+pub struct TransientProblem {
+    /// Thermal diffusivity (m^2/s)
+    pub diffusivity: f32,
+    /// Temperature at time 0 at a point in the domain
+    pub initial: fn(Point3<f32>) -> f32,
+    /// Temperature on the boundary of the closest material (None on the far field boundary) at a point and time
+    pub boundary: fn(Option<&Material>, Point3<f32>, f32) -> f32,
+}
+
+/// Averaged result of a number of space-time walks from a single point and time
+#[derive(Copy, Clone, Debug)]
+pub struct TransientEstimate {
+    pub time: f32,
+    pub value: f32,
+    /// Termination statistics of the walks (unterminated walks use the initial temperature at the point they stopped)
+    pub stats: WosStats,
+}
+
+///
+/// CPU space-time walk on spheres solver for the heat equation, using the same bvh distance queries (`scene_sd`) as the Laplace solver.
+/// Each sphere step samples the exit time of a brownian motion from the sphere, the walk ends on the boundary (with the boundary
+/// temperature at the remaining time) or, when the remaining time runs out inside a sphere, at the initial temperature of a point
+/// sampled from the (surviving) brownian motion distribution in the sphere.
+///
+pub struct TransientWosSolver<'a> {
+    scene: &'a LinearSceneBvh,
+    wos: WosSolver<'a>,
+    settings: WosSettings,
+    problem: TransientProblem,
+}
+
+impl<'a> TransientWosSolver<'a> {
+    pub fn new(
+        scene: &'a LinearSceneBvh,
+        settings: WosSettings,
+        problem: TransientProblem,
+    ) -> Self {
+        TransientWosSolver {
+            scene,
+            wos: WosSolver::new(scene, settings),
+            settings,
+            problem,
+        }
+    }
+
+    /// Space-time walk from the point at the time, returns the temperature sample
+    pub fn walk(&self, point: Point3<f32>, time: f32, stats: &mut WosStats) -> f32 {
+        let mut curr_point = point;
+        let mut remaining = time.max(0.0) as f64;
+        let mut epsilon = self.settings.tolerance;
+        let diffusivity = self.problem.diffusivity as f64;
+        stats.walks += 1;
+        for i in 0..self.settings.max_steps {
+            let (dist, material_index, far_field) = self.wos.boundary_distance(curr_point);
+            if i == 0 && self.settings.epsilon_policy == 1 {
+                epsilon = self.settings.tolerance * dist.max(0.0);
+            }
+            if dist < epsilon {
+                let material = if far_field {
+                    stats.far_field_terminated += 1;
+                    None
+                } else {
+                    material_index.map(|i| &self.scene.materials[i as usize])
+                };
+                return (self.problem.boundary)(material, curr_point, remaining as f32);
+            }
+
+            let radius = dist as f64;
+            let max_a = diffusivity * remaining / (radius * radius);
+            let u = util::random_float() as f64;
+            if u < ball_survival(max_a) {
+                // The remaining time runs out inside the sphere
+                let offset = sample_surviving_offset(max_a) * dist;
+                return (self.problem.initial)(curr_point + offset);
+            }
+
+            // Exit the sphere (uniformly on its surface) at the sampled exit time
+            let exit_a = bisect(0.0, max_a, |a| ball_survival(a) > u);
+            remaining = (remaining - exit_a * radius * radius / diffusivity).max(0.0);
+            curr_point += dist * util::random_unit_vector();
+        }
+        stats.unterminated += 1;
+        (self.problem.initial)(curr_point)
+    }
+
+    /// Average a number of walks from the point for each of the times
+    pub fn solve(&self, point: Point3<f32>, times: &[f32], walks: u32) -> Vec<TransientEstimate> {
+        times
+            .iter()
+            .map(|&time| {
+                let mut stats = WosStats::default();
+                let sum: f32 = (0..walks).map(|_| self.walk(point, time, &mut stats)).sum();
+                TransientEstimate {
+                    time,
+                    value: sum / walks.max(1) as f32,
+                    stats,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Probability a brownian motion started at the centre of a sphere hasn't left it by the dimensionless time a = diffusivity * t / radius^2
+/// Uses the small time (image) series for small a and the eigenfunction series otherwise, both converge quickly in their range.
+fn ball_survival(a: f64) -> f64 {
+    if a <= 0.0 {
+        return 1.0;
+    }
+    if a < 0.1 {
+        // 1 - 2 / sqrt(pi a) sum_m exp(-(m + 1/2)^2 / a)
+        let sum: f64 = (0..8).map(|m| (-(m as f64 + 0.5).powi(2) / a).exp()).sum();
+        (1.0 - 2.0 / (PI * a).sqrt() * sum).clamp(0.0, 1.0)
+    } else {
+        // sum_n 2 (-1)^(n + 1) exp(-(n pi)^2 a)
+        let sum: f64 = (1..32)
+            .map(|n| {
+                let sign = if n % 2 == 1 { 2.0 } else { -2.0 };
+                sign * (-(n as f64 * PI).powi(2) * a).exp()
+            })
+            .sum();
+        sum.clamp(0.0, 1.0)
+    }
+}
+
+/// Probability a brownian motion started at the centre of a unit sphere is within radius s at the dimensionless time a (and hasn't left the sphere)
+fn ball_radial_cdf(a: f64, s: f64) -> f64 {
+    // sum_n 2 exp(-(n pi)^2 a) (sin(n pi s) / (n pi) - s cos(n pi s))
+    let mut sum = 0.0;
+    for n in 1..256 {
+        let k = n as f64 * PI;
+        let decay = 2.0 * (-k * k * a).exp();
+        if decay < 1.0e-14 {
+            break;
+        }
+        sum += decay * ((k * s).sin() / k - s * (k * s).cos());
+    }
+    sum
+}
+
+/// Offset from the centre of a unit sphere of a brownian motion at the dimensionless time a, given it hasn't left the sphere
+fn sample_surviving_offset(a: f64) -> Vector3<f32> {
+    if a < GAUSSIAN_DIMENSIONLESS_TIME {
+        let sigma = (2.0 * a).sqrt() as f32;
+        loop {
+            let offset = sigma
+                * Vector3::new(
+                    util::random_normal(),
+                    util::random_normal(),
+                    util::random_normal(),
+                );
+            if offset.magnitude2() < 1.0 {
+                return offset;
+            }
+        }
+    }
+    let target = util::random_float() as f64 * ball_radial_cdf(a, 1.0);
+    let s = bisect(0.0, 1.0, |s| ball_radial_cdf(a, s) < target);
+    s as f32 * util::random_unit_vector()
+}
+
+/// Point where a monotonic condition switches from true (below) to false (above) in the range
+fn bisect(mut low: f64, mut high: f64, below: impl Fn(f64) -> bool) -> f64 {
+    for _ in 0..BISECTION_STEPS {
+        let mid = 0.5 * (low + high);
+        if below(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    0.5 * (low + high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monte_carlo::{
+        construction_scene, cuboid::Cuboid, hittable_primitive::HittablePrimitive,
+    };
+    use cgmath::Matrix4;
+
+    #[test]
+    fn test_ball_survival_series() {
+        // The small time and eigenfunction series agree where they switch over, and the radial distribution ends at the survival
+        let small: f64 = 1.0
+            - 2.0 / (PI * 0.1).sqrt()
+                * (0..8)
+                    .map(|m| (-(m as f64 + 0.5).powi(2) / 0.1).exp())
+                    .sum::<f64>();
+        assert!((small - ball_survival(0.1)).abs() < 1.0e-9);
+        assert!((ball_radial_cdf(0.05, 1.0) - ball_survival(0.05)).abs() < 1.0e-9);
+        assert!((ball_survival(1.0e-4) - 1.0).abs() < 1.0e-9);
+        assert!(ball_survival(1.0) < 1.0e-3);
+    }
+
+    #[test]
+    fn test_slab_heating() {
+        // Slab between plates at z = -1 and z = 1 suddenly held at 1 (initially 0), the temperature at the centre is
+        // u(0, t) = 1 - 4/pi sum_m (-1)^m / (2m + 1) exp(-diffusivity ((2m + 1) pi / 2)^2 t)
+        let plate = |z: f32| {
+            HittablePrimitive::Cuboid(Cuboid::new(
+                Matrix4::from_translation(Vector3::new(0.0, 0.0, z))
+                    * Matrix4::from_nonuniform_scale(100.0, 100.0, 0.1),
+                0,
+            ))
+        };
+        let scene = construction_scene::build_from_hittable_primitives(
+            &[Material::new(0, 0.0, 0.0, Vector3::new(1.0, 1.0, 1.0))],
+            &[plate(-1.1), plate(1.1)],
+        );
+        let solver = TransientWosSolver::new(
+            &scene,
+            WosSettings {
+                tolerance: 0.001,
+                max_steps: 256,
+                ..WosSettings::default()
+            },
+            TransientProblem {
+                diffusivity: 0.5,
+                initial: |_| 0.0,
+                boundary: |material, _, _| material.map_or(0.0, |m| m.albedo[0]),
+            },
+        );
+        let analytic = |t: f32| {
+            1.0 - 4.0 / std::f32::consts::PI
+                * (0..32)
+                    .map(|m| {
+                        let k = (2 * m + 1) as f32 * std::f32::consts::PI / 2.0;
+                        let sign = if m % 2 == 0 { 1.0 } else { -1.0 };
+                        sign / (2 * m + 1) as f32 * (-0.5 * k * k * t).exp()
+                    })
+                    .sum::<f32>()
+        };
+
+        let estimates = solver.solve(Point3::new(0.0, 0.0, 0.0), &[0.0, 0.2, 1.0], 10000);
+        assert_eq!(estimates[0].value, 0.0);
+        for estimate in estimates {
+            assert!((estimate.value - analytic(estimate.time)).abs() < 0.03);
+            assert_eq!(estimate.stats.unterminated, 0);
+        }
+    }
+}